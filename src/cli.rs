@@ -1,5 +1,6 @@
+use std::net::IpAddr;
 use std::path::PathBuf;
-use clap::{Args, Parser, Subcommand};
+use clap::{Args, Parser, Subcommand, ValueEnum};
 
 /// A toolkit for working with EPUB files.
 #[derive(Parser, Debug)]
@@ -7,16 +8,32 @@ use clap::{Args, Parser, Subcommand};
 pub struct Cli {
     #[command(subcommand)]
     pub command: Commands,
+
+    /// Print only errors, warnings and the output asked for (such as metadata).
+    #[arg(short, long, global = true, conflicts_with = "verbose")]
+    pub quiet: bool,
+
+    /// Print every step, such as each file 'webify' writes, instead of a progress bar.
+    #[arg(short, long, global = true)]
+    pub verbose: bool,
 }
 
 #[derive(Subcommand, Debug)]
 pub enum Commands {
     /// Unpacks an EPUB file into a specified directory.
     Unpack(UnpackArgs),
+    /// Packs an unpacked EPUB directory back into an EPUB file.
+    Pack(PackArgs),
     /// Extracts metadata from an EPUB file.
     Meta(MetaArgs),
     /// Converts an EPUB file into a static website.
     Webify(WebifyArgs),
+    /// Validates the structure of an EPUB file.
+    Check(CheckArgs),
+    /// Extracts the cover image of an EPUB file.
+    Cover(CoverArgs),
+    /// Converts an EPUB file into another document format.
+    Convert(ConvertArgs),
 }
 
 #[derive(Args, Debug)]
@@ -32,15 +49,103 @@ pub struct UnpackArgs {
 }
 
 #[derive(Args, Debug)]
+pub struct PackArgs {
+    /// The path to the unpacked EPUB directory.
+    #[arg(required = true)]
+    pub source: PathBuf,
+
+    /// The path of the EPUB file to create.
+    /// If omitted, a file named after the directory with an '.epub' extension will be created.
+    #[arg(short, long, value_name = "OUTPUT_FILE")]
+    pub output: Option<PathBuf>,
+
+    /// Obfuscates every font in the manifest with the IDPF algorithm,
+    /// listing them in 'META-INF/encryption.xml'.
+    #[arg(long)]
+    pub obfuscate_fonts: bool,
+}
+
+#[derive(Args, Debug)]
+#[command(args_conflicts_with_subcommands = true, subcommand_negates_reqs = true)]
 pub struct MetaArgs {
-    /// The path to the source EPUB file.
+    #[command(subcommand)]
+    pub action: Option<MetaAction>,
+
+    /// The path to the source EPUB file, or to an unpacked EPUB directory.
+    #[arg(required = true)]
+    pub source: Option<PathBuf>,
+
+    /// The output format.
+    #[arg(short, long, value_enum, default_value_t = MetaFormat::Text)]
+    pub format: MetaFormat,
+}
+
+#[derive(Subcommand, Debug)]
+pub enum MetaAction {
+    /// Edits the metadata of an EPUB file.
+    Set(Box<MetaSetArgs>),
+}
+
+#[derive(Args, Debug)]
+pub struct MetaSetArgs {
+    /// The path to the EPUB file to edit, or to an unpacked EPUB directory to edit in place.
     #[arg(required = true)]
     pub source: PathBuf,
+
+    /// Write the edited book to this file instead of modifying the source in place.
+    #[arg(short, long, value_name = "OUTPUT_FILE")]
+    pub output: Option<PathBuf>,
+
+    /// Sets the title.
+    #[arg(long)]
+    pub title: Option<String>,
+
+    /// Replaces the authors. Repeat the option for several authors.
+    #[arg(long = "author", value_name = "NAME")]
+    pub authors: Vec<String>,
+
+    /// Removes the author with this name.
+    #[arg(long = "remove-author", value_name = "NAME")]
+    pub remove_authors: Vec<String>,
+
+    /// Replaces the languages. Repeat the option for several languages.
+    #[arg(long = "language", value_name = "CODE")]
+    pub languages: Vec<String>,
+
+    /// Removes this language.
+    #[arg(long = "remove-language", value_name = "CODE")]
+    pub remove_languages: Vec<String>,
+
+    /// Sets the value of the book's unique identifier.
+    #[arg(long)]
+    pub identifier: Option<String>,
+
+    /// Removes an additional identifier with this value.
+    #[arg(long = "remove-identifier", value_name = "IDENTIFIER")]
+    pub remove_identifiers: Vec<String>,
+
+    /// Adds a subject.
+    #[arg(long = "add-subject", value_name = "SUBJECT")]
+    pub add_subjects: Vec<String>,
+
+    /// Removes a subject.
+    #[arg(long = "remove-subject", value_name = "SUBJECT")]
+    pub remove_subjects: Vec<String>,
+}
+
+/// Output formats supported by the `meta` command.
+#[derive(ValueEnum, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum MetaFormat {
+    /// A human-readable summary.
+    Text,
+    Json,
+    Yaml,
+    Toml,
 }
 
 #[derive(Args, Debug)]
 pub struct WebifyArgs {
-    /// The path to the source EPUB file.
+    /// The path to the source EPUB file, or to an unpacked EPUB directory.
     #[arg(required = true)]
     pub source: PathBuf,
 
@@ -53,7 +158,136 @@ pub struct WebifyArgs {
     #[arg(long)]
     pub no_nav: bool,
 
+    /// The URL the site will be published at. Social previews need absolute
+    /// URLs, so the cover is only declared as 'og:image' when this is given.
+    #[arg(long, value_name = "URL")]
+    pub base_url: Option<String>,
+
+    /// How the book's files are arranged in the generated site.
+    #[arg(long, value_enum, default_value_t = Layout::Flat)]
+    pub layout: Layout,
+
+    /// Combine all chapters into a single 'index.html', with links between
+    /// chapters turned into in-page anchors. Handy for printing and archiving.
+    #[arg(long)]
+    pub single_page: bool,
+
+    /// Like '--single-page', but also embeds the images, fonts and style sheets
+    /// in 'index.html', so the page can be shared as a single file.
+    #[arg(long)]
+    pub self_contained: bool,
+
+    /// The size of the largest file '--self-contained' embeds, in bytes or with a
+    /// K, M or G suffix. Larger files are written next to the page and reported.
+    #[arg(long, value_name = "SIZE", default_value = "10M", value_parser = parse_size, requires = "self_contained")]
+    pub inline_limit: u64,
+
+    /// A directory of templates to render the pages with: 'chapter.html' and
+    /// 'index.html' (MiniJinja syntax), plus files in 'static/', which are copied
+    /// to the site's 'assets/'. Templates it lacks fall back to the built-in ones.
+    #[arg(long, value_name = "DIR")]
+    pub template_dir: Option<PathBuf>,
+
+    /// Fail when a link of the book leads nowhere. The site is still written.
+    /// Missing fonts are not counted, as style sheets often list fallbacks a
+    /// book does not ship; '--report' lists them.
+    #[arg(long)]
+    pub strict: bool,
+
+    /// Write the build report, with every link that could not be resolved,
+    /// as JSON to FILE (default: 'webify-report.json').
+    #[arg(long, value_name = "FILE", num_args = 0..=1, default_missing_value = "webify-report.json")]
+    pub report: Option<PathBuf>,
+
     /// Serve the generated site on a local web server after building.
     #[arg(long)]
     pub serve: bool,
-}
\ No newline at end of file
+
+    /// Watch the source for changes, rebuild the site and live-reload open pages.
+    #[arg(long, requires = "serve")]
+    pub watch: bool,
+
+    /// The port the local web server listens on.
+    #[arg(long, default_value_t = 8000, requires = "serve")]
+    pub port: u16,
+
+    /// The address the local web server binds to.
+    #[arg(long, default_value = "127.0.0.1", requires = "serve")]
+    pub bind: IpAddr,
+}
+
+/// Output layouts supported by the `webify` command.
+#[derive(ValueEnum, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Layout {
+    /// All pages in 'chapters/' and all other files in 'assets/'; clashing names get a numeric suffix.
+    Flat,
+    /// Mirror the book's directory structure below 'chapters/' and 'assets/'.
+    Mirror,
+    /// Add a short hash of the source path to every file name.
+    Hashed,
+}
+
+#[derive(Args, Debug)]
+pub struct CheckArgs {
+    /// The path to the EPUB file or unpacked EPUB directory to validate.
+    #[arg(required = true)]
+    pub source: PathBuf,
+}
+#[derive(Args, Debug)]
+pub struct CoverArgs {
+    /// The path to the source EPUB file.
+    #[arg(required = true)]
+    pub source: PathBuf,
+
+    /// The path of the image file to create.
+    /// If omitted, a file named '[source]-cover' with the image's extension will be created.
+    #[arg(short, long, value_name = "OUTPUT_FILE")]
+    pub output: Option<PathBuf>,
+}
+
+#[derive(Args, Debug)]
+pub struct ConvertArgs {
+    /// The path to the source EPUB file.
+    #[arg(required = true)]
+    pub source: PathBuf,
+
+    /// The format to convert to.
+    #[arg(long = "to", value_enum, value_name = "FORMAT")]
+    pub format: ConvertFormat,
+
+    /// The file to create, or the directory with --split.
+    /// If omitted, '[source].md' (or the '[source]_md' directory, and likewise for txt) will be created.
+    #[arg(short, long, value_name = "OUTPUT")]
+    pub output: Option<PathBuf>,
+
+    /// Writes one file per spine item instead of a single file.
+    #[arg(long)]
+    pub split: bool,
+
+    /// Wraps plain text at this many columns (txt only).
+    #[arg(long, value_name = "COLUMNS", value_parser = clap::value_parser!(u16).range(1..))]
+    pub wrap: Option<u16>,
+}
+
+#[derive(ValueEnum, Clone, Copy, Debug)]
+pub enum ConvertFormat {
+    /// CommonMark with GitHub tables and footnotes.
+    #[value(alias = "md")]
+    Markdown,
+    /// Plain text, with a separator before each chapter.
+    #[value(alias = "text")]
+    Txt,
+}
+
+/// Parses a size such as '512', '300K' or '10M' (binary multiples) into bytes.
+fn parse_size(value: &str) -> Result<u64, String> {
+    let value = value.trim();
+    let (number, multiplier) = match value.char_indices().last() {
+        Some((i, 'k' | 'K')) => (&value[..i], 1 << 10),
+        Some((i, 'm' | 'M')) => (&value[..i], 1 << 20),
+        Some((i, 'g' | 'G')) => (&value[..i], 1 << 30),
+        _ => (value, 1),
+    };
+    let number: u64 = number.trim().parse().map_err(|_| format!("'{}' is not a size like 512, 300K or 10M", value))?;
+    number.checked_mul(multiplier).ok_or_else(|| format!("'{}' is too large", value))
+}
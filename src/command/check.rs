@@ -0,0 +1,27 @@
+use crate::cli::CheckArgs;
+use crate::output::status;
+use tome::error::{AppError, Result};
+use tome::epub::validator::{self, Severity};
+
+pub fn run(args: CheckArgs) -> Result<()> {
+    status!("🔎 Checking EPUB: {}", args.source.display());
+
+    let diagnostics = validator::validate_file(&args.source)?;
+    for diagnostic in &diagnostics {
+        println!("   {}", diagnostic);
+    }
+
+    let errors = diagnostics.iter().filter(|d| d.severity == Severity::Error).count();
+    let warnings = diagnostics.len() - errors;
+
+    if errors > 0 {
+        status!("\n❌ Found {} error(s) and {} warning(s).", errors, warnings);
+        return Err(AppError::ValidationFailed { errors });
+    }
+    if warnings > 0 {
+        status!("\n⚠️  No errors, {} warning(s).", warnings);
+    } else {
+        status!("✅ No problems found.");
+    }
+    Ok(())
+}
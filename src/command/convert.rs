@@ -0,0 +1,61 @@
+use std::path::{Path, PathBuf};
+
+use tome::epub::converter::{Converter, ExportFormat};
+use tome::error::{AppError, Result};
+use tome::Epub;
+
+use crate::cli::{ConvertArgs, ConvertFormat};
+use crate::output::status;
+
+pub fn run(args: ConvertArgs) -> Result<()> {
+    let format = match args.format {
+        ConvertFormat::Markdown => ExportFormat::Markdown,
+        ConvertFormat::Txt => ExportFormat::Text { width: args.wrap.map(usize::from) },
+    };
+    if args.wrap.is_some() && format == ExportFormat::Markdown {
+        eprintln!("⚠️  --wrap only applies to plain text, ignoring it.");
+    }
+
+    // 1. Determine the output path
+    let output = get_destination_path(&args.source, args.output, format, args.split)?;
+
+    status!(
+        "🚀 Converting '{}' -> '{}'",
+        args.source.display(),
+        output.display()
+    );
+
+    // 2. Open the EPUB and convert its spine
+    let mut epub = Epub::open(&args.source)?;
+    let report = Converter::new(format, &output, args.split).run(&mut epub)?;
+    for warning in &report.warnings {
+        eprintln!("⚠️  {}", warning);
+    }
+    status!("   ├── Parsed {} document(s) from the spine.", report.documents);
+    status!("   ├── Wrote {} file(s).", report.files_written);
+    status!("   └── Copied {} resource(s).", report.resources_copied);
+
+    status!("✅ EPUB converted successfully.");
+
+    Ok(())
+}
+
+/// Determines the output path: a file, or a directory when splitting.
+fn get_destination_path(source: &Path, output: Option<PathBuf>, format: ExportFormat, split: bool) -> Result<PathBuf> {
+    match output {
+        Some(path) => Ok(path),
+        None => {
+            // Default to "<stem>.<ext>", or the "<stem>_<ext>" directory
+            let stem = source.file_stem()
+                .ok_or_else(|| AppError::InvalidSourcePath {
+                    path: source.to_path_buf(),
+                })?
+                .to_string_lossy();
+            if split {
+                Ok(PathBuf::from(format!("{}_{}", stem, format.extension())))
+            } else {
+                Ok(PathBuf::from(format!("{}.{}", stem, format.extension())))
+            }
+        }
+    }
+}
@@ -0,0 +1,83 @@
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use crate::cli::CoverArgs;
+use crate::output::status;
+use tome::error::{AppError, Result};
+use tome::epub::{model::ManifestItem, Epub};
+
+pub fn run(args: CoverArgs) -> Result<()> {
+    // 1. Find the cover
+    let mut epub = Epub::open(&args.source)?;
+    let cover = epub.cover().cloned().ok_or_else(|| AppError::CoverNotFound {
+        path: args.source.clone(),
+    })?;
+
+    // 2. Determine the output file path
+    let dest_path = get_destination_path(&args.source, args.output, &cover)?;
+
+    status!(
+        "🖼️  Extracting cover '{}' -> '{}'",
+        cover.href,
+        dest_path.display()
+    );
+    if !extension_matches(&dest_path, &cover.media_type) {
+        // The image is copied as is, so e.g. an SVG cover saved as 'cover.png' is still SVG.
+        eprintln!(
+            "⚠️  The cover is {}, which does not match the extension of '{}'; it is saved without conversion.",
+            cover.media_type,
+            dest_path.display()
+        );
+    }
+
+    // 3. Copy the image out of the archive
+    let mut image = epub.read_by_manifest_item(&cover)?;
+    io::copy(&mut image, &mut fs::File::create(&dest_path)?)?;
+
+    status!("✅ Cover extracted successfully.");
+
+    Ok(())
+}
+
+/// Determines the output file path.
+fn get_destination_path(source: &Path, output: Option<PathBuf>, cover: &ManifestItem) -> Result<PathBuf> {
+    match output {
+        Some(path) => Ok(path),
+        None => {
+            // Default to "<stem>-cover.<ext>", keeping the image's own extension
+            let stem = source.file_stem()
+                .ok_or_else(|| AppError::InvalidSourcePath {
+                    path: source.to_path_buf(),
+                })?;
+            let extension = Path::new(&cover.href)
+                .extension()
+                .map(|ext| ext.to_string_lossy().to_lowercase())
+                .unwrap_or_else(|| extension_for(&cover.media_type).to_string());
+            Ok(PathBuf::from(format!("{}-cover.{}", stem.to_string_lossy(), extension)))
+        }
+    }
+}
+
+/// Whether `path` has the usual extension for `media_type`. Unknown media types always match.
+fn extension_matches(path: &Path, media_type: &str) -> bool {
+    let expected = extension_for(media_type);
+    let extension = path.extension().map(|ext| ext.to_string_lossy().to_lowercase());
+    match extension.as_deref() {
+        _ if expected == "img" => true,
+        Some("jpeg" | "jpe") => expected == "jpg",
+        Some(extension) => extension == expected,
+        None => false,
+    }
+}
+
+fn extension_for(media_type: &str) -> &'static str {
+    match media_type {
+        "image/jpeg" => "jpg",
+        "image/png" => "png",
+        "image/gif" => "gif",
+        "image/svg+xml" => "svg",
+        "image/webp" => "webp",
+        _ => "img",
+    }
+}
@@ -1,43 +1,262 @@
-use crate::cli::MetaArgs;
-use crate::error::Result;
-use crate::epub::{model::Metadata, Epub};
+use std::collections::BTreeMap;
+
+use serde::Serialize;
+
+use crate::cli::{MetaAction, MetaArgs, MetaFormat, MetaSetArgs};
+use crate::output::status;
+use tome::error::{AppError, Result};
+use tome::epub::editor::{self, MetadataEdits};
+use tome::epub::{model::{Creator, DcElement, Identifier, ItemRef, Metadata}, Epub};
+
+/// The document emitted by the machine-readable formats.
+#[derive(Serialize)]
+struct MetadataReport<'a> {
+    title: Option<&'a str>,
+    authors: Vec<&'a str>,
+    language: &'a [String],
+    identifiers: &'a [Identifier],
+    manifest: ManifestStats<'a>,
+    spine: SpineStats<'a>,
+    metadata: &'a Metadata,
+}
+
+#[derive(Serialize)]
+struct ManifestStats<'a> {
+    items: usize,
+    media_types: BTreeMap<&'a str, usize>,
+}
+
+#[derive(Serialize)]
+struct SpineStats<'a> {
+    length: usize,
+    /// Items in the default reading order, i.e. not marked `linear="no"`.
+    linear: usize,
+    direction: Option<&'a str>,
+    items: &'a [ItemRef],
+}
 
 pub fn run(args: MetaArgs) -> Result<()> {
-    println!("🔍 Analyzing metadata for: {}", args.source.display());
+    if let Some(MetaAction::Set(set_args)) = args.action {
+        return set(*set_args);
+    }
+    // clap guarantees a source whenever there is no subcommand.
+    let source = args.source.expect("source is required without a subcommand");
+
+    if args.format == MetaFormat::Text {
+        status!("🔍 Analyzing metadata for: {}", source.display());
+    }
 
     // The complex logic is now hidden behind `Epub::open`.
-    let epub = Epub::open(&args.source)?;
+    let epub = Epub::open(&source)?;
 
-    print_metadata(epub.metadata());
+    match args.format {
+        MetaFormat::Text => print_metadata(epub.metadata()),
+        format => println!("{}", serialize_report(&build_report(&epub), format)?),
+    }
 
     Ok(())
 }
 
+fn set(args: MetaSetArgs) -> Result<()> {
+    let edits = MetadataEdits {
+        title: args.title,
+        authors: args.authors,
+        remove_authors: args.remove_authors,
+        languages: args.languages,
+        remove_languages: args.remove_languages,
+        identifier: args.identifier,
+        remove_identifiers: args.remove_identifiers,
+        add_subjects: args.add_subjects,
+        remove_subjects: args.remove_subjects,
+    };
+    if edits.is_empty() {
+        return Err(AppError::InvalidMetadataEdit("no changes were requested".to_string()));
+    }
+
+    let dest = args.output.unwrap_or_else(|| args.source.clone());
+    status!("✏️  Updating metadata: '{}' -> '{}'", args.source.display(), dest.display());
+
+    editor::edit_metadata(&args.source, &dest, &edits)?;
+
+    status!("✅ Metadata updated successfully.");
+    Ok(())
+}
+
+fn build_report(epub: &Epub) -> MetadataReport<'_> {
+    let metadata = epub.metadata();
+    let spine = epub.spine();
+    let mut media_types = BTreeMap::new();
+    for item in &epub.manifest().items {
+        *media_types.entry(item.media_type.as_str()).or_insert(0) += 1;
+    }
+
+    MetadataReport {
+        title: metadata.title(),
+        authors: metadata.creator.iter().map(|c| c.name.as_str()).collect(),
+        language: &metadata.language,
+        identifiers: &metadata.identifier,
+        manifest: ManifestStats { items: epub.manifest().items.len(), media_types },
+        spine: SpineStats {
+            length: spine.item_refs.len(),
+            linear: spine.item_refs.iter().filter(|item_ref| item_ref.is_linear()).count(),
+            direction: spine.page_progression_direction.as_deref(),
+            items: &spine.item_refs,
+        },
+        metadata,
+    }
+}
+
+fn serialize_report(report: &MetadataReport, format: MetaFormat) -> Result<String> {
+    let to_error = |format: &'static str| move |e: &dyn std::fmt::Display| AppError::Serialization { format, message: e.to_string() };
+    match format {
+        MetaFormat::Json => serde_json::to_string_pretty(report).map_err(|e| to_error("JSON")(&e)),
+        MetaFormat::Yaml => serde_yaml::to_string(report).map_err(|e| to_error("YAML")(&e)),
+        MetaFormat::Toml => toml::to_string_pretty(report).map_err(|e| to_error("TOML")(&e)),
+        MetaFormat::Text => unreachable!("text output is printed directly"),
+    }
+}
+
 /// Prints metadata in a user-friendly format.
 fn print_metadata(metadata: &Metadata) {
     println!("\n--- EPUB Metadata ---");
 
-    if let Some(title) = metadata.title.first() {
+    if let Some(title) = metadata.title() {
         println!("Title:    {}", title);
     }
+    // Additional titles (subtitles, collection titles, ...) in their display order.
+    let mut other_titles: Vec<&DcElement> = metadata.title.iter().filter(|t| Some(t.value.as_str()) != metadata.title()).collect();
+    other_titles.sort_by_key(|t| {
+        metadata.refinement(t.id.as_deref(), "display-seq").and_then(|seq| seq.parse::<u32>().ok()).unwrap_or(u32::MAX)
+    });
+    for title in other_titles {
+        match metadata.refinement(title.id.as_deref(), "title-type") {
+            Some(title_type) => println!("          {} ({})", title.value, title_type),
+            None => println!("          {}", title.value),
+        }
+    }
 
-    let creators = metadata
-        .creator
-        .iter()
-        .map(|c| c.name.as_str())
-        .collect::<Vec<&str>>()
-        .join(", ");
+    let creators = describe_creators(metadata, &metadata.creator);
     if !creators.is_empty() {
         println!("Author(s): {}", creators);
     }
+    let contributors = describe_creators(metadata, &metadata.contributor);
+    if !contributors.is_empty() {
+        println!("Contributor(s): {}", contributors);
+    }
 
     if let Some(lang) = metadata.language.first() {
         println!("Language: {}", lang);
     }
 
-    if let Some(id) = metadata.identifier.first() {
-        println!("Identifier: {}", id.value);
+    for id in &metadata.identifier {
+        let scheme = id.scheme.as_deref().or_else(|| metadata.refinement(id.id.as_deref(), "identifier-type"));
+        match scheme {
+            Some(scheme) => println!("Identifier: {} ({})", id.value, scheme),
+            None => println!("Identifier: {}", id.value),
+        }
+    }
+
+    print_elements("Publisher", &metadata.publisher);
+    for date in &metadata.date {
+        match &date.event {
+            Some(event) => println!("Date:     {} ({})", date.value, event),
+            None => println!("Date:     {}", date.value),
+        }
+    }
+    if let Some(modified) = metadata.modified() {
+        println!("Modified: {}", modified);
+    }
+
+    let subjects = metadata.subject.iter().map(|s| s.value.as_str()).collect::<Vec<&str>>().join(", ");
+    if !subjects.is_empty() {
+        println!("Subjects: {}", subjects);
+    }
+    print_elements("Description", &metadata.description);
+    print_elements("Rights", &metadata.rights);
+    print_elements("Source", &metadata.source);
+    print_elements("Type", &metadata.r#type);
+    print_elements("Coverage", &metadata.coverage);
+    print_elements("Relation", &metadata.relation);
+
+    for collection in metadata.collections() {
+        let id = collection.id.as_deref();
+        let mut details = Vec::new();
+        if let Some(kind) = metadata.refinement(id, "collection-type") {
+            details.push(kind.to_string());
+        }
+        if let Some(position) = metadata.refinement(id, "group-position") {
+            details.push(format!("#{}", position));
+        }
+        if details.is_empty() {
+            println!("Collection: {}", collection.value);
+        } else {
+            println!("Collection: {} ({})", collection.value, details.join(", "));
+        }
+    }
+
+    // Everything else that isn't a refinement or already shown above.
+    let shown = ["dcterms:modified", "belongs-to-collection"];
+    let others: Vec<String> = metadata.meta.iter()
+        .filter(|m| m.refines.is_none())
+        .filter_map(|m| match (&m.property, &m.name) {
+            (Some(property), _) if !shown.contains(&property.as_str()) => match &m.scheme {
+                Some(scheme) => Some(format!("{} = {} ({})", property, m.value, scheme)),
+                None => Some(format!("{} = {}", property, m.value)),
+            },
+            (None, Some(name)) => Some(format!("{} = {}", name, m.content.as_deref().unwrap_or_default())),
+            _ => None,
+        })
+        .collect();
+    if !others.is_empty() {
+        println!("Other:");
+        for other in others {
+            println!("          {}", other);
+        }
     }
 
     println!("---------------------\n");
-}
\ No newline at end of file
+}
+
+fn print_elements(label: &str, elements: &[DcElement]) {
+    for element in elements {
+        println!("{}: {}", label, element.value.trim());
+    }
+}
+
+/// Formats creators as "Name (role, sort: File-as)", joined by semicolons
+/// because names are often written "Last, First".
+fn describe_creators(metadata: &Metadata, creators: &[Creator]) -> String {
+    creators
+        .iter()
+        .map(|c| {
+            let mut details = Vec::new();
+            if let Some(role) = metadata.role_of(c) {
+                details.push(describe_role(role).to_string());
+            }
+            if let Some(file_as) = metadata.file_as_of(c) {
+                details.push(format!("sort: {}", file_as));
+            }
+            if details.is_empty() {
+                c.name.clone()
+            } else {
+                format!("{} ({})", c.name, details.join(", "))
+            }
+        })
+        .collect::<Vec<String>>()
+        .join("; ")
+}
+
+/// Spells out the most common MARC relator codes.
+fn describe_role(code: &str) -> &str {
+    match code {
+        "aut" => "author",
+        "edt" => "editor",
+        "ill" => "illustrator",
+        "trl" => "translator",
+        "nrt" => "narrator",
+        "aui" => "introduction",
+        "bkp" => "producer",
+        "pbl" => "publisher",
+        _ => code,
+    }
+}
@@ -1,3 +1,7 @@
 pub mod unpack;
+pub mod pack;
 pub mod meta;
-pub mod webify;
\ No newline at end of file
+pub mod webify;
+pub mod check;
+pub mod cover;
+pub mod convert;
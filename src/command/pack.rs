@@ -0,0 +1,46 @@
+use std::path::{Path, PathBuf};
+
+use crate::cli::PackArgs;
+use crate::output::status;
+use tome::error::{AppError, Result};
+use tome::epub::packer;
+
+pub fn run(args: PackArgs) -> Result<()> {
+    // 1. Determine the output file path
+    let dest_path = get_destination_path(&args.source, args.output)?;
+
+    status!(
+        "📦 Packing '{}' -> '{}'",
+        args.source.display(),
+        dest_path.display()
+    );
+
+    // 2. Build the archive
+    let obfuscated = packer::pack_directory(&args.source, &dest_path, args.obfuscate_fonts)?;
+    if obfuscated > 0 {
+        status!("🔒 Obfuscated {} font(s).", obfuscated);
+    }
+
+    status!("✅ EPUB packed successfully.");
+
+    Ok(())
+}
+
+/// Determines the output file path.
+fn get_destination_path(source: &Path, output: Option<PathBuf>) -> Result<PathBuf> {
+    match output {
+        Some(path) => Ok(path),
+        None => {
+            // Default to "<directory name>.epub" in the current location
+            let name = source.canonicalize()?
+                .file_name()
+                .ok_or_else(|| AppError::InvalidSourcePath {
+                    path: source.to_path_buf(),
+                })?
+                .to_os_string();
+            let mut file_name = name;
+            file_name.push(".epub");
+            Ok(PathBuf::from(file_name))
+        }
+    }
+}
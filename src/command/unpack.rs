@@ -2,8 +2,9 @@ use std::fs;
 use std::path::{Path, PathBuf};
 
 use crate::cli::UnpackArgs;
-use crate::error::{AppError, Result};
-use crate::epub::Epub;
+use crate::output::status;
+use tome::error::{AppError, Result};
+use tome::epub::Epub;
 
 pub fn run(args: UnpackArgs) -> Result<()> {
     // 1. Determine the destination path
@@ -14,7 +15,7 @@ pub fn run(args: UnpackArgs) -> Result<()> {
             source,
         })?;
 
-    println!(
+    status!(
         "🚀 Unpacking '{}' -> '{}'",
         args.source.display(),
         dest_path.display()
@@ -24,7 +25,7 @@ pub fn run(args: UnpackArgs) -> Result<()> {
     let mut epub = Epub::open(&args.source)?;
     epub.unpack_to(&dest_path)?;
     
-    println!("✅ Archive extracted successfully.");
+    status!("✅ Archive extracted successfully.");
 
     Ok(())
 }
@@ -1,39 +1,212 @@
-use std::path::PathBuf;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::mpsc;
+use std::thread;
+use std::time::Duration;
 
-use crate::cli::WebifyArgs;
-use crate::error::{AppError, Result};
-use crate::epub::{Epub, site_generator::SiteGenerator};
+use notify::{EventKind, RecursiveMode, Watcher};
 
-pub fn run(args: WebifyArgs) -> Result<()> {
-    println!("🚀 Webifying EPUB: {}", args.source.display());
+use path_slash::PathBufExt;
+use anyhow::{anyhow, Result};
+use tome::error::AppError;
+use tome::{Epub, PageMode, SiteGenerator, SiteLayout, WebifyOptions, WebifyReport};
+
+use crate::cli::{Layout, WebifyArgs};
+use crate::output::{status, verbosity, TerminalProgress, Verbosity};
+use crate::server::{LiveReload, StaticServer};
+
+/// How long to wait for a burst of file system events to settle before rebuilding.
+const WATCH_DEBOUNCE: Duration = Duration::from_millis(300);
 
-    // 1. Open the EPUB file
-    let mut epub = Epub::open(&args.source)?;
+pub fn run(args: WebifyArgs) -> Result<()> {
+    status!("🚀 Webifying EPUB: {}", args.source.display());
 
-    // 2. Determine output directory
+    // 1. Determine output directory
     let output_dir = get_destination_path(&args.source, args.destination)?;
-    println!("   └── Output directory: {}", output_dir.display());
+    status!("   └── Output directory: {}", output_dir.display());
+
+    // 2. Open the EPUB file and run the site generator
+    let layout = match args.layout {
+        Layout::Flat => SiteLayout::Flat,
+        Layout::Mirror => SiteLayout::Mirror,
+        Layout::Hashed => SiteLayout::Hashed,
+    };
+    let mode = if args.self_contained {
+        PageMode::SelfContained { inline_limit: args.inline_limit }
+    } else if args.single_page {
+        PageMode::Single
+    } else {
+        PageMode::Chapters
+    };
+    let mut options = WebifyOptions::new().no_nav(args.no_nav).layout(layout).mode(mode);
+    if let Some(template_dir) = args.template_dir {
+        options = options.template_dir(template_dir);
+    }
+    if let Some(base_url) = args.base_url {
+        options = options.base_url(base_url);
+    }
+    let report = build_site(&args.source, &output_dir, &options)?;
+    print_report(&report, mode);
+    if let Some(report_path) = &args.report {
+        write_report(&report, report_path)?;
+        status!("   └── Wrote build report to {}", report_path.display());
+    }
+    if args.strict && report.diagnostics.has_broken_links() {
+        return Err(AppError::BrokenLinks { count: report.diagnostics.broken_links().count() }.into());
+    }
+
+    status!("✅ EPUB successfully converted to a static website.");
+    status!("   Find your site in: {}", output_dir.display());
+
+    // 3. Optionally serve the result
+    if args.serve {
+        let mut server = StaticServer::bind(&output_dir, args.bind, args.port)?;
+        println!("🌐 Serving site at http://{}/ (press Ctrl+C to stop)", server.local_addr()?);
+
+        if args.watch {
+            let live_reload = server.enable_live_reload();
+            thread::spawn(move || {
+                if let Err(e) = server.run() {
+                    eprintln!("❌ Error: {}", e);
+                }
+            });
+            watch_and_rebuild(&args.source, &output_dir, &options, &live_reload)?;
+        } else {
+            server.run()?;
+        }
+    }
+    Ok(())
+}
+
+/// Opens the EPUB and (re)generates the whole site into `output_dir`.
+/// Progress and warnings are shown as the build goes.
+fn build_site(source: &Path, output_dir: &Path, options: &WebifyOptions) -> tome::Result<WebifyReport> {
+    let mut epub = Epub::open(source)?;
+    SiteGenerator::new(&mut epub, output_dir, options)?
+        .with_progress(TerminalProgress::default())
+        .run()
+}
+
+/// Counts the links that could not be resolved, which were warned about as
+/// the build went (`--verbose` lists them again), and lists the files a
+/// self-contained build had to leave next to the page.
+fn print_report(report: &WebifyReport, mode: PageMode) {
+    if !report.diagnostics.is_empty() {
+        let broken = report.diagnostics.broken_links().count();
+        status!(
+            "   │   ⚠️  {} broken link(s), {} other link problem(s).",
+            broken,
+            report.diagnostics.links.len() - broken
+        );
+        if verbosity() == Verbosity::Verbose {
+            for diagnostic in &report.diagnostics.links {
+                status!("   │       {} [{}]", diagnostic, diagnostic.attribute);
+            }
+        }
+    }
+    if let PageMode::SelfContained { inline_limit } = mode
+        && !report.oversized_assets.is_empty() {
+        println!(
+            "   │   ⚠️  {} file(s) are over the {} limit and were written next to the page instead of being embedded:",
+            report.oversized_assets.len(),
+            format_size(inline_limit)
+        );
+        for asset in &report.oversized_assets {
+            println!("   │       {} ({})", asset.site_path.to_slash_lossy(), format_size(asset.size));
+        }
+    }
+}
 
-    // 3. Initialize and run the site generator
-    // Pass the --no-nav flag to the generator.
-    let mut generator = SiteGenerator::new(&mut epub, &output_dir, args.no_nav);
-    generator.run()?;
+/// Writes `report` as pretty-printed JSON.
+fn write_report(report: &WebifyReport, path: &Path) -> Result<()> {
+    let json = serde_json::to_string_pretty(report)
+        .map_err(|e| AppError::Serialization { format: "JSON", message: e.to_string() })?;
+    fs::write(path, json + "\n")?;
+    Ok(())
+}
 
-    println!("✅ EPUB successfully converted to a static website.");
-    println!("   Find your site in: {}", output_dir.display());
+/// Formats a number of bytes for people, e.g. `1.5 MB` (binary multiples).
+fn format_size(bytes: u64) -> String {
+    const UNITS: &[&str] = &["KB", "MB", "GB"];
+    if bytes < 1024 {
+        return format!("{} bytes", bytes);
+    }
+    let mut size = bytes as f64 / 1024.0;
+    let mut unit = 0;
+    while size >= 1024.0 && unit + 1 < UNITS.len() {
+        size /= 1024.0;
+        unit += 1;
+    }
+    format!("{:.1} {}", size, UNITS[unit])
+}
+
+/// Watches `source`, and the template directory if there is one, and
+/// regenerates the site whenever they change, telling connected browsers to
+/// reload after each successful build.
+fn watch_and_rebuild(source: &Path, output_dir: &Path, options: &WebifyOptions, live_reload: &LiveReload) -> Result<()> {
+    let (sender, events) = mpsc::channel();
+    let mut watcher = notify::recommended_watcher(sender).map_err(watch_error)?;
+
+    // Editors often replace a file instead of writing it in place, so for a
+    // single file we watch its directory and filter events by path.
+    let source = source.canonicalize()?;
+    let output_dir = output_dir.canonicalize()?;
+    if source.is_dir() {
+        watcher.watch(&source, RecursiveMode::Recursive).map_err(watch_error)?;
+    } else {
+        let parent = source.parent().unwrap_or_else(|| Path::new("."));
+        watcher.watch(parent, RecursiveMode::NonRecursive).map_err(watch_error)?;
+    }
+    let template_dir = options.template_dir_path().map(Path::canonicalize).transpose()?;
+    if let Some(template_dir) = &template_dir {
+        watcher.watch(template_dir, RecursiveMode::Recursive).map_err(watch_error)?;
+        status!("👀 Watching {} and {} for changes...", source.display(), template_dir.display());
+    } else {
+        status!("👀 Watching {} for changes...", source.display());
+    }
+
+    let is_relevant = |event: &notify::Event| {
+        matches!(event.kind, EventKind::Create(_) | EventKind::Modify(_) | EventKind::Remove(_))
+            && event.paths.iter().any(|path| {
+                let path = path.canonicalize().unwrap_or_else(|_| path.clone());
+                let is_input = path.starts_with(&source) || template_dir.as_ref().is_some_and(|dir| path.starts_with(dir));
+                is_input && !path.starts_with(&output_dir)
+            })
+    };
+
+    while let Ok(event) = events.recv() {
+        if !matches!(event, Ok(ref event) if is_relevant(event)) {
+            continue;
+        }
+        // Swallow the rest of the burst (e.g. truncate + write + rename).
+        while events.recv_timeout(WATCH_DEBOUNCE).is_ok() {}
+
+        status!("🔄 Change detected, rebuilding...");
+        match build_site(&source, &output_dir, options) {
+            Ok(_) => {
+                status!("✅ Rebuilt site, reloading browsers.");
+                live_reload.notify_reload();
+            }
+            Err(e) => eprintln!("❌ Rebuild failed: {}", e),
+        }
+    }
     Ok(())
 }
 
+fn watch_error(source: notify::Error) -> anyhow::Error {
+    anyhow!("File watcher error: {}", source)
+}
+
 /// Determines the output directory path.
-fn get_destination_path(source: &PathBuf, destination: Option<PathBuf>) -> Result<PathBuf> {
+fn get_destination_path(source: &Path, destination: Option<PathBuf>) -> Result<PathBuf> {
     match destination {
         Some(path) => Ok(path),
         None => {
             let stem = source.file_stem()
-                .ok_or_else(|| AppError::InvalidSourcePath { path: source.clone() })?;
+                .ok_or_else(|| AppError::InvalidSourcePath { path: source.to_path_buf() })?;
             let mut new_path = PathBuf::from(stem);
             new_path.set_extension("site"); // e.g., "mybook.epub" -> "mybook.site"
             Ok(new_path)
         }
     }
-}
\ No newline at end of file
+}
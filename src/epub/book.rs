@@ -0,0 +1,364 @@
+// file: epub/book.rs
+
+use std::collections::HashMap;
+use std::fmt;
+use std::fs;
+use std::io::{Read, Seek}; // `copy` is not used here, so remove it.
+use std::path::{Path, PathBuf};
+use zip::ZipArchive;
+use path_slash::PathBufExt; // Import the extension trait
+use path_clean::PathClean;
+
+use crate::error::{AppError, Result};
+use crate::epub::model::{Container, Encryption, Manifest, ManifestItem, Metadata, Package, Spine, TocEntry};
+use crate::epub::obfuscation::{self, ObfuscationKey, ObfuscationReader};
+use crate::epub::storage::{EntryReader, Storage};
+use crate::epub::{toc, xhtml};
+
+/// The name errors use for a book opened with `Epub::from_reader`.
+const READER_NAME: &str = "<reader>";
+const CONTAINER_PATH: &str = "META-INF/container.xml";
+
+/// Represents an opened EPUB file, or an unpacked copy of one.
+pub struct Epub {
+    /// The path to the original EPUB file or directory, or `READER_NAME` if it was opened from a reader.
+    path: PathBuf,
+    /// The book's files.
+    storage: Storage,
+    /// The parsed package data from the .opf file.
+    package: Package,
+    /// The path to the OPF file inside the archive, crucial for resolving relative paths.
+    opf_path: PathBuf,
+    /// The table of contents from the navigation document or NCX (may be empty).
+    toc: Vec<TocEntry>,
+    /// The manifest item of the cover image, if one could be found.
+    cover: Option<ManifestItem>,
+    /// Obfuscated fonts listed in `META-INF/encryption.xml`, by archive path.
+    obfuscated: HashMap<String, ObfuscationKey>,
+}
+
+impl fmt::Debug for Epub {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Epub")
+            .field("path", &self.path)
+            .field("opf_path", &self.opf_path)
+            .finish_non_exhaustive()
+    }
+}
+
+impl Epub {
+    /// Opens an EPUB file, or a directory holding an unpacked one as written
+    /// by `unpack_to` (with fonts in the clear).
+    pub fn open(path: &Path) -> Result<Self> {
+        Self::load(Storage::open(path)?, path.to_path_buf())
+    }
+
+    /// Opens an EPUB held by any seekable reader, such as an uploaded file
+    /// kept in memory. Wrap unbuffered sources in a `BufReader`.
+    ///
+    /// ```
+    /// use std::io::Cursor;
+    ///
+    /// use tome::Epub;
+    ///
+    /// fn open_upload(body: Vec<u8>) -> tome::Result<Epub> {
+    ///     Epub::from_reader(Cursor::new(body))
+    /// }
+    /// ```
+    pub fn from_reader<R: Read + Seek + Send + 'static>(reader: R) -> Result<Self> {
+        let path = PathBuf::from(READER_NAME);
+        Self::load(Storage::from_reader(Box::new(reader), &path)?, path)
+    }
+
+    fn load(mut storage: Storage, path: PathBuf) -> Result<Self> {
+        let opf_path_str = Self::parse_container(&storage.read_to_string(CONTAINER_PATH)?)?;
+        let package = Self::parse_opf(&mut storage, &opf_path_str)?;
+        // Unpacked fonts are already in the clear, even though `encryption.xml` still lists them.
+        let obfuscated = match Self::parse_encryption(&mut storage)? {
+            Some(encryption) if !storage.is_directory() => obfuscation::obfuscated_entries(&encryption, &package),
+            _ => HashMap::new(),
+        };
+        
+        let opf_path = PathBuf::from(opf_path_str);
+
+        let mut epub = Self {
+            path,
+            storage,
+            package,
+            opf_path,
+            toc: Vec::new(),
+            cover: None,
+            obfuscated,
+        };
+        epub.toc = epub.load_toc();
+        epub.cover = epub.find_cover();
+        Ok(epub)
+    }
+
+    pub fn metadata(&self) -> &Metadata { &self.package.metadata }
+    pub fn manifest(&self) -> &Manifest { &self.package.manifest }
+    pub fn spine(&self) -> &Spine { &self.package.spine }
+    pub fn toc(&self) -> &[TocEntry] { &self.toc }
+    pub fn cover(&self) -> Option<&ManifestItem> { self.cover.as_ref() }
+    /// The path of the package document inside the book, e.g. `OEBPS/content.opf`.
+    pub fn opf_path(&self) -> &Path { &self.opf_path }
+    /// Whether the book was opened from an unpacked directory rather than an EPUB file.
+    pub fn is_unpacked(&self) -> bool { self.storage.is_directory() }
+
+    /// Extracts the archive into `dest_path`. Obfuscated fonts are written in
+    /// the clear; `encryption.xml` is kept so that packing the directory again
+    /// restores the obfuscation.
+    pub fn unpack_to(&mut self, dest_path: &Path) -> Result<()> {
+        self.storage.extract(dest_path, &self.path)?;
+
+        for (archive_path, key) in &self.obfuscated {
+            // Only touch files that were actually extracted, at the path the extraction used.
+            let Some(relative_path) = self.storage.extracted_path(archive_path) else {
+                continue;
+            };
+            let file_path = dest_path.join(relative_path);
+            let mut content = Vec::new();
+            ObfuscationReader::new(fs::File::open(&file_path)?, Some(key.clone())).read_to_end(&mut content)?;
+            fs::write(&file_path, content)?;
+        }
+        Ok(())
+    }
+
+    /// Reads a file from the archive by its manifest item.
+    /// The href in the manifest item is relative to the OPF file,
+    /// so we need to resolve it. Obfuscated fonts are de-obfuscated as they are read.
+    // Note the lifetime annotations. We are returning a reader that borrows from `self.storage`.
+    pub fn read_by_manifest_item<'a>(&'a mut self, item: &ManifestItem) -> Result<ObfuscationReader<EntryReader<'a>>> {
+        let opf_dir = self.opf_path.parent().unwrap_or_else(|| Path::new(""));
+
+        // Manifest hrefs are URLs, so "My%20Cover.jpg" names "My Cover.jpg" in the archive.
+        let href = urlencoding::decode(&item.href).map(|h| h.into_owned()).unwrap_or_else(|_| item.href.clone());
+        let file_path = opf_dir.join(href);
+        
+        // Use the `to_slash_lossy()` method from `path_slash` and convert to a String.
+        // `to_slash_lossy` returns a `Cow<str>`, `.into_owned()` gives us a `String`.
+        let normalized_path = file_path.to_slash_lossy().into_owned();
+
+        let key = self.obfuscated.get(&normalized_path).cloned();
+        self.storage.entry(&normalized_path)
+            .map(|file| ObfuscationReader::new(file, key))
+    }
+
+    /// Builds the table of contents, preferring the EPUB 3 navigation document
+    /// and falling back to the NCX referenced by the spine.
+    /// A missing or malformed TOC is not fatal: it simply yields an empty list.
+    fn load_toc(&mut self) -> Vec<TocEntry> {
+        let manifest = &self.package.manifest.items;
+        let nav_item = manifest.iter().find(|item| item.has_property("nav")).cloned();
+        let ncx_item = self.package.spine.toc.as_ref()
+            .and_then(|id| manifest.iter().find(|item| &item.id == id))
+            .or_else(|| manifest.iter().find(|item| item.media_type == "application/x-dtbncx+xml"))
+            .cloned();
+
+        if let Some(item) = nav_item
+            && let Some(entries) = self.read_manifest_item_to_string(&item)
+                .and_then(|content| toc::parse_nav(&content, &item.href).ok())
+                .filter(|entries| !entries.is_empty()) {
+            return entries;
+        }
+
+        ncx_item
+            .and_then(|item| {
+                self.read_manifest_item_to_string(&item)
+                    .and_then(|content| toc::parse_ncx(&content, &item.href).ok())
+            })
+            .unwrap_or_default()
+    }
+
+    /// Finds the cover image, trying in order: the EPUB 3 `cover-image`
+    /// property, the EPUB 2 `<meta name="cover">`, the guide's `cover`
+    /// reference and finally any image whose id or file name has "cover" as a word.
+    fn find_cover(&mut self) -> Option<ManifestItem> {
+        let manifest = &self.package.manifest;
+        if let Some(item) = manifest.items.iter().find(|item| item.has_property("cover-image")) {
+            return Some(item.clone());
+        }
+
+        // The meta should name a manifest id, but some books put the href there.
+        if let Some(content) = self.package.metadata.named_meta("cover")
+            && let Some(item) = manifest.item(content).or_else(|| self.manifest_item_by_href(content))
+            && item.is_image() {
+            return Some(item.clone());
+        }
+
+        let guide_href = self.package.guide.references.iter()
+            .find(|reference| reference.r#type.eq_ignore_ascii_case("cover"))
+            .map(|reference| reference.href.clone());
+        if let Some(href) = guide_href
+            && let Some(item) = self.cover_from_page(&href) {
+            return Some(item);
+        }
+
+        let manifest = &self.package.manifest;
+        manifest.items.iter()
+            .filter(|item| item.is_image())
+            .find(|item| {
+                let file_name = Path::new(&item.href).file_name().map(|n| n.to_string_lossy());
+                mentions_cover(&item.id) || file_name.is_some_and(|n| mentions_cover(&n))
+            })
+            .cloned()
+    }
+
+    /// Resolves a guide reference, which may point at the image itself or at an XHTML cover page.
+    fn cover_from_page(&mut self, href: &str) -> Option<ManifestItem> {
+        let (path, _) = toc::resolve_href("", href);
+        let item = self.manifest_item_by_href(&path?)?.clone();
+        if item.is_image() {
+            return Some(item);
+        }
+        let content = self.read_manifest_item_to_string(&item)?;
+        let (image_path, _) = toc::resolve_href(&item.href, &xhtml::first_image_src(&content)?);
+        self.manifest_item_by_href(&image_path?).filter(|item| item.is_image()).cloned()
+    }
+
+    /// Finds a manifest item by an OPF-relative path, ignoring percent-encoding.
+    fn manifest_item_by_href(&self, href: &str) -> Option<&ManifestItem> {
+        let decode = |s: &str| urlencoding::decode(s).map(|d| d.into_owned()).unwrap_or_else(|_| s.to_string());
+        let wanted = decode(href);
+        self.package.manifest.items.iter()
+            .find(|item| Path::new(&decode(&item.href)).clean() == Path::new(&wanted).clean())
+    }
+
+    fn read_manifest_item_to_string(&mut self, item: &ManifestItem) -> Option<String> {
+        let mut content = String::new();
+        self.read_by_manifest_item(item).ok()?.read_to_string(&mut content).ok()?;
+        Some(content)
+    }
+
+    pub(super) fn find_opf_path_str<R: Read + Seek>(archive: &mut ZipArchive<R>) -> Result<String> {
+        let mut container_file =
+            archive
+                .by_name(CONTAINER_PATH)
+                .map_err(|e| AppError::ZipExtraction {
+                    path: CONTAINER_PATH.into(),
+                    source: e,
+                })?;
+
+        let mut content = String::new();
+        container_file.read_to_string(&mut content)?;
+        Self::parse_container(&content)
+    }
+
+    /// Returns the path of the package document named by `container.xml`.
+    fn parse_container(content: &str) -> Result<String> {
+        let container: Container =
+            quick_xml::de::from_str(content).map_err(|e| AppError::XmlParsing {
+                path: CONTAINER_PATH.into(),
+                source: e,
+            })?;
+
+        container
+            .rootfiles
+            .rootfile
+            .first()
+            .map(|rf| rf.full_path.clone())
+            .ok_or_else(|| AppError::InvalidEpubFormat("No rootfile found in container.xml".to_string()))
+    }
+    
+    /// Parses `META-INF/encryption.xml`, which most books don't have.
+    fn parse_encryption(storage: &mut Storage) -> Result<Option<Encryption>> {
+        if !storage.contains(obfuscation::ENCRYPTION_PATH) {
+            return Ok(None);
+        }
+        let content = storage.read_to_string(obfuscation::ENCRYPTION_PATH)?;
+
+        quick_xml::de::from_str(&content).map(Some).map_err(|e| AppError::XmlParsing {
+            path: obfuscation::ENCRYPTION_PATH.into(),
+            source: e,
+        })
+    }
+
+    fn parse_opf(storage: &mut Storage, opf_path: &str) -> Result<Package> {
+        let opf_content = storage.read_to_string(opf_path)?;
+
+        quick_xml::de::from_str(&opf_content).map_err(|e| AppError::XmlParsing {
+            path: opf_path.to_string().into(),
+            source: e,
+        })
+    }
+}
+
+/// Whether `name` has "cover" as a word of its own, as in `cover.jpg`,
+/// `cover-image`, `bookCover` or `cover2`, but not `discover.png`.
+fn mentions_cover(name: &str) -> bool {
+    // ASCII lowercasing keeps byte offsets, so positions in `lower` index `name` too.
+    let lower = name.to_ascii_lowercase();
+    lower.match_indices("cover").any(|(start, word)| {
+        let before = name[..start].chars().next_back();
+        let after = name[start + word.len()..].chars().next();
+        let starts_word = before.is_none_or(|c| !c.is_alphabetic()) || name[start..].starts_with('C');
+        let ends_word = after.is_none_or(|c| !c.is_lowercase());
+        starts_word && ends_word
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::epub::packer;
+
+    const CONTAINER: &str = r#"<?xml version="1.0"?>
+<container version="1.0" xmlns="urn:oasis:names:tc:opendocument:xmlns:container">
+  <rootfiles><rootfile full-path="OEBPS/content.opf" media-type="application/oebps-package+xml"/></rootfiles>
+</container>
+"#;
+
+    const OPF: &str = r#"<?xml version="1.0" encoding="UTF-8"?>
+<package xmlns="http://www.idpf.org/2007/opf" unique-identifier="uid" version="3.0">
+  <metadata xmlns:dc="http://purl.org/dc/elements/1.1/">
+    <dc:identifier id="uid">urn:uuid:11111111-2222-3333-4444-555555555555</dc:identifier>
+    <dc:title>Fonts</dc:title>
+    <dc:language>en</dc:language>
+  </metadata>
+  <manifest>
+    <item id="font" href="fonts/body.ttf" media-type="font/ttf"/>
+  </manifest>
+  <spine/>
+</package>
+"#;
+
+    fn read_font(epub: &mut Epub) -> Vec<u8> {
+        let item = epub.manifest().items.iter().find(|item| item.id == "font").unwrap().clone();
+        let mut data = Vec::new();
+        epub.read_by_manifest_item(&item).unwrap().read_to_end(&mut data).unwrap();
+        data
+    }
+
+    #[test]
+    fn fonts_of_an_unpacked_directory_are_read_as_they_are() {
+        let dir = std::env::temp_dir().join(format!("tome-book-test-{}", std::process::id()));
+        let book = dir.join("book");
+        fs::create_dir_all(book.join("META-INF")).unwrap();
+        fs::create_dir_all(book.join("OEBPS/fonts")).unwrap();
+        fs::write(book.join("mimetype"), "application/epub+zip").unwrap();
+        fs::write(book.join("META-INF/container.xml"), CONTAINER).unwrap();
+        fs::write(book.join(obfuscation::ENCRYPTION_PATH), obfuscation::add_idpf_entries(None, &["OEBPS/fonts/body.ttf".to_string()])).unwrap();
+        fs::write(book.join("OEBPS/content.opf"), OPF).unwrap();
+        let font: Vec<u8> = (0..3000).map(|i| (i % 256) as u8).collect();
+        fs::write(book.join("OEBPS/fonts/body.ttf"), &font).unwrap();
+
+        // `unpack` de-obfuscates, so the font on disk is the plain one...
+        assert_eq!(read_font(&mut Epub::open(&book).unwrap()), font);
+        // ...while in the packed book it is obfuscated, and read back in the clear.
+        let packed = dir.join("book.epub");
+        packer::pack_directory(&book, &packed, true).unwrap();
+        assert_eq!(read_font(&mut Epub::open(&packed).unwrap()), font);
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn cover_must_be_a_word_of_its_own() {
+        for name in ["cover", "cover.jpg", "Cover.PNG", "cover-image", "book_cover", "bookCover", "coverImage", "cover2.jpg", "COVER.JPG"] {
+            assert!(mentions_cover(name), "{}", name);
+        }
+        for name in ["discover.png", "recover.jpg", "coverage.png", "Discovery", "uncovered"] {
+            assert!(!mentions_cover(name), "{}", name);
+        }
+    }
+}
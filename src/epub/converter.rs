@@ -0,0 +1,469 @@
+// src/epub/converter.rs
+use std::cell::RefCell;
+use std::collections::{HashMap, HashSet};
+use std::fs;
+use std::io::{copy, Read};
+use std::path::{Path, PathBuf};
+
+use path_slash::PathBufExt;
+
+use crate::epub::layout::{PathPlanner, SiteLayout};
+use crate::epub::model::{Manifest, ManifestItem};
+use crate::epub::path_resolver::{LinkSource, PathResolver};
+use crate::epub::xhtml::{self, Element};
+use crate::epub::{markdown, text, toc, Epub};
+use crate::error::Result;
+
+/// The widest chapter separator in text output.
+const SEPARATOR_WIDTH: usize = 72;
+
+/// The formats a book can be converted to.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ExportFormat {
+    Markdown,
+    /// Plain text, optionally wrapped at the given number of columns.
+    Text { width: Option<usize> },
+}
+
+impl ExportFormat {
+    pub fn extension(self) -> &'static str {
+        match self {
+            ExportFormat::Markdown => "md",
+            ExportFormat::Text { .. } => "txt",
+        }
+    }
+}
+
+/// The outcome of a successful `Converter::run`.
+#[derive(Debug, Default)]
+pub struct ConvertReport {
+    /// The number of spine documents parsed.
+    pub documents: usize,
+    /// The number of files written, not counting resources.
+    pub files_written: usize,
+    /// The number of images and other resources copied next to the output.
+    pub resources_copied: usize,
+    /// Problems that did not stop the conversion, such as documents that
+    /// could not be parsed and were skipped.
+    pub warnings: Vec<String>,
+}
+
+/// A spine item, parsed and named.
+struct Document {
+    item: ManifestItem,
+    tree: Element,
+    /// The output file name, e.g. `03-chapter1.md`. In single-file mode no
+    /// such file is written, but the name still identifies the document.
+    file_name: String,
+    /// The label of the first TOC entry pointing into the document.
+    title: Option<String>,
+}
+
+/// Where links point, worked out before any document is rendered.
+#[derive(Default)]
+struct LinkPlan {
+    /// (document, id) -> the anchor to emit at that element. An empty id stands
+    /// for the start of the document.
+    anchors: HashMap<(usize, String), String>,
+    /// (document, id) -> the label of the footnote defined by that element.
+    footnotes: HashMap<(usize, String), String>,
+}
+
+/// What a link in a document turns into.
+pub(super) enum LinkTarget {
+    Url(String),
+    /// A reference to a footnote, by label.
+    Footnote(String),
+}
+
+/// Resolves the links of one document for a renderer.
+pub(super) struct DocumentLinks<'a> {
+    converter: &'a Converter<'a>,
+    index: usize,
+    /// The document, as a `PathResolver::key`.
+    document: String,
+}
+
+impl DocumentLinks<'_> {
+    /// Resolves `href`, found in `attribute`. `None` means the link should be reduced to its text.
+    pub(super) fn link(&self, href: &str, attribute: &str, is_noteref: bool) -> Option<LinkTarget> {
+        if !href.starts_with('#') && (href.starts_with('/') || href.contains(':')) {
+            return Some(LinkTarget::Url(href.to_string()));
+        }
+        match self.converter.target(href, self.index, LinkSource { document: &self.document, attribute })? {
+            Target::Document(index, fragment) => self.document_link(index, fragment, is_noteref),
+            Target::Asset(path) => {
+                self.converter.used_assets.borrow_mut().insert(path.clone());
+                Some(LinkTarget::Url(urlencoding::encode(&path.to_slash_lossy()).replace("%2F", "/")))
+            }
+        }
+    }
+
+    fn document_link(&self, index: usize, fragment: &str, is_noteref: bool) -> Option<LinkTarget> {
+        let key = (index, fragment.to_string());
+        if is_noteref
+            && self.converter.unit(index) == self.converter.unit(self.index)
+            && let Some(label) = self.converter.plan.footnotes.get(&key) {
+            return Some(LinkTarget::Footnote(label.clone()));
+        }
+
+        let anchor = self.converter.plan.anchors.get(&key).map(|anchor| format!("#{}", anchor)).unwrap_or_default();
+        let file = if self.converter.split && index != self.index {
+            self.converter.documents[index].file_name.as_str()
+        } else {
+            ""
+        };
+        let url = format!("{}{}", urlencoding::encode(file).replace("%2F", "/"), anchor);
+        (!url.is_empty()).then_some(LinkTarget::Url(url))
+    }
+
+    /// The anchor to emit for an element with the given id, if anything links to it.
+    pub(super) fn anchor(&self, id: &str) -> Option<&str> {
+        self.converter.plan.anchors.get(&(self.index, id.to_string())).map(String::as_str)
+    }
+
+    /// The label under which the element with the given id becomes a footnote.
+    pub(super) fn footnote(&self, id: &str) -> Option<&str> {
+        self.converter.plan.footnotes.get(&(self.index, id.to_string())).map(String::as_str)
+    }
+}
+
+enum Target<'a> {
+    /// A spine document and a fragment, empty for the document itself.
+    Document(usize, &'a str),
+    Asset(PathBuf),
+}
+
+/// Converts the spine of a book into documents of another format, either a
+/// single file or one file per spine item. Resources the documents refer
+/// to, such as images, are copied next to them.
+pub struct Converter<'a> {
+    format: ExportFormat,
+    split: bool,
+    /// The output file, or the output directory when splitting.
+    output: &'a Path,
+    documents: Vec<Document>,
+    resolver: PathResolver,
+    /// Document file names -> index into `documents`.
+    document_paths: HashMap<PathBuf, usize>,
+    /// Non-document manifest items and where they are copied to.
+    assets: Vec<(ManifestItem, PathBuf)>,
+    plan: LinkPlan,
+    /// Assets the rendered documents refer to; only these are copied.
+    used_assets: RefCell<HashSet<PathBuf>>,
+    warnings: Vec<String>,
+}
+
+impl<'a> Converter<'a> {
+    pub fn new(format: ExportFormat, output: &'a Path, split: bool) -> Self {
+        Self {
+            format,
+            split,
+            output,
+            documents: Vec::new(),
+            resolver: PathResolver::new(),
+            document_paths: HashMap::new(),
+            assets: Vec::new(),
+            plan: LinkPlan::default(),
+            used_assets: RefCell::new(HashSet::new()),
+            warnings: Vec::new(),
+        }
+    }
+
+    pub fn run(&mut self, epub: &mut Epub) -> Result<ConvertReport> {
+        self.load_documents(epub)?;
+        self.plan_paths(epub.manifest());
+        // Plain text has no links, so there is nothing to plan.
+        if self.format == ExportFormat::Markdown {
+            self.plan_links();
+        }
+        let files_written = self.write_documents()?;
+        let resources_copied = self.copy_assets(epub)?;
+
+        let mut warnings = std::mem::take(&mut self.warnings);
+        warnings.extend(self.resolver.take_warnings());
+        Ok(ConvertReport { documents: self.documents.len(), files_written, resources_copied, warnings })
+    }
+
+    /// The directory the documents are written to; asset paths are relative to it.
+    fn base_dir(&self) -> &Path {
+        if self.split {
+            self.output
+        } else {
+            self.output.parent().unwrap_or_else(|| Path::new(""))
+        }
+    }
+
+    /// Documents that end up in the same output file share a unit.
+    fn unit(&self, index: usize) -> usize {
+        if self.split { index } else { 0 }
+    }
+
+    /// Parses the spine documents in reading order, non-linear ones included.
+    fn load_documents(&mut self, epub: &mut Epub) -> Result<()> {
+        let spine_items: Vec<ManifestItem> = epub.spine().item_refs.iter()
+            .filter_map(|item_ref| epub.manifest().spine_item(&item_ref.idref).cloned())
+            .collect();
+        let width = spine_items.len().to_string().len().max(2);
+        let titles = toc::document_titles(epub.toc());
+
+        for item in spine_items {
+            let mut content = Vec::new();
+            epub.read_by_manifest_item(&item)?.read_to_end(&mut content)?;
+            let tree = match xhtml::parse_tree(&String::from_utf8_lossy(&content)) {
+                Ok(tree) => tree,
+                Err(e) => {
+                    self.warnings.push(format!("Skipping '{}': {}", item.href, e));
+                    continue;
+                }
+            };
+            let title = titles.get(&PathResolver::key(&item.href)).cloned();
+            self.add_document(item, tree, title, width);
+        }
+        Ok(())
+    }
+
+    /// Appends a document, numbering its file name with `width` digits.
+    fn add_document(&mut self, item: ManifestItem, tree: Element, title: Option<String>, width: usize) {
+        let stem = Path::new(&PathResolver::key(&item.href))
+            .file_stem()
+            .map(|stem| stem.to_string_lossy().into_owned())
+            .unwrap_or_default();
+        let file_name = format!("{:0width$}-{}.{}", self.documents.len() + 1, stem, self.format.extension());
+        self.documents.push(Document { item, tree, file_name, title });
+    }
+
+    fn plan_paths(&mut self, manifest: &Manifest) {
+        for (index, document) in self.documents.iter().enumerate() {
+            let path = PathBuf::from(&document.file_name);
+            self.resolver.insert(&document.item.href, path.clone());
+            self.document_paths.insert(path, index);
+        }
+
+        let assets_dir = if self.split {
+            "assets".to_string()
+        } else {
+            let stem = self.output.file_stem().map(|stem| stem.to_string_lossy().into_owned()).unwrap_or_default();
+            format!("{}_assets", stem)
+        };
+        let mut planner = PathPlanner::new(SiteLayout::Flat, &[]).with_assets_dir(&assets_dir);
+        for item in &manifest.items {
+            let key = PathResolver::key(&item.href);
+            if self.documents.iter().any(|document| PathResolver::key(&document.item.href) == key) {
+                continue;
+            }
+            let (path, _) = planner.assign(&key, false);
+            self.resolver.insert(&item.href, path.clone());
+            self.assets.push((item.clone(), path));
+        }
+    }
+
+    /// Resolves a link found in document `index`.
+    fn target<'l>(&self, href: &'l str, index: usize, from: LinkSource<'_>) -> Option<Target<'l>> {
+        if let Some(fragment) = href.strip_prefix('#') {
+            return Some(Target::Document(index, fragment));
+        }
+        let (path, fragment) = self.resolver.resolve(href, from)?;
+        match self.document_paths.get(path) {
+            Some(&target) => Some(Target::Document(target, fragment.unwrap_or(""))),
+            None => Some(Target::Asset(path.clone())),
+        }
+    }
+
+    /// Finds every link target up front, so that anchors are only emitted
+    /// where something points and note references can become footnotes.
+    fn plan_links(&mut self) {
+        let mut note_ids = HashSet::new();
+        let mut targets = Vec::new();
+        let mut noterefs = HashSet::new();
+        for (index, document) in self.documents.iter().enumerate() {
+            let key = PathResolver::key(&document.item.href);
+            document.tree.walk(&mut |element| {
+                if let Some(id) = element.attribute("id")
+                    && ["footnote", "endnote", "rearnote", "note"].iter().any(|t| element.has_semantic(t)) {
+                    note_ids.insert((index, id.to_string()));
+                }
+                if element.name == "a"
+                    && let Some(href) = element.attribute("href")
+                    && let Some(Target::Document(target, fragment)) = self.target(href, index, LinkSource { document: &key, attribute: "href" }) {
+                    let key = (target, fragment.to_string());
+                    if element.has_semantic("noteref") && self.unit(target) == self.unit(index) {
+                        noterefs.insert(key.clone());
+                    }
+                    // Links to the start of another file need no anchor when splitting.
+                    if !(fragment.is_empty() && self.split) {
+                        targets.push(key);
+                    }
+                }
+            });
+        }
+
+        // Ids only need to be unique within an output file; earlier documents keep theirs unchanged.
+        targets.sort_by_key(|(index, _)| *index);
+        let mut used: HashMap<usize, HashSet<String>> = HashMap::new();
+        let mut unique_name = |unit: usize, name: String| {
+            let taken = used.entry(unit).or_default();
+            let mut candidate = name.clone();
+            let mut counter = 2;
+            while !taken.insert(candidate.clone()) {
+                candidate = format!("{}-{}", name, counter);
+                counter += 1;
+            }
+            candidate
+        };
+
+        let mut plan = LinkPlan::default();
+        for key in targets {
+            if plan.anchors.contains_key(&key) || plan.footnotes.contains_key(&key) {
+                continue;
+            }
+            let (index, id) = &key;
+            let unit = self.unit(*index);
+            if noterefs.contains(&key) && note_ids.contains(&key) {
+                let label = unique_name(unit, id.clone());
+                plan.footnotes.insert(key, label);
+            } else {
+                let name = if id.is_empty() {
+                    Path::new(&self.documents[*index].file_name).file_stem().map(|s| s.to_string_lossy().into_owned()).unwrap_or_default()
+                } else {
+                    id.clone()
+                };
+                let anchor = unique_name(unit, name);
+                plan.anchors.insert(key, anchor);
+            }
+        }
+        self.plan = plan;
+    }
+
+    fn render(&self, index: usize) -> String {
+        let document = &self.documents[index];
+        let links = DocumentLinks { converter: self, index, document: PathResolver::key(&document.item.href) };
+        let body = document.tree.find("body").unwrap_or(&document.tree);
+        match self.format {
+            ExportFormat::Markdown => {
+                let output = markdown::render(body, &links);
+                match self.plan.anchors.get(&(index, String::new())) {
+                    Some(anchor) => format!("<a id=\"{}\"></a>\n\n{}", anchor, output),
+                    None => output,
+                }
+            }
+            ExportFormat::Text { width } => {
+                // Every chapter starts with a separator, named after its TOC entry.
+                let rule = "=".repeat(width.map_or(SEPARATOR_WIDTH, |width| width.clamp(1, SEPARATOR_WIDTH)));
+                let header = match &document.title {
+                    Some(title) => format!("{}\n{}\n{}", rule, text::wrap(&xhtml::collapse_whitespace(title), width), rule),
+                    None => rule,
+                };
+                let output = text::render(body, width);
+                if output.is_empty() { header } else { format!("{}\n\n{}", header, output) }
+            }
+        }
+    }
+
+    /// Writes the documents and returns the number of files written.
+    fn write_documents(&self) -> Result<usize> {
+        if self.split {
+            fs::create_dir_all(self.output)?;
+            for index in 0..self.documents.len() {
+                let content = self.render(index);
+                fs::write(self.output.join(&self.documents[index].file_name), finish(content))?;
+            }
+            return Ok(self.documents.len());
+        }
+
+        if let Some(parent) = self.output.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        let chapters: Vec<String> = (0..self.documents.len())
+            .map(|index| self.render(index))
+            .filter(|chapter| !chapter.is_empty())
+            .collect();
+        fs::write(self.output, finish(chapters.join("\n\n")))?;
+        Ok(1)
+    }
+
+    /// Copies the resources the documents refer to and returns how many there were.
+    fn copy_assets(&self, epub: &mut Epub) -> Result<usize> {
+        let used_assets = self.used_assets.borrow();
+        let mut copied = 0;
+        for (item, path) in self.assets.iter().filter(|(_, path)| used_assets.contains(path)) {
+            let dest_path = self.base_dir().join(path);
+            if let Some(parent) = dest_path.parent() { fs::create_dir_all(parent)?; }
+            copy(&mut epub.read_by_manifest_item(item)?, &mut fs::File::create(&dest_path)?)?;
+            copied += 1;
+        }
+        Ok(copied)
+    }
+}
+
+/// Ends a file with exactly one newline.
+fn finish(content: String) -> String {
+    format!("{}\n", content.trim_end())
+}
+
+/// Renders the given `(href, content)` spine documents, one string per
+/// document, as a split or single-file conversion would.
+#[cfg(test)]
+pub(super) fn render_documents(format: ExportFormat, documents: &[(&str, &str)], split: bool) -> Vec<String> {
+    let output = Path::new(if split { "book" } else { "book.out" });
+    let mut converter = Converter::new(format, output, split);
+    let items: Vec<ManifestItem> = documents.iter()
+        .enumerate()
+        .map(|(index, (href, _))| ManifestItem {
+            id: format!("item{}", index + 1),
+            href: href.to_string(),
+            media_type: "application/xhtml+xml".to_string(),
+            properties: None,
+        })
+        .collect();
+    for (item, (_, content)) in items.iter().zip(documents) {
+        converter.add_document(item.clone(), xhtml::parse_tree(content).unwrap(), None, 2);
+    }
+    converter.plan_paths(&Manifest { items });
+    converter.plan_links();
+    (0..converter.documents.len()).map(|index| converter.render(index)).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const CHAPTER_ONE: &str = r#"<html><body>
+        <p>See <a href="ch2.xhtml#sec">the section</a> or <a href="ch2.xhtml">chapter two</a>.</p>
+    </body></html>"#;
+    const CHAPTER_TWO: &str = r#"<html><body><h1 id="sec">Two</h1><p>Text.</p></body></html>"#;
+
+    #[test]
+    fn split_documents_link_to_each_other_by_file_name() {
+        let rendered = render_documents(ExportFormat::Markdown, &[("Text/ch1.xhtml", CHAPTER_ONE), ("Text/ch2.xhtml", CHAPTER_TWO)], true);
+        assert_eq!(rendered[0], "See [the section](02-ch2.md#sec) or [chapter two](02-ch2.md).");
+        // Linking to the start of a file needs no anchor.
+        assert_eq!(rendered[1], "# <a id=\"sec\"></a>Two\n\nText.");
+    }
+
+    #[test]
+    fn a_single_file_links_to_anchors() {
+        let rendered = render_documents(ExportFormat::Markdown, &[("Text/ch1.xhtml", CHAPTER_ONE), ("Text/ch2.xhtml", CHAPTER_TWO)], false);
+        assert_eq!(rendered[0], "See [the section](#sec) or [chapter two](#02-ch2).");
+        assert_eq!(rendered[1], "<a id=\"02-ch2\"></a>\n\n# <a id=\"sec\"></a>Two\n\nText.");
+    }
+
+    #[test]
+    fn ids_repeated_across_documents_get_unique_anchors_in_a_single_file() {
+        let first = r#"<html><body><h1 id="top">One</h1><p><a href="ch2.xhtml#top">Next</a></p></body></html>"#;
+        let second = r#"<html><body><h1 id="top">Two</h1><p><a href="ch1.xhtml#top">Back</a></p></body></html>"#;
+        let rendered = render_documents(ExportFormat::Markdown, &[("ch1.xhtml", first), ("ch2.xhtml", second)], false);
+        assert_eq!(rendered[0], "# <a id=\"top\"></a>One\n\n[Next](#top-2)");
+        assert_eq!(rendered[1], "# <a id=\"top-2\"></a>Two\n\n[Back](#top)");
+    }
+
+    #[test]
+    fn text_separators_are_clamped_to_the_wrap_width() {
+        let chapter = [("ch1.xhtml", "<html><body><p>One two three</p></body></html>")];
+        let rule = |width| render_documents(ExportFormat::Text { width }, &chapter, true).remove(0).lines().next().unwrap().len();
+        assert_eq!(rule(None), SEPARATOR_WIDTH);
+        assert_eq!(rule(Some(20)), 20);
+        assert_eq!(rule(Some(200)), SEPARATOR_WIDTH);
+        assert_eq!(rule(Some(0)), 1);
+    }
+}
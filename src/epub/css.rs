@@ -0,0 +1,399 @@
+// src/epub/css.rs
+
+/// A token and where it sits in the source.
+struct Token {
+    kind: TokenKind,
+    start: usize,
+    end: usize,
+}
+
+enum TokenKind {
+    Comment,
+    /// A quoted string; `value` has its escapes resolved.
+    String { value: String },
+    /// An unquoted `url(...)`, spanning the whole function.
+    Url { value: String },
+    /// An identifier followed by `(`, lowercased; the span includes the `(`.
+    Function(String),
+    /// `@name`, lowercased.
+    AtKeyword(String),
+    OpenParen,
+    CloseParen,
+    Semicolon,
+    OpenBrace,
+    CloseBrace,
+    Other,
+}
+
+/// Rewrites every URL in `css`: `url(...)` in any of its forms, `@import
+/// "file.css"` and the strings of `image-set()`. Everything else, comments
+/// included, is copied through byte for byte.
+///
+/// `rewrite` receives the URL with CSS escapes resolved and returns `None`
+/// to leave it untouched.
+pub(super) fn rewrite_urls(css: &str, mut rewrite: impl FnMut(&str) -> Option<String>) -> String {
+    let mut output = String::with_capacity(css.len());
+    let mut copied_to = 0;
+    // Open functions and parentheses, innermost last.
+    let mut functions: Vec<String> = Vec::new();
+    let mut in_import = false;
+
+    for token in Tokenizer::new(css) {
+        let replacement = match &token.kind {
+            TokenKind::Url { value } => rewrite(value).map(|url| format_url(&url)),
+            TokenKind::String { value } => {
+                let is_url = match functions.last() {
+                    Some(name) => name == "url" || name.ends_with("image-set"),
+                    None => in_import,
+                };
+                if is_url {
+                    let quote = css[token.start..].chars().next().unwrap_or('"');
+                    rewrite(value).map(|url| format_string(&url, quote))
+                } else {
+                    None
+                }
+            }
+            TokenKind::Function(name) => {
+                functions.push(name.clone());
+                None
+            }
+            TokenKind::OpenParen => {
+                functions.push(String::new());
+                None
+            }
+            TokenKind::CloseParen => {
+                functions.pop();
+                None
+            }
+            TokenKind::AtKeyword(name) => {
+                in_import = name == "import";
+                None
+            }
+            TokenKind::Semicolon | TokenKind::OpenBrace | TokenKind::CloseBrace => {
+                in_import = false;
+                functions.clear();
+                None
+            }
+            TokenKind::Comment | TokenKind::Other => None,
+        };
+
+        if let Some(replacement) = replacement {
+            output.push_str(&css[copied_to..token.start]);
+            output.push_str(&replacement);
+            copied_to = token.end;
+        }
+    }
+    output.push_str(&css[copied_to..]);
+    output
+}
+
+/// Writes a URL as `url(...)`, quoting it when it contains characters that
+/// an unquoted URL can't hold.
+fn format_url(url: &str) -> String {
+    if url.chars().any(|c| c.is_whitespace() || matches!(c, '(' | ')' | '"' | '\'' | '\\')) {
+        format!("url({})", format_string(url, '"'))
+    } else {
+        format!("url({})", url)
+    }
+}
+
+fn format_string(value: &str, quote: char) -> String {
+    let mut result = String::with_capacity(value.len() + 2);
+    result.push(quote);
+    for c in value.chars() {
+        match c {
+            '\\' => result.push_str("\\\\"),
+            '\n' => result.push_str("\\a "),
+            c if c == quote => {
+                result.push('\\');
+                result.push(c);
+            }
+            c => result.push(c),
+        }
+    }
+    result.push(quote);
+    result
+}
+
+struct Tokenizer<'a> {
+    css: &'a str,
+    pos: usize,
+}
+
+impl<'a> Tokenizer<'a> {
+    fn new(css: &'a str) -> Self {
+        Self { css, pos: 0 }
+    }
+
+    fn peek(&self) -> Option<char> {
+        self.css[self.pos..].chars().next()
+    }
+
+    fn peek_at(&self, offset: usize) -> Option<char> {
+        self.css[self.pos..].chars().nth(offset)
+    }
+
+    fn bump(&mut self) -> Option<char> {
+        let c = self.peek()?;
+        self.pos += c.len_utf8();
+        Some(c)
+    }
+
+    fn starts_escape(&self) -> bool {
+        self.peek() == Some('\\') && !matches!(self.peek_at(1), None | Some('\n'))
+    }
+
+    fn starts_ident(&self) -> bool {
+        match self.peek() {
+            Some(c) if is_name_start(c) => true,
+            Some('-') => matches!(self.peek_at(1), Some(c) if is_name_start(c) || c == '-'),
+            Some('\\') => self.starts_escape(),
+            _ => false,
+        }
+    }
+
+    /// Consumes an escape, the backslash included, and returns the character it stands for.
+    fn consume_escape(&mut self) -> char {
+        self.bump(); // the backslash
+        let hex: String = self.css[self.pos..].chars().take_while(char::is_ascii_hexdigit).take(6).collect();
+        if hex.is_empty() {
+            return self.bump().unwrap_or('\u{fffd}');
+        }
+        self.pos += hex.len();
+        // A single whitespace character terminates a hex escape.
+        if self.peek().is_some_and(|c| c.is_ascii_whitespace()) {
+            self.bump();
+        }
+        u32::from_str_radix(&hex, 16).ok()
+            .and_then(char::from_u32)
+            .filter(|&c| c != '\0')
+            .unwrap_or('\u{fffd}')
+    }
+
+    fn consume_name(&mut self) -> String {
+        let mut name = String::new();
+        loop {
+            match self.peek() {
+                Some(c) if is_name(c) => {
+                    name.push(c);
+                    self.bump();
+                }
+                Some('\\') if self.starts_escape() => name.push(self.consume_escape()),
+                _ => return name,
+            }
+        }
+    }
+
+    /// Consumes a quoted string, the opening quote being the next character.
+    fn consume_string(&mut self) -> String {
+        let quote = self.bump();
+        let mut value = String::new();
+        loop {
+            match self.peek() {
+                None => return value,
+                // An unescaped newline ends a (bad) string without consuming it.
+                Some('\n') => return value,
+                Some(c) if Some(c) == quote => {
+                    self.bump();
+                    return value;
+                }
+                Some('\\') => match self.peek_at(1) {
+                    None => {
+                        self.bump();
+                    }
+                    Some('\n') => {
+                        self.bump();
+                        self.bump();
+                    }
+                    Some(_) => value.push(self.consume_escape()),
+                },
+                Some(c) => {
+                    value.push(c);
+                    self.bump();
+                }
+            }
+        }
+    }
+
+    /// Consumes the rest of an unquoted `url(`, up to and including the `)`.
+    fn consume_unquoted_url(&mut self) -> String {
+        let mut value = String::new();
+        self.skip_whitespace();
+        loop {
+            match self.peek() {
+                None => return value,
+                Some(')') => {
+                    self.bump();
+                    return value;
+                }
+                Some(c) if c.is_ascii_whitespace() => {
+                    self.skip_whitespace();
+                    if matches!(self.peek(), Some(')') | None) {
+                        self.bump();
+                    } else {
+                        self.consume_bad_url();
+                    }
+                    return value;
+                }
+                Some('\\') if self.starts_escape() => value.push(self.consume_escape()),
+                Some(c) => {
+                    value.push(c);
+                    self.bump();
+                }
+            }
+        }
+    }
+
+    /// Skips the remnants of a malformed URL, so that they don't confuse the tokens after it.
+    fn consume_bad_url(&mut self) {
+        while let Some(c) = self.peek() {
+            if c == '\\' && self.starts_escape() {
+                self.consume_escape();
+            } else {
+                self.bump();
+                if c == ')' {
+                    return;
+                }
+            }
+        }
+    }
+
+    fn skip_whitespace(&mut self) {
+        while self.peek().is_some_and(|c| c.is_ascii_whitespace()) {
+            self.bump();
+        }
+    }
+}
+
+impl Iterator for Tokenizer<'_> {
+    type Item = Token;
+
+    fn next(&mut self) -> Option<Token> {
+        let start = self.pos;
+        let c = self.peek()?;
+
+        let kind = if self.css[self.pos..].starts_with("/*") {
+            self.pos = match self.css[self.pos + 2..].find("*/") {
+                Some(end) => self.pos + 2 + end + 2,
+                None => self.css.len(),
+            };
+            TokenKind::Comment
+        } else if c == '"' || c == '\'' {
+            TokenKind::String { value: self.consume_string() }
+        } else if c == '@' {
+            self.bump();
+            if self.starts_ident() {
+                TokenKind::AtKeyword(self.consume_name().to_lowercase())
+            } else {
+                TokenKind::Other
+            }
+        } else if self.starts_ident() {
+            let name = self.consume_name();
+            if self.peek() == Some('(') {
+                self.bump();
+                let name = name.to_lowercase();
+                let rest = self.css[self.pos..].trim_start_matches(|c: char| c.is_ascii_whitespace());
+                if name == "url" && !rest.starts_with(['"', '\'']) {
+                    TokenKind::Url { value: self.consume_unquoted_url() }
+                } else {
+                    TokenKind::Function(name)
+                }
+            } else {
+                TokenKind::Other
+            }
+        } else {
+            self.bump();
+            match c {
+                '(' => TokenKind::OpenParen,
+                ')' => TokenKind::CloseParen,
+                ';' => TokenKind::Semicolon,
+                '{' => TokenKind::OpenBrace,
+                '}' => TokenKind::CloseBrace,
+                '\\' if self.peek().is_some() => {
+                    // A stray escape; keep its escaped character out of the tokenizer's way.
+                    self.bump();
+                    TokenKind::Other
+                }
+                _ => TokenKind::Other,
+            }
+        };
+
+        Some(Token { kind, start, end: self.pos })
+    }
+}
+
+fn is_name_start(c: char) -> bool {
+    c.is_ascii_alphabetic() || c == '_' || !c.is_ascii()
+}
+
+fn is_name(c: char) -> bool {
+    is_name_start(c) || c.is_ascii_digit() || c == '-'
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// The URLs `rewrite_urls` finds in `css`, in order.
+    fn urls(css: &str) -> Vec<String> {
+        let mut urls = Vec::new();
+        rewrite_urls(css, |url| {
+            urls.push(url.to_string());
+            None
+        });
+        urls
+    }
+
+    /// Moves every relative URL below `new/`.
+    fn relocate(css: &str) -> String {
+        rewrite_urls(css, |url| (!url.starts_with("data:")).then(|| format!("new/{}", url)))
+    }
+
+    #[test]
+    fn escaped_parenthesis_in_unquoted_url() {
+        let css = r"a { background: url(a\)b.png) no-repeat }";
+        assert_eq!(urls(css), ["a)b.png"]);
+        assert_eq!(relocate(css), r#"a { background: url("new/a)b.png") no-repeat }"#);
+    }
+
+    #[test]
+    fn urls_in_comments_are_left_alone() {
+        let css = "/* url(x.png) */ a { background: url(y.png) } /* @import \"z.css\"; */";
+        assert_eq!(urls(css), ["y.png"]);
+        assert_eq!(relocate(css), "/* url(x.png) */ a { background: url(new/y.png) } /* @import \"z.css\"; */");
+    }
+
+    #[test]
+    fn data_uris_containing_parentheses() {
+        let quoted = r#"url("data:image/svg+xml,<svg><path d='M0 0 (1)'/></svg>")"#;
+        let unquoted = r"url(data:image/svg+xml,%3Csvg%3E\(1\)%3C/svg%3E)";
+        let css = format!("a {{ background: {}, {}, url(after.png) }}", quoted, unquoted);
+        assert_eq!(urls(&css), [
+            "data:image/svg+xml,<svg><path d='M0 0 (1)'/></svg>",
+            "data:image/svg+xml,%3Csvg%3E(1)%3C/svg%3E",
+            "after.png",
+        ]);
+        // Left untouched, they are copied byte for byte.
+        assert_eq!(relocate(&css), css.replace("url(after.png)", "url(new/after.png)"));
+    }
+
+    #[test]
+    fn import_with_a_string_or_a_url() {
+        let css = "@import \"x.css\";\n@import url(y.css) screen;\n@import url('z.css');\na::before { content: \"w.css\" }";
+        assert_eq!(urls(css), ["x.css", "y.css", "z.css"]);
+        assert_eq!(
+            relocate(css),
+            "@import \"new/x.css\";\n@import url(new/y.css) screen;\n@import url('new/z.css');\na::before { content: \"w.css\" }"
+        );
+    }
+
+    #[test]
+    fn image_set_with_several_candidates() {
+        let css = "a { background: image-set(\"a.png\" 1x, url(b.png) 2x, 'c.avif' type(\"image/avif\")); b: -webkit-image-set(\"d.png\" 1x) }";
+        assert_eq!(urls(css), ["a.png", "b.png", "c.avif", "d.png"]);
+        assert_eq!(
+            relocate(css),
+            "a { background: image-set(\"new/a.png\" 1x, url(new/b.png) 2x, 'new/c.avif' type(\"image/avif\")); b: -webkit-image-set(\"new/d.png\" 1x) }"
+        );
+    }
+}
@@ -0,0 +1,77 @@
+// src/epub/diagnostics.rs
+use std::fmt;
+
+use serde::Serialize;
+
+/// The links of a book that a build could not follow as written, as found
+/// in `WebifyReport::diagnostics`.
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct Diagnostics {
+    /// One entry per link and place it was found, in the order they were met.
+    pub links: Vec<LinkDiagnostic>,
+}
+
+impl Diagnostics {
+    pub fn is_empty(&self) -> bool {
+        self.links.is_empty()
+    }
+
+    /// The links that lead nowhere in the site. Missing fonts and links that
+    /// only differ in case from a file are not among them.
+    pub fn broken_links(&self) -> impl Iterator<Item = &LinkDiagnostic> {
+        self.links.iter().filter(|link| link.is_broken())
+    }
+
+    pub fn has_broken_links(&self) -> bool {
+        self.broken_links().next().is_some()
+    }
+}
+
+/// A link that could not be resolved, or only loosely.
+#[derive(Debug, Clone, Serialize)]
+pub struct LinkDiagnostic {
+    /// The file holding the link, by its path relative to the OPF file.
+    pub source: String,
+    /// Where the link is in the file: the attribute holding it, such as
+    /// `href` or `srcset`, or `url()` for CSS.
+    pub attribute: String,
+    /// The link as written.
+    pub link: String,
+    /// The path the link names relative to the OPF file: resolved against
+    /// `source`, percent-decoded and normalized, without its fragment.
+    pub key: String,
+    pub reason: LinkProblem,
+}
+
+impl LinkDiagnostic {
+    /// Whether the link leads nowhere in the site.
+    pub fn is_broken(&self) -> bool {
+        self.reason == LinkProblem::NotFound
+    }
+}
+
+impl fmt::Display for LinkDiagnostic {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match &self.reason {
+            LinkProblem::NotFound => write!(f, "Could not resolve link '{}' (resolved to '{}') in '{}'", self.link, self.key, self.source),
+            LinkProblem::MissingFont => write!(f, "Could not find font '{}' (resolved to '{}') in '{}'", self.link, self.key, self.source),
+            LinkProblem::CaseMismatch { actual } => write!(f, "'{}' in '{}' only matches '{}' when ignoring case", self.link, self.source, actual),
+        }
+    }
+}
+
+/// Why a link is in the diagnostics.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+#[serde(tag = "kind", rename_all = "kebab-case")]
+#[non_exhaustive]
+pub enum LinkProblem {
+    /// No file of the book has that path.
+    NotFound,
+    /// No font file has that path. Style sheets often list fonts the book
+    /// does not ship as fallbacks, so these are neither warned about as the
+    /// build goes nor counted as broken links.
+    MissingFont,
+    /// Only the file `actual` matches, when ignoring case. The link was
+    /// pointed at it, as a case-insensitive system would have.
+    CaseMismatch { actual: String },
+}
@@ -0,0 +1,569 @@
+// src/epub/editor.rs
+use std::collections::{HashMap, HashSet};
+use std::fs;
+use std::io::{BufReader, Read, Seek, Write};
+use std::path::{Path, PathBuf};
+
+use path_slash::PathExt;
+use quick_xml::events::{BytesEnd, BytesStart, BytesText, Event};
+use quick_xml::{Reader, Writer};
+use zip::write::SimpleFileOptions;
+use zip::{ZipArchive, ZipWriter};
+
+use crate::epub::model::{Encryption, Package};
+use crate::epub::obfuscation::{self, ObfuscationKey, ENCRYPTION_PATH};
+use crate::epub::xhtml::{attribute, collapse_whitespace, text_content};
+use crate::epub::Epub;
+use crate::error::{AppError, Result};
+
+/// A set of changes to apply to the `<metadata>` of a package document.
+///
+/// Values are matched after collapsing whitespace, so `--remove-subject`
+/// works against subjects that are wrapped over several lines.
+#[derive(Debug, Default)]
+pub struct MetadataEdits {
+    /// Replaces the text of the first `dc:title`.
+    pub title: Option<String>,
+    /// When non-empty, replaces every `dc:creator`.
+    pub authors: Vec<String>,
+    pub remove_authors: Vec<String>,
+    /// When non-empty, replaces every `dc:language`.
+    pub languages: Vec<String>,
+    pub remove_languages: Vec<String>,
+    /// Replaces the value of the identifier named by `unique-identifier`.
+    pub identifier: Option<String>,
+    pub remove_identifiers: Vec<String>,
+    pub add_subjects: Vec<String>,
+    pub remove_subjects: Vec<String>,
+}
+
+impl MetadataEdits {
+    pub fn is_empty(&self) -> bool {
+        self.title.is_none()
+            && self.authors.is_empty()
+            && self.remove_authors.is_empty()
+            && self.languages.is_empty()
+            && self.remove_languages.is_empty()
+            && self.identifier.is_none()
+            && self.remove_identifiers.is_empty()
+            && self.add_subjects.is_empty()
+            && self.remove_subjects.is_empty()
+    }
+}
+
+/// Applies `edits` to the package document of the EPUB at `source` and
+/// writes the result to `dest`, which may be the same file.
+///
+/// Every other entry is copied byte-for-byte without being recompressed, so
+/// `mimetype` keeps its position and storage method. The exception are
+/// obfuscated fonts when the unique identifier changes: their key derives
+/// from it, so they are obfuscated again with the new one.
+///
+/// `source` may also be an unpacked directory, which is edited in place:
+/// `dest` must then be the directory itself.
+pub fn edit_metadata(source: &Path, dest: &Path, edits: &MetadataEdits) -> Result<()> {
+    if source.is_dir() {
+        return edit_unpacked_metadata(source, dest, edits);
+    }
+    let mut archive = ZipArchive::new(BufReader::new(fs::File::open(source)?))
+        .map_err(|e| AppError::ZipExtraction { path: source.to_path_buf(), source: e })?;
+    let opf_path = Epub::find_opf_path_str(&mut archive)?;
+
+    let mut opf = String::new();
+    archive.by_name(&opf_path)
+        .map_err(|e| AppError::ZipExtraction { path: opf_path.clone().into(), source: e })?
+        .read_to_string(&mut opf)?;
+    let modified = chrono::Utc::now().format("%Y-%m-%dT%H:%M:%SZ").to_string();
+    let new_opf = rewrite_metadata(&opf, &opf_path, edits, &modified)?;
+    let rekeyed = if edits.identifier.is_some() {
+        rekeyed_fonts(&mut archive, &opf, &new_opf, &opf_path)?
+    } else {
+        HashMap::new()
+    };
+    let opf = new_opf;
+
+    let temp = TempFile::next_to(dest)?;
+    let to_zip_error = |source| AppError::ZipCreation { path: dest.to_path_buf(), source };
+
+    let mut writer = ZipWriter::new(fs::File::create(&temp.path)?);
+    for index in 0..archive.len() {
+        let file = archive.by_index_raw(index).map_err(|e| AppError::ZipExtraction { path: source.to_path_buf(), source: e })?;
+        if file.name() == opf_path {
+            let options = SimpleFileOptions::default().compression_method(file.compression());
+            writer.start_file(opf_path.as_str(), options).map_err(to_zip_error)?;
+            writer.write_all(opf.as_bytes())?;
+        } else if let Some((old_key, new_key)) = rekeyed.get(file.name()) {
+            let options = SimpleFileOptions::default().compression_method(file.compression());
+            let name = file.name().to_string();
+            drop(file);
+            let mut data = Vec::new();
+            archive.by_index(index)
+                .map_err(|e| AppError::ZipExtraction { path: source.to_path_buf(), source: e })?
+                .read_to_end(&mut data)?;
+            old_key.apply_to_file(&mut data);
+            new_key.apply_to_file(&mut data);
+            writer.start_file(name, options).map_err(to_zip_error)?;
+            writer.write_all(&data)?;
+        } else {
+            writer.raw_copy_file(file).map_err(to_zip_error)?;
+        }
+    }
+    writer.finish().map_err(to_zip_error)?;
+    drop(archive);
+
+    temp.persist(dest)
+}
+
+/// The obfuscated fonts whose key changes from the package document
+/// `old_opf` to `new_opf`, with their old and new keys, by archive path.
+fn rekeyed_fonts<R: Read + Seek>(archive: &mut ZipArchive<R>, old_opf: &str, new_opf: &str, opf_path: &str) -> Result<HashMap<String, (ObfuscationKey, ObfuscationKey)>> {
+    let mut content = String::new();
+    match archive.by_name(ENCRYPTION_PATH) {
+        Ok(mut file) => file.read_to_string(&mut content)?,
+        Err(_) => return Ok(HashMap::new()),
+    };
+    let encryption: Encryption = quick_xml::de::from_str(&content)
+        .map_err(|source| AppError::XmlParsing { path: ENCRYPTION_PATH.into(), source })?;
+    let parse = |opf: &str| -> Result<Package> {
+        quick_xml::de::from_str(opf).map_err(|source| AppError::XmlParsing { path: opf_path.into(), source })
+    };
+    let mut new_keys = obfuscation::obfuscated_entries(&encryption, &parse(new_opf)?);
+
+    let mut rekeyed = HashMap::new();
+    for (path, old_key) in obfuscation::obfuscated_entries(&encryption, &parse(old_opf)?) {
+        match new_keys.remove(&path) {
+            Some(new_key) if new_key == old_key => {}
+            Some(new_key) => { rekeyed.insert(path, (old_key, new_key)); }
+            None => return Err(AppError::InvalidMetadataEdit(format!(
+                "'{}' is obfuscated with a key the new identifier does not provide (Adobe obfuscation needs a UUID)",
+                path
+            ))),
+        }
+    }
+    Ok(rekeyed)
+}
+
+/// A file written next to its destination and renamed over it, so editing in
+/// place never leaves a half-written book behind. Until `persist` succeeds,
+/// dropping it removes the file.
+struct TempFile {
+    path: PathBuf,
+    persisted: bool,
+}
+
+impl TempFile {
+    fn next_to(dest: &Path) -> Result<Self> {
+        let file_name = dest.file_name().ok_or_else(|| AppError::InvalidSourcePath { path: dest.to_path_buf() })?;
+        let path = dest.with_file_name(format!(".{}.tmp", file_name.to_string_lossy()));
+        Ok(TempFile { path, persisted: false })
+    }
+
+    fn persist(mut self, dest: &Path) -> Result<()> {
+        fs::rename(&self.path, dest)?;
+        self.persisted = true;
+        Ok(())
+    }
+}
+
+impl Drop for TempFile {
+    fn drop(&mut self) {
+        if !self.persisted {
+            // The error that got us here is the one worth reporting.
+            let _ = fs::remove_file(&self.path);
+        }
+    }
+}
+
+/// Applies `edits` to the package document of an unpacked book.
+fn edit_unpacked_metadata(dir: &Path, dest: &Path, edits: &MetadataEdits) -> Result<()> {
+    if dest.canonicalize().ok() != Some(dir.canonicalize()?) {
+        return Err(AppError::InvalidMetadataEdit(format!(
+            "'{}' is an unpacked directory, which can only be edited in place",
+            dir.display()
+        )));
+    }
+    let opf_path = Epub::open(dir)?.opf_path().to_slash_lossy().into_owned();
+    let opf_file = dir.join(&opf_path);
+    let modified = chrono::Utc::now().format("%Y-%m-%dT%H:%M:%SZ").to_string();
+    let opf = rewrite_metadata(&fs::read_to_string(&opf_file)?, &opf_path, edits, &modified)?;
+
+    let temp = TempFile::next_to(&opf_file)?;
+    fs::write(&temp.path, opf)?;
+    temp.persist(&opf_file)
+}
+
+/// A direct child element of `<metadata>`, as a range of events.
+struct MetadataNode {
+    first: usize,
+    last: usize,
+    name: String,
+    id: Option<String>,
+    refines: Option<String>,
+    property: Option<String>,
+    text: String,
+}
+
+/// Rewrites the `<metadata>` of a package document at the event level,
+/// leaving everything it does not understand untouched. `modified` is the
+/// timestamp written to `dcterms:modified` in EPUB 3 packages.
+fn rewrite_metadata(opf: &str, opf_path: &str, edits: &MetadataEdits, modified: &str) -> Result<String> {
+    let mut reader = Reader::from_str(opf);
+    let mut events = Vec::new();
+    loop {
+        let event = reader.read_event().map_err(|e| AppError::XmlParsing { path: opf_path.into(), source: e.into() })?;
+        match event {
+            Event::Eof => break,
+            event => events.push(event.into_owned()),
+        }
+    }
+
+    let Some(metadata_start) = events.iter().position(|e| matches!(e, Event::Start(s) if s.local_name().as_ref() == b"metadata")) else {
+        return Err(AppError::InvalidEpubFormat("the package document has no <metadata>".to_string()));
+    };
+    let package = events.iter().find_map(|e| match e {
+        Event::Start(s) if s.local_name().as_ref() == b"package" => Some(s),
+        _ => None,
+    });
+    let unique_id = package.and_then(|p| attribute(p, "unique-identifier"));
+    let is_epub3 = package.and_then(|p| attribute(p, "version")).is_some_and(|v| v.starts_with('3'));
+    let has_opf_prefix = events[..=metadata_start].iter().any(|e| {
+        matches!(e, Event::Start(s) if s.attributes().flatten().any(|a| a.key.as_ref() == b"xmlns:opf"))
+    });
+
+    let nodes = collect_metadata_nodes(&events, metadata_start);
+    let find = |name: &'static str| nodes.iter().filter(move |n| n.name == name);
+    let matches_any = |node: &MetadataNode, values: &[String]| values.iter().any(|v| collapse_whitespace(v) == node.text);
+
+    let mut removed: HashSet<usize> = HashSet::new();
+    let mut replaced: Vec<(usize, String)> = Vec::new();
+    let mut appended: Vec<BytesStart<'static>> = Vec::new();
+    let mut appended_text: Vec<String> = Vec::new();
+    let mut append = |element: BytesStart<'static>, text: &str| {
+        appended.push(element);
+        appended_text.push(text.to_string());
+    };
+
+    let dc_prefix = nodes.iter()
+        .filter(|n| n.name != "meta" && n.name != "link")
+        .map(|n| element_name(&events[n.first]))
+        .find_map(|name| name.split_once(':').map(|(prefix, _)| prefix.to_string()))
+        .unwrap_or_else(|| "dc".to_string());
+    let dc = |local: &str| format!("{}:{}", dc_prefix, local);
+    let mut ids = IdAllocator::new(&events);
+
+    if let Some(title) = &edits.title {
+        match find("title").next() {
+            Some(node) => replaced.push((node.first, title.clone())),
+            None => append(BytesStart::new(dc("title")), title),
+        }
+    }
+
+    for (index, node) in nodes.iter().enumerate() {
+        let remove = match node.name.as_str() {
+            "creator" => !edits.authors.is_empty() || matches_any(node, &edits.remove_authors),
+            "language" => !edits.languages.is_empty() || matches_any(node, &edits.remove_languages),
+            "subject" => matches_any(node, &edits.remove_subjects),
+            "identifier" if matches_any(node, &edits.remove_identifiers) => {
+                if node.id.is_some() && node.id == unique_id {
+                    return Err(AppError::InvalidMetadataEdit(format!(
+                        "'{}' is the unique identifier of the book; use --identifier to change it",
+                        node.text
+                    )));
+                }
+                true
+            }
+            _ => false,
+        };
+        if remove {
+            removed.insert(index);
+        }
+    }
+
+    // Refinements of removed elements would otherwise dangle.
+    let removed_ids: HashSet<String> = removed.iter().filter_map(|&i| nodes[i].id.as_ref().map(|id| format!("#{}", id))).collect();
+    for (index, node) in nodes.iter().enumerate() {
+        if node.refines.as_ref().is_some_and(|r| removed_ids.contains(r)) {
+            removed.insert(index);
+        }
+    }
+
+    for author in &edits.authors {
+        let id = ids.next("creator");
+        if is_epub3 {
+            append(BytesStart::new(dc("creator")).with_attributes([("id", id.as_str())]), author);
+            let refines = format!("#{}", id);
+            append(
+                BytesStart::new("meta").with_attributes([("refines", refines.as_str()), ("property", "role"), ("scheme", "marc:relators")]),
+                "aut",
+            );
+        } else if has_opf_prefix {
+            append(BytesStart::new(dc("creator")).with_attributes([("opf:role", "aut")]), author);
+        } else {
+            append(BytesStart::new(dc("creator")), author);
+        }
+    }
+
+    for language in &edits.languages {
+        append(BytesStart::new(dc("language")), language);
+    }
+
+    if let Some(identifier) = &edits.identifier {
+        let node = find("identifier").find(|n| n.id.is_some() && n.id == unique_id);
+        match node {
+            Some(node) => replaced.push((node.first, identifier.clone())),
+            None => {
+                return Err(AppError::InvalidMetadataEdit(
+                    "the package has no identifier matching its unique-identifier attribute".to_string(),
+                ));
+            }
+        }
+    }
+
+    for subject in &edits.add_subjects {
+        let exists = find("subject").any(|n| n.text == collapse_whitespace(subject));
+        if !exists {
+            append(BytesStart::new(dc("subject")), subject);
+        }
+    }
+
+    if is_epub3 {
+        let node = find("meta").find(|n| n.refines.is_none() && n.property.as_deref() == Some("dcterms:modified"));
+        match node {
+            Some(node) => replaced.push((node.first, modified.to_string())),
+            None => append(BytesStart::new("meta").with_attributes([("property", "dcterms:modified")]), modified),
+        }
+    }
+
+    // Required elements must survive the edit.
+    for (name, flag) in [("title", "--title"), ("language", "--language"), ("identifier", "--identifier")] {
+        let kept = nodes.iter().enumerate().any(|(i, n)| n.name == name && !removed.contains(&i));
+        let added = appended.iter().any(|e| e.local_name().as_ref() == name.as_bytes());
+        if !kept && !added {
+            return Err(AppError::InvalidMetadataEdit(format!(
+                "the book would be left without a dc:{}; pass {} to set one",
+                name, flag
+            )));
+        }
+    }
+
+    // Reuse the indentation of the last metadata child for new elements.
+    let insert_at = nodes.last().map_or(metadata_start + 1, |n| n.last + 1);
+    let indent = nodes.last()
+        .and_then(|n| n.first.checked_sub(1))
+        .and_then(|i| whitespace_text(&events[i]))
+        .unwrap_or_default();
+
+    let skipped: HashSet<usize> = removed.iter()
+        .flat_map(|&i| {
+            let node = &nodes[i];
+            // Take the indentation in front of a removed element with it.
+            let leading = node.first.checked_sub(1).filter(|&p| whitespace_text(&events[p]).is_some());
+            leading.into_iter().chain(node.first..=node.last)
+        })
+        .collect();
+
+    let mut writer = Writer::new(Vec::new());
+    let mut index = 0;
+    while index < events.len() {
+        if index == insert_at {
+            for (element, text) in appended.iter().zip(&appended_text) {
+                writer.write_event(Event::Text(BytesText::from_escaped(indent.as_str())))?;
+                write_text_element(&mut writer, element.clone(), text)?;
+            }
+        }
+        if skipped.contains(&index) {
+            index += 1;
+            continue;
+        }
+        if let Some((_, text)) = replaced.iter().find(|(first, _)| *first == index) {
+            let node = nodes.iter().find(|n| n.first == index).expect("replaced nodes are metadata children");
+            let element = match &events[index] {
+                Event::Start(e) | Event::Empty(e) => e.clone(),
+                _ => unreachable!("metadata nodes start with an element"),
+            };
+            write_text_element(&mut writer, element, text)?;
+            index = node.last + 1;
+            continue;
+        }
+        writer.write_event(events[index].borrow())?;
+        index += 1;
+    }
+
+    Ok(String::from_utf8_lossy(&writer.into_inner()).into_owned())
+}
+
+/// Splits the direct children of the `<metadata>` starting at `start` into nodes.
+fn collect_metadata_nodes(events: &[Event<'static>], start: usize) -> Vec<MetadataNode> {
+    let mut nodes = Vec::new();
+    let mut depth = 0usize;
+    let mut current: Option<MetadataNode> = None;
+
+    for (index, event) in events.iter().enumerate().skip(start + 1) {
+        match event {
+            Event::Start(e) => {
+                if depth == 0 {
+                    current = Some(new_node(index, e));
+                }
+                depth += 1;
+            }
+            Event::Empty(e) if depth == 0 => nodes.push(new_node(index, e)),
+            Event::End(_) if depth == 0 => break,
+            Event::End(_) => {
+                depth -= 1;
+                if depth == 0
+                    && let Some(mut node) = current.take() {
+                    node.last = index;
+                    node.text = collapse_whitespace(&node.text);
+                    nodes.push(node);
+                }
+            }
+            Event::Text(e) => {
+                if let Some(node) = current.as_mut() {
+                    node.text.push_str(&text_content(e));
+                }
+            }
+            Event::CData(e) => {
+                if let Some(node) = current.as_mut() {
+                    node.text.push_str(&String::from_utf8_lossy(e));
+                }
+            }
+            _ => {}
+        }
+    }
+    nodes
+}
+
+fn new_node(index: usize, element: &BytesStart) -> MetadataNode {
+    MetadataNode {
+        first: index,
+        last: index,
+        name: String::from_utf8_lossy(element.local_name().as_ref()).into_owned(),
+        id: attribute(element, "id"),
+        refines: attribute(element, "refines"),
+        property: attribute(element, "property"),
+        text: String::new(),
+    }
+}
+
+fn write_text_element(writer: &mut Writer<Vec<u8>>, element: BytesStart<'static>, text: &str) -> Result<()> {
+    let end = BytesEnd::new(String::from_utf8_lossy(element.name().as_ref()).into_owned());
+    writer.write_event(Event::Start(element))?;
+    writer.write_event(Event::Text(BytesText::new(text)))?;
+    writer.write_event(Event::End(end))?;
+    Ok(())
+}
+
+fn element_name(event: &Event) -> String {
+    match event {
+        Event::Start(e) | Event::Empty(e) => String::from_utf8_lossy(e.name().as_ref()).into_owned(),
+        _ => String::new(),
+    }
+}
+
+fn whitespace_text(event: &Event) -> Option<String> {
+    match event {
+        Event::Text(e) if e.iter().all(u8::is_ascii_whitespace) => Some(String::from_utf8_lossy(e).into_owned()),
+        _ => None,
+    }
+}
+
+/// Hands out ids that are not yet used anywhere in the package document.
+struct IdAllocator {
+    used: HashSet<String>,
+}
+
+impl IdAllocator {
+    fn new(events: &[Event]) -> Self {
+        let used = events.iter()
+            .filter_map(|e| match e {
+                Event::Start(e) | Event::Empty(e) => attribute(e, "id"),
+                _ => None,
+            })
+            .collect();
+        Self { used }
+    }
+
+    fn next(&mut self, prefix: &str) -> String {
+        let id = (1..)
+            .map(|n| format!("{}{:02}", prefix, n))
+            .find(|id| !self.used.contains(id))
+            .expect("an unused id always exists");
+        self.used.insert(id.clone());
+        id
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::epub::packer;
+
+    const OPF: &str = r#"<?xml version="1.0" encoding="UTF-8"?>
+<package xmlns="http://www.idpf.org/2007/opf" unique-identifier="uid" version="3.0">
+  <metadata xmlns:dc="http://purl.org/dc/elements/1.1/">
+    <dc:identifier id="uid">urn:uuid:11111111-2222-3333-4444-555555555555</dc:identifier>
+    <dc:title>Fonts</dc:title>
+    <dc:language>en</dc:language>
+  </metadata>
+  <manifest>
+    <item id="font" href="fonts/body.ttf" media-type="font/ttf"/>
+  </manifest>
+  <spine/>
+</package>
+"#;
+
+    const CONTAINER: &str = r#"<?xml version="1.0"?>
+<container version="1.0" xmlns="urn:oasis:names:tc:opendocument:xmlns:container">
+  <rootfiles><rootfile full-path="OEBPS/content.opf" media-type="application/oebps-package+xml"/></rootfiles>
+</container>
+"#;
+
+    fn read_font(path: &Path) -> Vec<u8> {
+        let mut epub = Epub::open(path).unwrap();
+        let item = epub.manifest().items.iter().find(|item| item.id == "font").unwrap().clone();
+        let mut data = Vec::new();
+        epub.read_by_manifest_item(&item).unwrap().read_to_end(&mut data).unwrap();
+        data
+    }
+
+    #[test]
+    fn a_failed_write_leaves_no_temporary_file() {
+        let dir = std::env::temp_dir().join(format!("tome-editor-cleanup-test-{}", std::process::id()));
+        // Renaming a file over a directory fails after the book has been written.
+        let dest = dir.join("book.epub");
+        fs::create_dir_all(&dest).unwrap();
+
+        let source = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/epub-33.epub");
+        let edits = MetadataEdits { title: Some("Edited".to_string()), ..Default::default() };
+        assert!(edit_metadata(&source, &dest, &edits).is_err());
+        let entries: Vec<_> = fs::read_dir(&dir).unwrap().map(|entry| entry.unwrap().file_name()).collect();
+        assert_eq!(entries, ["book.epub"]);
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn changing_the_identifier_obfuscates_fonts_with_the_new_key() {
+        let dir = std::env::temp_dir().join(format!("tome-editor-test-{}", std::process::id()));
+        let book = dir.join("book");
+        fs::create_dir_all(book.join("META-INF")).unwrap();
+        fs::create_dir_all(book.join("OEBPS/fonts")).unwrap();
+        fs::write(book.join("META-INF/container.xml"), CONTAINER).unwrap();
+        fs::write(book.join("OEBPS/content.opf"), OPF).unwrap();
+        let font: Vec<u8> = (0..3000).map(|i| (i % 256) as u8).collect();
+        fs::write(book.join("OEBPS/fonts/body.ttf"), &font).unwrap();
+
+        let packed = dir.join("packed.epub");
+        let edited = dir.join("edited.epub");
+        assert_eq!(packer::pack_directory(&book, &packed, true).unwrap(), 1);
+        assert_eq!(read_font(&packed), font);
+
+        let edits = MetadataEdits { identifier: Some("urn:isbn:9780000000002".to_string()), ..Default::default() };
+        edit_metadata(&packed, &edited, &edits).unwrap();
+        let identifiers: Vec<String> = Epub::open(&edited).unwrap().metadata().identifier.iter().map(|i| i.value.clone()).collect();
+        assert_eq!(identifiers, ["urn:isbn:9780000000002"]);
+        assert_eq!(read_font(&edited), font);
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,122 @@
+// src/epub/layout.rs
+use std::collections::HashSet;
+use std::path::{Component, Path, PathBuf};
+
+use path_clean::PathClean;
+use path_slash::PathBufExt;
+use sha1::{Digest, Sha1};
+
+/// How the files of a book are arranged in the generated site.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum SiteLayout {
+    /// `chapters/<name>.html` and `assets/<name>`; names that clash get a numeric suffix.
+    #[default]
+    Flat,
+    /// Keeps the book's directory structure below `chapters/` and `assets/`.
+    Mirror,
+    /// Appends a short hash of the source path to every file name.
+    Hashed,
+}
+
+/// How the content documents are turned into pages.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum PageMode {
+    /// A page per spine item, below `chapters/`, with `index.html` as the table of contents.
+    #[default]
+    Chapters,
+    /// The whole book in `index.html`.
+    Single,
+    /// The whole book in `index.html`, with every asset of up to `inline_limit`
+    /// bytes embedded in it, so the page needs no other files.
+    SelfContained { inline_limit: u64 },
+}
+
+/// Hands out output paths for manifest items, making sure no two items
+/// (nor the generator's own files) end up at the same place.
+pub(super) struct PathPlanner {
+    layout: SiteLayout,
+    assets_dir: String,
+    /// Paths already handed out, lowercased so that case-insensitive file systems are safe too.
+    taken: HashSet<String>,
+}
+
+impl PathPlanner {
+    /// `reserved` lists site paths the generator writes itself, such as `assets/styles.css`.
+    pub(super) fn new(layout: SiteLayout, reserved: &[&str]) -> Self {
+        Self {
+            layout,
+            assets_dir: "assets".to_string(),
+            taken: reserved.iter().map(|path| path.to_lowercase()).collect(),
+        }
+    }
+
+    /// Puts everything but content documents below `dir` instead of `assets/`.
+    pub(super) fn with_assets_dir(mut self, dir: &str) -> Self {
+        self.assets_dir = dir.to_string();
+        self
+    }
+
+    /// Chooses the site path for the item at `href` (relative to the OPF file).
+    /// Content documents go below `chapters/` with an `.html` extension, everything
+    /// else below the assets directory. The flag is set when the natural name was
+    /// already taken.
+    pub(super) fn assign(&mut self, href: &str, is_document: bool) -> (PathBuf, bool) {
+        let source = Self::relative_source_path(href);
+        let file_name = source.file_name().map(|n| n.to_string_lossy().into_owned()).unwrap_or_else(|| "unknown".to_string());
+        let (mut stem, mut extension) = split_file_name(&file_name);
+        if is_document {
+            extension = Some("html".to_string());
+        }
+        if self.layout == SiteLayout::Hashed {
+            stem = format!("{}-{}", stem, short_hash(&source.to_slash_lossy()));
+        }
+
+        let mut dir = PathBuf::from(if is_document { "chapters" } else { &self.assets_dir });
+        if self.layout == SiteLayout::Mirror
+            && let Some(parent) = source.parent() {
+            dir.push(parent);
+        }
+
+        let build = |suffix: Option<usize>| {
+            let name = match (suffix, &extension) {
+                (None, Some(ext)) => format!("{}.{}", stem, ext),
+                (None, None) => stem.clone(),
+                (Some(n), Some(ext)) => format!("{}-{}.{}", stem, n, ext),
+                (Some(n), None) => format!("{}-{}", stem, n),
+            };
+            dir.join(name)
+        };
+
+        let mut candidate = build(None);
+        let mut renamed = false;
+        let mut counter = 2;
+        while !self.taken.insert(candidate.to_slash_lossy().to_lowercase()) {
+            candidate = build(Some(counter));
+            renamed = true;
+            counter += 1;
+        }
+        (candidate, renamed)
+    }
+
+    /// The cleaned source path, with any `..` or root components dropped so
+    /// that the output can never escape the site directory.
+    fn relative_source_path(href: &str) -> PathBuf {
+        Path::new(href)
+            .clean()
+            .components()
+            .filter(|component| matches!(component, Component::Normal(_)))
+            .collect()
+    }
+}
+
+fn split_file_name(file_name: &str) -> (String, Option<String>) {
+    match file_name.rsplit_once('.') {
+        Some((stem, ext)) if !stem.is_empty() => (stem.to_string(), Some(ext.to_string())),
+        _ => (file_name.to_string(), None),
+    }
+}
+
+/// The first eight hex digits of the SHA-1 of `text`.
+fn short_hash(text: &str) -> String {
+    Sha1::digest(text.as_bytes()).iter().take(4).map(|b| format!("{:02x}", b)).collect()
+}
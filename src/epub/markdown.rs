@@ -0,0 +1,369 @@
+// src/epub/markdown.rs
+use crate::epub::converter::{DocumentLinks, LinkTarget};
+use crate::epub::xhtml::{collapse_ascii_whitespace, indent, Element, Indent, Node, BLOCK_ELEMENTS, HIDDEN_ELEMENTS};
+
+/// Renders the content of `body` as CommonMark, with the GitHub extensions
+/// for tables, strikethrough and footnotes. Note references whose note is
+/// part of the same output become footnotes; the notes themselves are
+/// collected at the end.
+pub(super) fn render(body: &Element, links: &DocumentLinks) -> String {
+    let mut renderer = Renderer { links, footnotes: Vec::new() };
+    let mut blocks = renderer.blocks(body);
+    for (label, content) in std::mem::take(&mut renderer.footnotes) {
+        blocks.push(format!("[^{}]: {}", label, indent(&content, "    ", Indent::Continuation)));
+    }
+    blocks.join("\n\n")
+}
+
+struct Renderer<'a> {
+    links: &'a DocumentLinks<'a>,
+    /// Footnote definitions found so far: label and rendered content.
+    footnotes: Vec<(String, String)>,
+}
+
+impl Renderer<'_> {
+    /// Renders the children of `container` as a list of blocks. Runs of
+    /// inline content between block elements become paragraphs.
+    fn blocks(&mut self, container: &Element) -> Vec<String> {
+        let mut blocks = Vec::new();
+        let mut paragraph = String::new();
+        for child in &container.children {
+            match child {
+                Node::Text(text) => paragraph.push_str(&escape(text)),
+                Node::Element(element) if HIDDEN_ELEMENTS.contains(&element.name.as_str()) => {}
+                Node::Element(element) => {
+                    if self.footnote(element) {
+                        continue;
+                    } else if BLOCK_ELEMENTS.contains(&element.name.as_str()) {
+                        flush_paragraph(&mut paragraph, &mut blocks);
+                        self.block(element, &mut blocks);
+                    } else {
+                        paragraph.push_str(&self.inline_element(element));
+                    }
+                }
+            }
+        }
+        flush_paragraph(&mut paragraph, &mut blocks);
+        blocks
+    }
+
+    fn block(&mut self, element: &Element, blocks: &mut Vec<String>) {
+        let anchor = self.anchor(element);
+        match element.name.as_str() {
+            heading @ ("h1" | "h2" | "h3" | "h4" | "h5" | "h6") => {
+                let level = heading[1..].parse().unwrap_or(1);
+                let text = collapse_ascii_whitespace(&self.inline(element));
+                if !text.is_empty() || !anchor.is_empty() {
+                    blocks.push(format!("{} {}{}", "#".repeat(level), anchor, text));
+                }
+            }
+            "p" | "dt" => {
+                let mut content = self.blocks(element);
+                if element.name == "dt"
+                    && let Some(first) = content.first_mut() {
+                    *first = format!("**{}**", first);
+                }
+                prepend_anchor(anchor, &mut content);
+                blocks.extend(content);
+            }
+            "ul" | "ol" => {
+                push_anchor(anchor, blocks);
+                let list = self.list(element);
+                if !list.is_empty() {
+                    blocks.push(list);
+                }
+            }
+            "blockquote" => {
+                push_anchor(anchor, blocks);
+                let content = self.blocks(element).join("\n\n");
+                if !content.is_empty() {
+                    blocks.push(indent(&content, "> ", Indent::All).lines().map(str::trim_end).collect::<Vec<_>>().join("\n"));
+                }
+            }
+            "pre" => {
+                push_anchor(anchor, blocks);
+                let code = element.text();
+                let code = code.strip_prefix('\n').unwrap_or(&code).trim_end();
+                let fence = "`".repeat(longest_run(code, '`').max(2) + 1);
+                blocks.push(format!("{}\n{}\n{}", fence, code, fence));
+            }
+            "table" => {
+                push_anchor(anchor, blocks);
+                if let Some(table) = self.table(element) {
+                    blocks.push(table);
+                }
+            }
+            "hr" => blocks.push("---".to_string()),
+            _ => {
+                push_anchor(anchor, blocks);
+                blocks.extend(self.blocks(element));
+            }
+        }
+    }
+
+    /// Renders a list, indenting the content of each item under its marker.
+    fn list(&mut self, list: &Element) -> String {
+        let ordered = list.name == "ol";
+        let mut number: usize = list.attribute("start").and_then(|start| start.trim().parse().ok()).unwrap_or(1);
+        let mut items = Vec::new();
+        for item in list.elements().filter(|element| element.name == "li") {
+            // Endnotes are often the items of a list.
+            if self.footnote(item) {
+                continue;
+            }
+            let marker = if ordered { format!("{}. ", number) } else { "- ".to_string() };
+            number += 1;
+            let mut content = self.blocks(item);
+            prepend_anchor(self.anchor(item), &mut content);
+            // Keep nested lists tight; separate anything else with a blank line.
+            let mut text = String::new();
+            for block in content {
+                if !text.is_empty() {
+                    text.push_str(if is_list(&block) { "\n" } else { "\n\n" });
+                }
+                text.push_str(&block);
+            }
+            let padding = " ".repeat(marker.len());
+            items.push(format!("{}{}", marker, indent(&text, &padding, Indent::Continuation)).trim_end().to_string());
+        }
+        items.join("\n")
+    }
+
+    /// Collects `element` as a footnote definition if note references point at it.
+    fn footnote(&mut self, element: &Element) -> bool {
+        let Some(label) = element.attribute("id").and_then(|id| self.links.footnote(id)) else {
+            return false;
+        };
+        let label = label.to_string();
+        let content = self.blocks(element).join("\n\n");
+        self.footnotes.push((label, content));
+        true
+    }
+
+    /// Renders a table as a GitHub table, taking its first row as the header.
+    fn table(&mut self, table: &Element) -> Option<String> {
+        let rows: Vec<Vec<String>> = table.rows().into_iter()
+            .map(|row| {
+                row.elements()
+                    .filter(|cell| cell.name == "td" || cell.name == "th")
+                    .map(|cell| collapse_ascii_whitespace(&self.inline(cell)).replace('|', "\\|"))
+                    .collect()
+            })
+            .collect();
+        let columns = rows.iter().map(Vec::len).max().filter(|&columns| columns > 0)?;
+
+        let line = |cells: &[String]| {
+            let padded: Vec<&str> = (0..columns).map(|i| cells.get(i).map_or("", String::as_str)).collect();
+            format!("| {} |", padded.join(" | "))
+        };
+        let mut lines = vec![line(&rows[0]), format!("|{}", " --- |".repeat(columns))];
+        lines.extend(rows[1..].iter().map(|row| line(row)));
+        Some(lines.join("\n"))
+    }
+
+    /// Renders the children of `element` as inline content.
+    fn inline(&mut self, element: &Element) -> String {
+        let mut text = String::new();
+        for child in &element.children {
+            match child {
+                Node::Text(content) => text.push_str(&escape(content)),
+                Node::Element(child) if HIDDEN_ELEMENTS.contains(&child.name.as_str()) => {}
+                Node::Element(child) => text.push_str(&self.inline_element(child)),
+            }
+        }
+        text
+    }
+
+    fn inline_element(&mut self, element: &Element) -> String {
+        let anchor = self.anchor(element);
+        let content = match element.name.as_str() {
+            "em" | "i" | "cite" | "dfn" | "var" => wrap(&self.inline(element), "*"),
+            "strong" | "b" => wrap(&self.inline(element), "**"),
+            "del" | "s" | "strike" => wrap(&self.inline(element), "~~"),
+            "code" | "kbd" | "samp" | "tt" => code_span(&element.text()),
+            // A raw newline; `normalize` turns it into a hard line break.
+            "br" => "\n".to_string(),
+            "img" => self.image(element.attribute("src"), "src", element.attribute("alt").unwrap_or("")),
+            "image" => self.image(element.attribute("xlink:href").or_else(|| element.attribute("href")), "xlink:href", ""),
+            "a" => self.link(element),
+            // Only the images of an SVG drawing carry over.
+            "svg" => {
+                let mut images = Vec::new();
+                element.walk(&mut |child| if child.name == "image" { images.push(child) });
+                images.into_iter()
+                    .map(|image| self.image(image.attribute("xlink:href").or_else(|| image.attribute("href")), "xlink:href", ""))
+                    .collect()
+            }
+            _ => self.inline(element),
+        };
+        format!("{}{}", anchor, content)
+    }
+
+    fn link(&mut self, element: &Element) -> String {
+        // Links back from a note to its reference make no sense once it is a footnote.
+        if element.has_semantic("backlink") {
+            return String::new();
+        }
+        let text = self.inline(element);
+        let target = element.attribute("href").and_then(|href| self.links.link(href, "href", element.has_semantic("noteref")));
+        match target {
+            Some(LinkTarget::Footnote(label)) => format!("[^{}]", label),
+            Some(LinkTarget::Url(url)) if !normalize(&text).is_empty() => format!("[{}]({})", text.trim(), destination(&url)),
+            _ => text,
+        }
+    }
+
+    /// An image whose `src` was found in `attribute`.
+    fn image(&mut self, src: Option<&str>, attribute: &str, alt: &str) -> String {
+        let alt = escape(&collapse_ascii_whitespace(alt));
+        match src.and_then(|src| self.links.link(src, attribute, false)) {
+            Some(LinkTarget::Url(url)) => format!("![{}]({})", alt, destination(&url)),
+            _ => alt,
+        }
+    }
+
+    /// An empty HTML anchor for elements that links point at.
+    fn anchor(&self, element: &Element) -> String {
+        element.attribute("id")
+            .and_then(|id| self.links.anchor(id))
+            .map(|anchor| format!("<a id=\"{}\"></a>", anchor))
+            .unwrap_or_default()
+    }
+}
+
+fn flush_paragraph(paragraph: &mut String, blocks: &mut Vec<String>) {
+    let text = normalize(paragraph);
+    paragraph.clear();
+    if text.is_empty() {
+        return;
+    }
+    // Text that happens to look like block syntax must not turn into it.
+    let mut chars = text.char_indices().skip_while(|(_, c)| c.is_ascii_digit());
+    let text = match (text.chars().next(), chars.next()) {
+        (Some('#' | '>' | '-' | '+' | '='), _) => format!("\\{}", text),
+        (Some(c), Some((i, '.' | ')'))) if c.is_ascii_digit() => format!("{}\\{}", &text[..i], &text[i..]),
+        _ => text,
+    };
+    blocks.push(text);
+}
+
+fn prepend_anchor(anchor: String, blocks: &mut Vec<String>) {
+    match blocks.first_mut() {
+        Some(first) => first.insert_str(0, &anchor),
+        None if !anchor.is_empty() => blocks.push(anchor),
+        None => {}
+    }
+}
+
+fn push_anchor(anchor: String, blocks: &mut Vec<String>) {
+    if !anchor.is_empty() {
+        blocks.push(anchor);
+    }
+}
+
+/// Collapses spaces within each line and drops blank lines; the line breaks
+/// left by `<br>` become hard breaks.
+fn normalize(text: &str) -> String {
+    text.split('\n')
+        .map(collapse_ascii_whitespace)
+        .filter(|line| !line.is_empty())
+        .collect::<Vec<_>>()
+        .join("\\\n")
+}
+
+/// Escapes the characters that would otherwise be taken as inline syntax.
+/// Whitespace, line breaks included, becomes a single space.
+fn escape(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    let mut last_was_space = false;
+    for c in text.chars() {
+        if c.is_ascii_whitespace() {
+            if !last_was_space {
+                escaped.push(' ');
+            }
+            last_was_space = true;
+            continue;
+        }
+        last_was_space = false;
+        if matches!(c, '\\' | '`' | '*' | '_' | '[' | ']' | '<' | '~') {
+            escaped.push('\\');
+        }
+        escaped.push(c);
+    }
+    escaped
+}
+
+/// Wraps inline content in emphasis markers, which must hug the text.
+fn wrap(content: &str, marker: &str) -> String {
+    let trimmed = content.trim();
+    if trimmed.is_empty() {
+        return content.to_string();
+    }
+    let leading = if content.starts_with(char::is_whitespace) { " " } else { "" };
+    let trailing = if content.ends_with(char::is_whitespace) { " " } else { "" };
+    format!("{}{}{}{}{}", leading, marker, trimmed, marker, trailing)
+}
+
+fn code_span(code: &str) -> String {
+    let code = collapse_ascii_whitespace(code);
+    if code.is_empty() {
+        return String::new();
+    }
+    let fence = "`".repeat(longest_run(&code, '`') + 1);
+    let padding = if code.starts_with('`') || code.ends_with('`') { " " } else { "" };
+    format!("{}{}{}{}{}", fence, padding, code, padding, fence)
+}
+
+/// Writes a link destination, using the pointy-bracket form when it contains spaces or parentheses.
+fn destination(url: &str) -> String {
+    if url.contains([' ', '(', ')']) {
+        format!("<{}>", url.replace('<', "%3C").replace('>', "%3E"))
+    } else {
+        url.to_string()
+    }
+}
+
+fn longest_run(text: &str, c: char) -> usize {
+    text.split(|other| other != c).map(str::len).max().unwrap_or(0)
+}
+
+/// Whether `block` is a list that may directly follow a line of text; only
+/// ordered lists starting at 1 are allowed to interrupt a paragraph.
+fn is_list(block: &str) -> bool {
+    block.starts_with("- ") || block.starts_with("1. ")
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::epub::converter::{render_documents, ExportFormat};
+
+    fn markdown(body: &str) -> String {
+        let content = format!("<html><body>{}</body></html>", body);
+        render_documents(ExportFormat::Markdown, &[("chapter.xhtml", &content)], false).remove(0)
+    }
+
+    #[test]
+    fn note_references_become_footnotes() {
+        let body = r##"<p>Text<a epub:type="noteref" href="#n1">1</a>.</p>
+            <aside epub:type="footnote" id="n1"><p>The note.</p></aside>"##;
+        assert_eq!(markdown(body), "Text[^n1].\n\n[^n1]: The note.");
+    }
+
+    #[test]
+    fn linked_headings_get_an_anchor() {
+        let body = r##"<h2 id="start">The <em>Start</em></h2><h2 id="other">Other</h2><p><a href="#start">Back</a></p>"##;
+        assert_eq!(markdown(body), "## <a id=\"start\"></a>The *Start*\n\n## Other\n\n[Back](#start)");
+    }
+
+    #[test]
+    fn text_that_looks_like_a_list_is_escaped() {
+        assert_eq!(markdown("<p>1986. A year</p>"), "1986\\. A year");
+        assert_eq!(markdown("<p>- not a list</p>"), "\\- not a list");
+    }
+
+    #[test]
+    fn nested_lists_are_indented_under_their_item() {
+        let body = "<ol><li>One<ul><li>Sub</li><li>Sub <b>two</b></li></ul></li><li><p>Two</p><p>More</p></li></ol>";
+        assert_eq!(markdown(body), "1. One\n   - Sub\n   - Sub **two**\n2. Two\n\n   More");
+    }
+}
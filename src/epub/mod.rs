@@ -1,6 +1,24 @@
-pub use lib::Epub;
+pub use book::Epub;
+pub use storage::{EntryReader, ReadSeek};
+pub use obfuscation::ObfuscationReader;
+mod css;
+pub mod converter;
+pub mod diagnostics;
+pub mod editor;
+pub mod layout;
+mod markdown;
 pub mod model;
+mod obfuscation;
+pub mod packer;
+mod path_resolver;
+pub mod progress;
 mod rewriter;
 pub mod site_generator;
+mod storage;
+mod templates;
+mod text;
+mod toc;
+pub mod validator;
+mod xhtml;
 
-mod lib;
\ No newline at end of file
+mod book;
\ No newline at end of file
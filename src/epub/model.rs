@@ -1,5 +1,5 @@
 // epub/model.rs
-use serde::Deserialize;
+use serde::{Deserialize, Serialize};
 
 // Corresponds to the top-level <container> tag in container.xml
 #[derive(Debug, Deserialize)]
@@ -13,7 +13,7 @@ pub struct Container {
 pub struct Rootfiles {
     // `#[serde(rename = "rootfile")]` tells Serde to collect all
     // "rootfile" child tags into this Vec.
-    #[serde(rename = "rootfile")]
+    #[serde(rename = "rootfile", default)]
     pub(crate) rootfile: Vec<Rootfile>,
 }
 
@@ -24,44 +24,195 @@ pub struct Rootfile {
     #[serde(rename = "@full-path")]
     pub(crate) full_path: String,
     #[serde(rename = "@media-type")]
-    pub(crate) _media_type: String,
+    pub(crate) media_type: String,
 }
 
 #[derive(Debug, Deserialize)]
 pub struct Package {
+    // The id of the dc:identifier that identifies the book.
+    #[serde(rename = "@unique-identifier", default)]
+    pub unique_identifier_id: Option<String>,
     pub metadata: Metadata,
     pub manifest: Manifest,
     pub spine: Spine,
+    // EPUB 2 only; deprecated in EPUB 3 but still found in many books.
+    #[serde(default)]
+    pub guide: Guide,
 }
 
-#[derive(Debug, Deserialize)]
+impl Package {
+    /// The value of the identifier named by `unique-identifier`.
+    pub fn unique_identifier(&self) -> Option<&str> {
+        let id = self.unique_identifier_id.as_deref()?;
+        self.metadata.identifier.iter()
+            .find(|identifier| identifier.id.as_deref() == Some(id))
+            .map(|identifier| identifier.value.trim())
+    }
+}
+
+#[derive(Debug, Deserialize, Serialize)]
 pub struct Metadata {
     #[serde(rename = "creator", default)]
     pub creator: Vec<Creator>,
 
+    #[serde(rename = "contributor", default)]
+    pub contributor: Vec<Creator>,
+
     #[serde(rename = "title", default)]
-    pub title: Vec<String>,
+    pub title: Vec<DcElement>,
 
     #[serde(rename = "language", default)]
     pub language: Vec<String>,
 
     #[serde(rename = "identifier", default)]
     pub identifier: Vec<Identifier>,
+
+    #[serde(rename = "publisher", default)]
+    pub publisher: Vec<DcElement>,
+
+    #[serde(rename = "date", default)]
+    pub date: Vec<Date>,
+
+    #[serde(rename = "description", default)]
+    pub description: Vec<DcElement>,
+
+    #[serde(rename = "subject", default)]
+    pub subject: Vec<DcElement>,
+
+    #[serde(rename = "rights", default)]
+    pub rights: Vec<DcElement>,
+
+    #[serde(rename = "source", default)]
+    pub source: Vec<DcElement>,
+
+    #[serde(rename = "type", default)]
+    pub r#type: Vec<DcElement>,
+
+    #[serde(rename = "coverage", default)]
+    pub coverage: Vec<DcElement>,
+
+    #[serde(rename = "relation", default)]
+    pub relation: Vec<DcElement>,
+
+    // Both EPUB 2 `<meta name content>` and EPUB 3 `<meta property>` elements.
+    #[serde(rename = "meta", default)]
+    pub meta: Vec<Meta>,
 }
 
-#[derive(Debug, Deserialize)]
+impl Metadata {
+    /// The main title: the one refined with `title-type` "main", or the first one.
+    pub fn title(&self) -> Option<&str> {
+        self.title.iter()
+            .find(|t| self.refinement(t.id.as_deref(), "title-type") == Some("main"))
+            .or_else(|| self.title.first())
+            .map(|t| t.value.as_str())
+    }
+
+    /// The `content` of an EPUB 2 `<meta name="...">` element.
+    pub fn named_meta(&self, name: &str) -> Option<&str> {
+        self.meta.iter()
+            .find(|m| m.name.as_deref() == Some(name))
+            .and_then(|m| m.content.as_deref())
+    }
+
+    /// All `<meta>` elements that refine the element with the given id.
+    pub fn refinements<'a>(&'a self, id: &str) -> impl Iterator<Item = &'a Meta> {
+        let target = format!("#{}", id);
+        self.meta.iter().filter(move |m| m.refines.as_deref() == Some(target.as_str()))
+    }
+
+    /// The value of the first refinement with `property` for the element with id `id`.
+    pub fn refinement<'a>(&'a self, id: Option<&str>, property: &str) -> Option<&'a str> {
+        let id = id?;
+        self.refinements(id)
+            .find(|m| m.property.as_deref() == Some(property))
+            .map(|m| m.value.as_str())
+    }
+
+    /// The role of a creator or contributor (e.g. "aut"), from `opf:role` or an EPUB 3 refinement.
+    pub fn role_of<'a>(&'a self, creator: &'a Creator) -> Option<&'a str> {
+        creator.role.as_deref().or_else(|| self.refinement(creator.id.as_deref(), "role"))
+    }
+
+    /// The sort name of a creator or contributor, from `opf:file-as` or an EPUB 3 refinement.
+    pub fn file_as_of<'a>(&'a self, creator: &'a Creator) -> Option<&'a str> {
+        creator.file_as.as_deref().or_else(|| self.refinement(creator.id.as_deref(), "file-as"))
+    }
+
+    /// The EPUB 3 last-modification date (`dcterms:modified`).
+    pub fn modified(&self) -> Option<&str> {
+        self.meta.iter()
+            .find(|m| m.refines.is_none() && m.property.as_deref() == Some("dcterms:modified"))
+            .map(|m| m.value.as_str())
+    }
+
+    /// The series or sets the book belongs to (`belongs-to-collection`).
+    pub fn collections(&self) -> impl Iterator<Item = &Meta> {
+        self.meta.iter().filter(|m| m.refines.is_none() && m.property.as_deref() == Some("belongs-to-collection"))
+    }
+}
+
+/// A Dublin Core element whose only structure is its text and an optional id
+/// that EPUB 3 `<meta refines>` elements can point at.
+#[derive(Debug, Deserialize, Serialize)]
+pub struct DcElement {
+    #[serde(rename(deserialize = "@id", serialize = "id"), default)]
+    pub id: Option<String>,
+    #[serde(rename(deserialize = "$text", serialize = "value"), default)]
+    pub value: String,
+}
+
+#[derive(Debug, Deserialize, Serialize)]
 pub struct Creator {
-    #[serde(rename = "@id", default)]
-    pub(crate) _id: Option<String>,
-    #[serde(rename = "$text")]
+    #[serde(rename(deserialize = "@id", serialize = "id"), default)]
+    pub id: Option<String>,
+    // EPUB 2 `opf:role`; EPUB 3 uses a `role` refinement instead.
+    #[serde(rename(deserialize = "@role", serialize = "role"), default)]
+    pub role: Option<String>,
+    // EPUB 2 `opf:file-as`; EPUB 3 uses a `file-as` refinement instead.
+    #[serde(rename(deserialize = "@file-as", serialize = "file_as"), default)]
+    pub file_as: Option<String>,
+    #[serde(rename(deserialize = "$text", serialize = "name"), default)]
     pub name: String,
 }
 
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Deserialize, Serialize)]
 pub struct Identifier {
-    #[serde(rename = "@id", default)]
-    pub(crate) _id: Option<String>,
-    #[serde(rename = "$text")]
+    #[serde(rename(deserialize = "@id", serialize = "id"), default)]
+    pub id: Option<String>,
+    // EPUB 2 `opf:scheme`, e.g. "ISBN" or "UUID".
+    #[serde(rename(deserialize = "@scheme", serialize = "scheme"), default)]
+    pub scheme: Option<String>,
+    #[serde(rename(deserialize = "$text", serialize = "value"), default)]
+    pub value: String,
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+pub struct Date {
+    // EPUB 2 `opf:event`, e.g. "publication" or "modification".
+    #[serde(rename(deserialize = "@event", serialize = "event"), default)]
+    pub event: Option<String>,
+    #[serde(rename(deserialize = "$text", serialize = "value"), default)]
+    pub value: String,
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+pub struct Meta {
+    #[serde(rename(deserialize = "@id", serialize = "id"), default)]
+    pub id: Option<String>,
+    // EPUB 2 form: `<meta name="cover" content="cover-image"/>`.
+    #[serde(rename(deserialize = "@name", serialize = "name"), default)]
+    pub name: Option<String>,
+    #[serde(rename(deserialize = "@content", serialize = "content"), default)]
+    pub content: Option<String>,
+    // EPUB 3 form: `<meta property="role" refines="#creator" scheme="marc:relators">aut</meta>`.
+    #[serde(rename(deserialize = "@property", serialize = "property"), default)]
+    pub property: Option<String>,
+    #[serde(rename(deserialize = "@refines", serialize = "refines"), default)]
+    pub refines: Option<String>,
+    #[serde(rename(deserialize = "@scheme", serialize = "scheme"), default)]
+    pub scheme: Option<String>,
+    #[serde(rename(deserialize = "$text", serialize = "value"), default)]
     pub value: String,
 }
 
@@ -71,6 +222,20 @@ pub struct Manifest {
     pub items: Vec<ManifestItem>,
 }
 
+impl Manifest {
+    /// Finds an item by its manifest id.
+    pub fn item(&self, id: &str) -> Option<&ManifestItem> {
+        self.items.iter().find(|item| item.id == id)
+    }
+
+    /// Finds the item a spine `<itemref>` points at. Ids are case-sensitive,
+    /// but sloppy books get them wrong, so a match ignoring case is accepted
+    /// when there is no exact one.
+    pub fn spine_item(&self, idref: &str) -> Option<&ManifestItem> {
+        self.item(idref).or_else(|| self.items.iter().find(|item| item.id.eq_ignore_ascii_case(idref)))
+    }
+}
+
 #[derive(Debug, Deserialize, Clone)]
 pub struct ManifestItem {
     #[serde(rename = "@id")]
@@ -79,16 +244,128 @@ pub struct ManifestItem {
     pub href: String,
     #[serde(rename = "@media-type")]
     pub media_type: String,
+    // Space-separated list, e.g. "nav" or "cover-image scripted".
+    #[serde(rename = "@properties", default)]
+    pub properties: Option<String>,
+}
+
+impl ManifestItem {
+    /// Checks whether the item declares the given EPUB 3 property.
+    pub fn has_property(&self, property: &str) -> bool {
+        self.properties
+            .as_deref()
+            .is_some_and(|props| props.split_whitespace().any(|p| p == property))
+    }
+
+    pub fn is_image(&self) -> bool {
+        self.media_type.starts_with("image/")
+    }
+
+    /// Covers the EPUB 3 `font/*` types as well as the older ones still used by EPUB 2 books.
+    pub fn is_font(&self) -> bool {
+        self.media_type.starts_with("font/")
+            || self.media_type.starts_with("application/font-")
+            || self.media_type.starts_with("application/x-font-")
+            || self.media_type == "application/vnd.ms-opentype"
+    }
 }
 
 #[derive(Debug, Deserialize)]
 pub struct Spine {
+    // EPUB 2: the manifest id of the NCX document.
+    #[serde(rename = "@toc", default)]
+    pub toc: Option<String>,
+    // "ltr", "rtl" or "default".
+    #[serde(rename = "@page-progression-direction", default)]
+    pub page_progression_direction: Option<String>,
     #[serde(rename = "itemref", default)]
     pub item_refs: Vec<ItemRef>,
 }
 
-#[derive(Debug, Deserialize)]
+impl Spine {
+    /// Whether pages progress from right to left, as in Arabic, Hebrew or vertical CJK books.
+    pub fn is_rtl(&self) -> bool {
+        self.page_progression_direction.as_deref() == Some("rtl")
+    }
+}
+
+#[derive(Debug, Deserialize, Serialize)]
 pub struct ItemRef {
-    #[serde(rename = "@idref")]
+    #[serde(rename(deserialize = "@idref", serialize = "idref"))]
     pub idref: String,
-}
\ No newline at end of file
+    // "no" marks auxiliary content (footnotes, answer keys) outside the reading order.
+    #[serde(rename(deserialize = "@linear", serialize = "linear"), default)]
+    pub linear: Option<String>,
+    // Space-separated list, e.g. "page-spread-left".
+    #[serde(rename(deserialize = "@properties", serialize = "properties"), default)]
+    pub properties: Option<String>,
+}
+
+impl ItemRef {
+    /// Whether the item is part of the default reading order.
+    pub fn is_linear(&self) -> bool {
+        self.linear.as_deref() != Some("no")
+    }
+}
+
+#[derive(Debug, Deserialize, Default)]
+pub struct Guide {
+    #[serde(rename = "reference", default)]
+    pub references: Vec<GuideReference>,
+}
+
+// Corresponds to a <reference type="cover" href="cover.xhtml"/> tag
+#[derive(Debug, Deserialize)]
+pub struct GuideReference {
+    #[serde(rename = "@type")]
+    pub r#type: String,
+    #[serde(rename = "@href")]
+    pub href: String,
+}
+
+// Corresponds to the top-level <encryption> tag in META-INF/encryption.xml
+#[derive(Debug, Deserialize, Default)]
+pub struct Encryption {
+    #[serde(rename = "EncryptedData", default)]
+    pub encrypted_data: Vec<EncryptedData>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct EncryptedData {
+    #[serde(rename = "EncryptionMethod")]
+    pub encryption_method: EncryptionMethod,
+    #[serde(rename = "CipherData")]
+    pub cipher_data: CipherData,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct EncryptionMethod {
+    #[serde(rename = "@Algorithm")]
+    pub algorithm: String,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct CipherData {
+    #[serde(rename = "CipherReference")]
+    pub cipher_reference: CipherReference,
+}
+
+// The URI is relative to the root of the container, e.g. "OEBPS/fonts/a.otf".
+#[derive(Debug, Deserialize)]
+pub struct CipherReference {
+    #[serde(rename = "@URI")]
+    pub uri: String,
+}
+
+/// A node in the book's table of contents, built from the EPUB 3 navigation
+/// document or the EPUB 2 NCX.
+#[derive(Debug, Clone, Default)]
+pub struct TocEntry {
+    pub label: String,
+    /// The target document, relative to the OPF file (like `ManifestItem::href`).
+    /// `None` for entries that only group their children.
+    pub href: Option<String>,
+    /// The fragment identifier inside the target document, without the `#`.
+    pub fragment: Option<String>,
+    pub children: Vec<TocEntry>,
+}
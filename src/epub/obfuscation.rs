@@ -0,0 +1,224 @@
+// src/epub/obfuscation.rs
+use std::collections::HashMap;
+use std::io::{self, Read};
+
+use sha1::{Digest, Sha1};
+
+use crate::epub::model::{Encryption, Package};
+
+pub(crate) const ENCRYPTION_PATH: &str = "META-INF/encryption.xml";
+
+/// The font obfuscation schemes used by EPUB publishers. Both XOR the start
+/// of the file with a key derived from the book's identifier, so applying
+/// them twice gives back the original bytes.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Algorithm {
+    /// The IDPF algorithm: the SHA-1 of the unique identifier, over the first 1040 bytes.
+    Idpf,
+    /// Adobe's variant: the bytes of the book's UUID, over the first 1024 bytes.
+    Adobe,
+}
+
+impl Algorithm {
+    pub(crate) const IDPF_URI: &'static str = "http://www.idpf.org/2008/embedding";
+    pub(crate) const ADOBE_URI: &'static str = "http://ns.adobe.com/pdf/enc#RC";
+
+    pub(crate) fn from_uri(uri: &str) -> Option<Self> {
+        match uri.trim() {
+            Self::IDPF_URI => Some(Algorithm::Idpf),
+            Self::ADOBE_URI => Some(Algorithm::Adobe),
+            _ => None,
+        }
+    }
+
+    /// Derives the key from the package's identifiers, if it has a suitable one.
+    pub(crate) fn key(self, package: &Package) -> Option<ObfuscationKey> {
+        match self {
+            Algorithm::Idpf => {
+                // Whitespace is stripped before hashing, as the specification requires.
+                let identifier: String = package.unique_identifier()?
+                    .chars()
+                    .filter(|c| !matches!(c, ' ' | '\t' | '\r' | '\n'))
+                    .collect();
+                Some(ObfuscationKey { key: Sha1::digest(identifier.as_bytes()).to_vec(), length: 1040 })
+            }
+            Algorithm::Adobe => {
+                // The unique identifier if it is a UUID, otherwise the first UUID identifier.
+                let identifiers = package.metadata.identifier.iter().map(|i| i.value.as_str());
+                let key = package.unique_identifier().into_iter().chain(identifiers).find_map(uuid_bytes)?;
+                Some(ObfuscationKey { key, length: 1024 })
+            }
+        }
+    }
+}
+
+/// A key, together with how many leading bytes of a file it covers.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct ObfuscationKey {
+    key: Vec<u8>,
+    length: usize,
+}
+
+impl ObfuscationKey {
+    /// XORs a whole file with the key: obfuscates it, or restores it if it was obfuscated.
+    pub(crate) fn apply_to_file(&self, data: &mut [u8]) {
+        self.apply(0, data);
+    }
+
+    /// XORs `buf`, which starts at `offset` in the file, with the key.
+    fn apply(&self, offset: usize, buf: &mut [u8]) {
+        for (i, byte) in buf.iter_mut().enumerate() {
+            let position = offset + i;
+            if position >= self.length {
+                break;
+            }
+            *byte ^= self.key[position % self.key.len()];
+        }
+    }
+}
+
+/// A reader that (de-)obfuscates the data read through it.
+pub struct ObfuscationReader<R> {
+    inner: R,
+    key: Option<ObfuscationKey>,
+    position: usize,
+}
+
+impl<R: Read> ObfuscationReader<R> {
+    /// Wraps `inner`; without a key the data passes through unchanged.
+    pub(crate) fn new(inner: R, key: Option<ObfuscationKey>) -> Self {
+        Self { inner, key, position: 0 }
+    }
+}
+
+impl<R: Read> Read for ObfuscationReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let read = self.inner.read(buf)?;
+        if let Some(key) = &self.key {
+            key.apply(self.position, &mut buf[..read]);
+        }
+        self.position += read;
+        Ok(read)
+    }
+}
+
+/// Maps the archive paths listed in `encryption.xml` with a supported
+/// algorithm to their keys. Entries using real encryption, or whose key
+/// can't be derived, are left out.
+pub(crate) fn obfuscated_entries(encryption: &Encryption, package: &Package) -> HashMap<String, ObfuscationKey> {
+    encryption.encrypted_data.iter()
+        .filter_map(|data| {
+            let algorithm = Algorithm::from_uri(&data.encryption_method.algorithm)?;
+            let key = algorithm.key(package)?;
+            let uri = &data.cipher_data.cipher_reference.uri;
+            let path = urlencoding::decode(uri).map(|p| p.into_owned()).unwrap_or_else(|_| uri.clone());
+            Some((path, key))
+        })
+        .collect()
+}
+
+/// Lists `paths` as IDPF-obfuscated in an `encryption.xml` document, adding
+/// to `existing` when the container already has one.
+pub(crate) fn add_idpf_entries(existing: Option<&str>, paths: &[String]) -> String {
+    let entries: String = paths.iter()
+        .map(|path| format!(
+            "  <EncryptedData xmlns=\"http://www.w3.org/2001/04/xmlenc#\">\n    <EncryptionMethod Algorithm=\"{}\"/>\n    <CipherData>\n      <CipherReference URI=\"{}\"/>\n    </CipherData>\n  </EncryptedData>\n",
+            Algorithm::IDPF_URI,
+            quick_xml::escape::escape(urlencoding::encode(path).replace("%2F", "/")),
+        ))
+        .collect();
+
+    // The new entries go just before the closing tag of the root element.
+    match existing.and_then(|xml| xml.rfind("</").map(|end| (xml, end))) {
+        Some((xml, end)) => format!("{}{}{}", &xml[..end], entries, &xml[end..]),
+        None => format!(
+            "<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n<encryption xmlns=\"urn:oasis:names:tc:opendocument:xmlns:container\">\n{}</encryption>\n",
+            entries
+        ),
+    }
+}
+
+/// Parses a UUID, with or without its `urn:uuid:` prefix, into its 16 bytes.
+fn uuid_bytes(identifier: &str) -> Option<Vec<u8>> {
+    let identifier = identifier.trim();
+    let uuid = identifier.strip_prefix("urn:uuid:").unwrap_or(identifier);
+    let hex: String = uuid.chars().filter(|c| *c != '-').collect();
+    if hex.len() != 32 || !hex.chars().all(|c| c.is_ascii_hexdigit()) {
+        return None;
+    }
+    (0..32).step_by(2).map(|i| u8::from_str_radix(&hex[i..i + 2], 16).ok()).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A package whose unique identifier is `identifier`.
+    fn package(identifier: &str) -> Package {
+        let opf = format!(
+            r#"<package xmlns="http://www.idpf.org/2007/opf" unique-identifier="uid" version="3.0">
+                <metadata xmlns:dc="http://purl.org/dc/elements/1.1/">
+                    <dc:identifier id="uid">{}</dc:identifier>
+                    <dc:title>Test</dc:title>
+                </metadata>
+                <manifest/>
+                <spine/>
+            </package>"#,
+            identifier
+        );
+        quick_xml::de::from_str(&opf).unwrap()
+    }
+
+    fn font(length: usize) -> Vec<u8> {
+        (0..length).map(|i| (i * 7 % 251) as u8).collect()
+    }
+
+    fn read_through(data: &[u8], key: &ObfuscationKey) -> Vec<u8> {
+        let mut output = Vec::new();
+        ObfuscationReader::new(data, Some(key.clone())).read_to_end(&mut output).unwrap();
+        output
+    }
+
+    #[test]
+    fn idpf_key_strips_whitespace_from_the_identifier() {
+        let key = Algorithm::Idpf.key(&package(" urn:isbn:\n 978-0-00\t0000-00-0 ")).unwrap();
+        assert_eq!(key.key, Sha1::digest(b"urn:isbn:978-0-000000-00-0").to_vec());
+        assert_eq!(key.length, 1040);
+    }
+
+    #[test]
+    fn adobe_key_is_the_bytes_of_a_urn_uuid() {
+        let key = Algorithm::Adobe.key(&package("urn:uuid:0123abcd-4567-89ef-0123-456789abcdef")).unwrap();
+        assert_eq!(key.key, vec![0x01, 0x23, 0xab, 0xcd, 0x45, 0x67, 0x89, 0xef, 0x01, 0x23, 0x45, 0x67, 0x89, 0xab, 0xcd, 0xef]);
+        assert_eq!(key.length, 1024);
+    }
+
+    #[test]
+    fn adobe_key_needs_a_uuid() {
+        assert!(Algorithm::Adobe.key(&package("urn:isbn:9780000000000")).is_none());
+    }
+
+    #[test]
+    fn obfuscation_round_trips_a_font_shorter_than_the_prefix() {
+        for algorithm in [Algorithm::Idpf, Algorithm::Adobe] {
+            let key = algorithm.key(&package("urn:uuid:0123abcd-4567-89ef-0123-456789abcdef")).unwrap();
+            let original = font(100);
+            let obfuscated = read_through(&original, &key);
+            assert_ne!(obfuscated, original);
+            assert_eq!(read_through(&obfuscated, &key), original);
+        }
+    }
+
+    #[test]
+    fn obfuscation_only_covers_the_prefix_of_a_longer_font() {
+        for algorithm in [Algorithm::Idpf, Algorithm::Adobe] {
+            let key = algorithm.key(&package("urn:uuid:0123abcd-4567-89ef-0123-456789abcdef")).unwrap();
+            let original = font(5000);
+            let mut obfuscated = original.clone();
+            key.apply_to_file(&mut obfuscated);
+            assert_ne!(obfuscated[..key.length], original[..key.length]);
+            assert_eq!(obfuscated[key.length..], original[key.length..]);
+            assert_eq!(read_through(&obfuscated, &key), original);
+        }
+    }
+}
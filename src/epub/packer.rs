@@ -0,0 +1,172 @@
+// src/epub/packer.rs
+use std::collections::HashMap;
+use std::fs;
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+
+use path_clean::PathClean;
+use path_slash::PathBufExt;
+use zip::write::SimpleFileOptions;
+use zip::{CompressionMethod, ZipWriter};
+
+use crate::epub::model::{Container, Encryption, Package};
+use crate::epub::obfuscation::{self, Algorithm, ObfuscationKey, ObfuscationReader, ENCRYPTION_PATH};
+use crate::error::{AppError, Result};
+
+const MIMETYPE_FILENAME: &str = "mimetype";
+const EPUB_MIMETYPE: &str = "application/epub+zip";
+const CONTAINER_PATH: &str = "META-INF/container.xml";
+
+/// Builds an EPUB archive at `dest` from an unpacked directory.
+///
+/// The OCF container format requires `mimetype` to be the first entry and to
+/// be stored uncompressed, so it is written explicitly (using the standard
+/// value if the directory has none), followed by `META-INF/container.xml`
+/// and then every other file, deflated, in a stable order. Hidden files such
+/// as `.DS_Store` are skipped.
+///
+/// Fonts listed in `META-INF/encryption.xml` are obfuscated again, as `unpack`
+/// writes them in the clear. With `obfuscate_fonts`, every font in the
+/// manifest is obfuscated with the IDPF algorithm and added to that file.
+/// Returns the number of obfuscated files.
+pub fn pack_directory(source_dir: &Path, dest: &Path, obfuscate_fonts: bool) -> Result<usize> {
+    if !source_dir.join(CONTAINER_PATH).is_file() {
+        return Err(AppError::InvalidEpubFormat(format!(
+            "'{}' not found in '{}'",
+            CONTAINER_PATH,
+            source_dir.display()
+        )));
+    }
+
+    let mimetype = match fs::read_to_string(source_dir.join(MIMETYPE_FILENAME)) {
+        Ok(content) => content.trim().to_string(),
+        Err(e) if e.kind() == io::ErrorKind::NotFound => EPUB_MIMETYPE.to_string(),
+        Err(e) => return Err(e.into()),
+    };
+
+    let plan = plan_obfuscation(source_dir, obfuscate_fonts)?;
+
+    // Don't pack the output into itself when it is written inside the source directory.
+    let dest_abs = std::path::absolute(dest)?;
+    let mut files = Vec::new();
+    collect_files(source_dir, Path::new(""), &mut files)?;
+    files.retain(|rel| {
+        let name = rel.to_slash_lossy();
+        name != MIMETYPE_FILENAME && name != CONTAINER_PATH
+            && (plan.encryption_xml.is_none() || name != ENCRYPTION_PATH)
+            && std::path::absolute(source_dir.join(rel)).map_or(true, |abs| abs != dest_abs)
+    });
+    files.sort();
+
+    let to_zip_error = |source| AppError::ZipCreation { path: dest.to_path_buf(), source };
+    let mut writer = ZipWriter::new(fs::File::create(dest)?);
+
+    let stored = SimpleFileOptions::default().compression_method(CompressionMethod::Stored);
+    writer.start_file(MIMETYPE_FILENAME, stored).map_err(to_zip_error)?;
+    writer.write_all(mimetype.as_bytes())?;
+
+    let deflated = SimpleFileOptions::default().compression_method(CompressionMethod::Deflated);
+    writer.start_file(CONTAINER_PATH, deflated).map_err(to_zip_error)?;
+    writer.write_all(&fs::read(source_dir.join(CONTAINER_PATH))?)?;
+
+    if let Some(encryption_xml) = &plan.encryption_xml {
+        writer.start_file(ENCRYPTION_PATH, deflated).map_err(to_zip_error)?;
+        writer.write_all(encryption_xml.as_bytes())?;
+    }
+
+    let mut obfuscated = 0;
+    for rel in &files {
+        let name = rel.to_slash_lossy();
+        let key = plan.keys.get(name.as_ref()).cloned();
+        obfuscated += usize::from(key.is_some());
+        writer.start_file(name, deflated).map_err(to_zip_error)?;
+        // Obfuscation applies to the raw bytes, before compression.
+        let mut file = ObfuscationReader::new(fs::File::open(source_dir.join(rel))?, key);
+        io::copy(&mut file, &mut writer)?;
+    }
+
+    writer.finish().map_err(to_zip_error)?;
+    Ok(obfuscated)
+}
+
+/// Which files to obfuscate, by path inside the container.
+struct ObfuscationPlan {
+    keys: HashMap<String, ObfuscationKey>,
+    /// A new `encryption.xml` when fonts had to be added to it.
+    encryption_xml: Option<String>,
+}
+
+fn plan_obfuscation(source_dir: &Path, obfuscate_fonts: bool) -> Result<ObfuscationPlan> {
+    let existing = match fs::read_to_string(source_dir.join(ENCRYPTION_PATH)) {
+        Ok(content) => Some(content),
+        Err(e) if e.kind() == io::ErrorKind::NotFound => None,
+        Err(e) => return Err(e.into()),
+    };
+    if existing.is_none() && !obfuscate_fonts {
+        return Ok(ObfuscationPlan { keys: HashMap::new(), encryption_xml: None });
+    }
+
+    let encryption: Encryption = match &existing {
+        Some(content) => quick_xml::de::from_str(content).map_err(|source| AppError::XmlParsing {
+            path: source_dir.join(ENCRYPTION_PATH),
+            source,
+        })?,
+        None => Encryption::default(),
+    };
+    let (opf_path, package) = read_package(source_dir)?;
+    let mut keys = obfuscation::obfuscated_entries(&encryption, &package);
+    if !obfuscate_fonts {
+        return Ok(ObfuscationPlan { keys, encryption_xml: None });
+    }
+
+    let opf_dir = Path::new(&opf_path).parent().unwrap_or_else(|| Path::new(""));
+    let mut added = Vec::new();
+    for item in package.manifest.items.iter().filter(|item| item.is_font()) {
+        let href = urlencoding::decode(&item.href).map(|h| h.into_owned()).unwrap_or_else(|_| item.href.clone());
+        let path = opf_dir.join(href).clean().to_slash_lossy().into_owned();
+        if keys.contains_key(&path) || !source_dir.join(&path).is_file() {
+            continue;
+        }
+        let key = Algorithm::Idpf.key(&package).ok_or_else(|| {
+            AppError::InvalidEpubFormat("Cannot obfuscate fonts: the package has no unique identifier".to_string())
+        })?;
+        keys.insert(path.clone(), key);
+        added.push(path);
+    }
+
+    let encryption_xml = (!added.is_empty()).then(|| obfuscation::add_idpf_entries(existing.as_deref(), &added));
+    Ok(ObfuscationPlan { keys, encryption_xml })
+}
+
+/// Reads the package document that `container.xml` points at, returning its path too.
+fn read_package(source_dir: &Path) -> Result<(String, Package)> {
+    let container_path = source_dir.join(CONTAINER_PATH);
+    let container: Container = quick_xml::de::from_str(&fs::read_to_string(&container_path)?)
+        .map_err(|source| AppError::XmlParsing { path: container_path, source })?;
+    let opf_path = container.rootfiles.rootfile.first()
+        .map(|rootfile| rootfile.full_path.clone())
+        .ok_or_else(|| AppError::InvalidEpubFormat("No rootfile found in container.xml".to_string()))?;
+
+    let package_path = source_dir.join(&opf_path);
+    let package = quick_xml::de::from_str(&fs::read_to_string(&package_path)?)
+        .map_err(|source| AppError::XmlParsing { path: package_path, source })?;
+    Ok((opf_path, package))
+}
+
+/// Recursively lists the files below `root.join(rel_dir)` as paths relative to
+/// `root`, skipping hidden files and directories.
+pub(super) fn collect_files(root: &Path, rel_dir: &Path, files: &mut Vec<PathBuf>) -> Result<()> {
+    for entry in fs::read_dir(root.join(rel_dir))? {
+        let entry = entry?;
+        if entry.file_name().to_string_lossy().starts_with('.') {
+            continue;
+        }
+        let rel_path = rel_dir.join(entry.file_name());
+        if entry.file_type()?.is_dir() {
+            collect_files(root, &rel_path, files)?;
+        } else {
+            files.push(rel_path);
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,272 @@
+// src/epub/path_resolver.rs
+use std::cell::RefCell;
+use std::collections::{HashMap, HashSet};
+use std::path::{Path, PathBuf};
+
+use path_clean::PathClean;
+use path_slash::PathBufExt;
+
+use crate::epub::diagnostics::{LinkDiagnostic, LinkProblem};
+
+/// Where a link was found, to resolve it against and to report it by.
+#[derive(Debug, Clone, Copy)]
+pub(super) struct LinkSource<'s> {
+    /// The file holding the link, as a `PathResolver::key`.
+    pub(super) document: &'s str,
+    /// The attribute holding the link, or `url()` for CSS.
+    pub(super) attribute: &'s str,
+}
+
+/// Maps the files of a book (by their path relative to the OPF file) to
+/// their location in the generated site, and rewrites links accordingly.
+///
+/// Lookups are exact first. Books written on case-insensitive systems often
+/// get the case of a link wrong, so a lookup that only matches when ignoring
+/// case still succeeds, with a warning.
+///
+/// Links that can't be followed as written are collected as diagnostics.
+#[derive(Debug, Default)]
+pub(super) struct PathResolver {
+    site_paths: HashMap<String, PathBuf>,
+    /// Lowercased key -> the first key inserted with that spelling.
+    folded: HashMap<String, String>,
+    /// Problems met while resolving links, each reported once, in order.
+    warnings: RefCell<Vec<String>>,
+    warned: RefCell<HashSet<String>>,
+    diagnostics: RefCell<Vec<LinkDiagnostic>>,
+    /// The (document, attribute, link) of every diagnostic, as the same
+    /// link may be resolved several times.
+    reported: RefCell<HashSet<(String, String, String)>>,
+}
+
+impl PathResolver {
+    pub(super) fn new() -> Self {
+        Self::default()
+    }
+
+    /// Normalizes an OPF-relative href into a lookup key: percent-decoded,
+    /// cleaned and slash-separated, with its case preserved.
+    pub(super) fn key(href: &str) -> String {
+        let decoded = urlencoding::decode(href).map(|s| s.into_owned()).unwrap_or_else(|_| href.to_string());
+        Path::new(&decoded).clean().to_slash_lossy().into_owned()
+    }
+
+    pub(super) fn insert(&mut self, href: &str, site_path: PathBuf) {
+        let key = Self::key(href);
+        self.folded.entry(key.to_lowercase()).or_insert_with(|| key.clone());
+        self.site_paths.insert(key, site_path);
+    }
+
+    /// The site path of the file at `href` (relative to the OPF file).
+    pub(super) fn site_path(&self, href: &str) -> Option<&PathBuf> {
+        let (site_path, actual) = self.lookup(&Self::key(href))?;
+        if let Some(actual) = actual {
+            self.warn(format!("'{}' only matches '{}' when ignoring case", href, actual));
+        }
+        Some(site_path)
+    }
+
+    /// The site path of `key`, with the key that matched if only its case does.
+    fn lookup(&self, key: &str) -> Option<(&PathBuf, Option<&String>)> {
+        if let Some(site_path) = self.site_paths.get(key) {
+            return Some((site_path, None));
+        }
+        let actual = self.folded.get(&key.to_lowercase())?;
+        Some((self.site_paths.get(actual)?, Some(actual)))
+    }
+
+    /// Finds what a relative link in the file `from.document` points at: the
+    /// target's site path and the fragment, without its `#`. Returns `None`
+    /// for links to the document itself (empty, fragment or query only),
+    /// absolute paths and external URLs, and reports relative links that
+    /// match no file. A query is not part of the file's path, and is dropped.
+    pub(super) fn resolve<'l>(&self, link: &'l str, from: LinkSource<'_>) -> Option<(&PathBuf, Option<&'l str>)> {
+        if link.is_empty() || link.starts_with(['#', '?', '/']) || link.contains(':') {
+            return None; // Self-references, absolute paths, or external URLs are not rewritten
+        }
+
+        // Split the link into file path and fragment (anchor) parts
+        let (file_part, fragment) = match link.split_once('#') {
+            Some((file_part, fragment)) => (file_part, Some(fragment)),
+            None => (link, None),
+        };
+        let file_part = file_part.split_once('?').map_or(file_part, |(path, _query)| path);
+
+        let decoded_link = urlencoding::decode(file_part).unwrap_or_else(|_| file_part.into()).into_owned();
+        let source_epub_dir = Path::new(from.document).parent().unwrap_or_else(|| Path::new(""));
+        let key = source_epub_dir.join(decoded_link).clean().to_slash_lossy().into_owned();
+
+        let reason = match self.lookup(&key) {
+            Some((target_site_path, None)) => return Some((target_site_path, fragment)),
+            Some((target_site_path, Some(actual))) => {
+                self.report(from, link, &key, LinkProblem::CaseMismatch { actual: actual.clone() });
+                return Some((target_site_path, fragment));
+            }
+            None if is_font(file_part) => LinkProblem::MissingFont,
+            None => LinkProblem::NotFound,
+        };
+        self.report(from, link, &key, reason);
+        None
+    }
+
+    /// Records a diagnostic, once per place the link is found.
+    fn report(&self, from: LinkSource<'_>, link: &str, key: &str, reason: LinkProblem) {
+        let place = (from.document.to_string(), from.attribute.to_string(), link.to_string());
+        if !self.reported.borrow_mut().insert(place) {
+            return;
+        }
+        let diagnostic = LinkDiagnostic {
+            source: from.document.to_string(),
+            attribute: from.attribute.to_string(),
+            link: link.to_string(),
+            key: key.to_string(),
+            reason,
+        };
+        // Reduce noise: style sheets often list fonts as fallbacks that the book does not ship.
+        if diagnostic.reason != LinkProblem::MissingFont {
+            self.warn(diagnostic.to_string());
+        }
+        self.diagnostics.borrow_mut().push(diagnostic);
+    }
+
+    fn warn(&self, warning: String) {
+        if self.warned.borrow_mut().insert(warning.clone()) {
+            self.warnings.borrow_mut().push(warning);
+        }
+    }
+
+    /// Hands over the warnings collected so far.
+    pub(super) fn take_warnings(&self) -> Vec<String> {
+        self.warnings.take()
+    }
+
+    /// Hands over the diagnostics collected so far.
+    pub(super) fn take_diagnostics(&self) -> Vec<LinkDiagnostic> {
+        self.reported.borrow_mut().clear();
+        self.diagnostics.take()
+    }
+
+    /// Rewrites a relative link from its context in the EPUB to its new context in the static site.
+    pub(super) fn rewrite_link(&self, original_link: &str, from: LinkSource<'_>, source_site_path: &Path) -> Option<String> {
+        let (target_site_path, fragment) = self.resolve(original_link, from)?;
+        let source_site_dir = source_site_path.parent().unwrap_or_else(|| Path::new(""));
+        let new_relative_path = pathdiff::diff_paths(target_site_path, source_site_dir)?;
+        let mut result = new_relative_path.to_slash().map(|s| s.into_owned())?;
+        // Append the fragment part if it exists
+        if let Some(fragment) = fragment {
+            result.push('#');
+            result.push_str(fragment);
+        }
+        Some(result)
+    }
+}
+
+/// Whether `path` names a font file, by its extension.
+fn is_font(path: &str) -> bool {
+    let extension = Path::new(path).extension().and_then(|s| s.to_str()).unwrap_or_default();
+    ["ttf", "otf", "woff", "woff2"].iter().any(|font| extension.eq_ignore_ascii_case(font))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::epub::diagnostics::Diagnostics;
+
+    const CHAPTER: LinkSource = LinkSource { document: "Text/ch01.xhtml", attribute: "href" };
+    const STYLE_SHEET: LinkSource = LinkSource { document: "Styles/style.css", attribute: "url()" };
+
+    fn rewrite(resolver: &PathResolver, link: &str) -> Option<String> {
+        resolver.rewrite_link(link, CHAPTER, Path::new("chapters/ch01.html"))
+    }
+
+    fn reasons(resolver: &PathResolver) -> Vec<LinkProblem> {
+        resolver.take_diagnostics().into_iter().map(|diagnostic| diagnostic.reason).collect()
+    }
+
+    #[test]
+    fn climbing_above_the_root_does_not_wrap_around() {
+        let mut resolver = PathResolver::new();
+        resolver.insert("Images/a.png", PathBuf::from("assets/a.png"));
+        assert_eq!(rewrite(&resolver, "../Images/a.png").as_deref(), Some("../assets/a.png"));
+        // "Text/../../Images/a.png" is "../Images/a.png", which is outside the book.
+        assert_eq!(rewrite(&resolver, "../../Images/a.png"), None);
+        assert_eq!(reasons(&resolver), [LinkProblem::NotFound]);
+    }
+
+    #[test]
+    fn percent_encoded_links_match_decoded_paths() {
+        let mut resolver = PathResolver::new();
+        resolver.insert("Images/My%20Cover.jpg", PathBuf::from("assets/My Cover.jpg"));
+        assert_eq!(resolver.site_path("Images/My Cover.jpg"), Some(&PathBuf::from("assets/My Cover.jpg")));
+        assert_eq!(rewrite(&resolver, "../Images/My%20Cover.jpg#top").as_deref(), Some("../assets/My Cover.jpg#top"));
+    }
+
+    #[test]
+    fn case_mismatch_resolves_and_is_reported_once() {
+        let mut resolver = PathResolver::new();
+        resolver.insert("Images/Cover.JPG", PathBuf::from("assets/Cover.JPG"));
+        assert_eq!(rewrite(&resolver, "../images/cover.jpg").as_deref(), Some("../assets/Cover.JPG"));
+        assert_eq!(rewrite(&resolver, "../images/cover.jpg").as_deref(), Some("../assets/Cover.JPG"));
+        assert_eq!(resolver.take_warnings(), ["'../images/cover.jpg' in 'Text/ch01.xhtml' only matches 'Images/Cover.JPG' when ignoring case"]);
+        assert_eq!(reasons(&resolver), [LinkProblem::CaseMismatch { actual: "Images/Cover.JPG".to_string() }]);
+    }
+
+    #[test]
+    fn ambiguous_case_mismatch_takes_the_first_file_inserted() {
+        let mut resolver = PathResolver::new();
+        resolver.insert("Images/a.png", PathBuf::from("assets/a.png"));
+        resolver.insert("images/A.png", PathBuf::from("assets/A-2.png"));
+        // Exact matches win over case-insensitive ones.
+        assert_eq!(rewrite(&resolver, "../images/A.png").as_deref(), Some("../assets/A-2.png"));
+        assert_eq!(rewrite(&resolver, "../IMAGES/a.PNG").as_deref(), Some("../assets/a.png"));
+        assert_eq!(reasons(&resolver), [LinkProblem::CaseMismatch { actual: "Images/a.png".to_string() }]);
+    }
+
+    #[test]
+    fn empty_and_query_only_links_point_at_the_document_itself() {
+        let mut resolver = PathResolver::new();
+        resolver.insert("Text/ch01.xhtml", PathBuf::from("chapters/ch01.html"));
+        assert_eq!(resolver.resolve("", CHAPTER), None);
+        assert_eq!(resolver.resolve("?page=2", CHAPTER), None);
+        assert!(resolver.take_diagnostics().is_empty());
+    }
+
+    #[test]
+    fn queries_are_not_part_of_the_path() {
+        let mut resolver = PathResolver::new();
+        resolver.insert("Images/img.png", PathBuf::from("assets/img.png"));
+        resolver.insert("Images/why%3F.png", PathBuf::from("assets/why.png"));
+        assert_eq!(rewrite(&resolver, "../Images/img.png?v=2").as_deref(), Some("../assets/img.png"));
+        assert_eq!(rewrite(&resolver, "../Images/img.png?v=2#top").as_deref(), Some("../assets/img.png#top"));
+        // An encoded `?` belongs to the file name.
+        assert_eq!(rewrite(&resolver, "../Images/why%3F.png").as_deref(), Some("../assets/why.png"));
+        assert!(resolver.take_diagnostics().is_empty());
+    }
+
+    #[test]
+    fn missing_fonts_are_diagnosed_without_a_warning() {
+        let resolver = PathResolver::new();
+        for link in ["../Fonts/body.ttf", "../Fonts/Body.TTF", "../Fonts/title.WOFF2"] {
+            assert_eq!(resolver.resolve(link, STYLE_SHEET), None);
+        }
+        assert_eq!(resolver.resolve("../Images/bg.png", STYLE_SHEET), None);
+        assert_eq!(resolver.take_warnings(), ["Could not resolve link '../Images/bg.png' (resolved to 'Images/bg.png') in 'Styles/style.css'"]);
+        assert_eq!(reasons(&resolver), [LinkProblem::MissingFont, LinkProblem::MissingFont, LinkProblem::MissingFont, LinkProblem::NotFound]);
+    }
+
+    #[test]
+    fn only_links_that_lead_nowhere_are_broken() {
+        let mut resolver = PathResolver::new();
+        resolver.insert("Images/a.png", PathBuf::from("assets/a.png"));
+        resolver.resolve("../Fonts/body.otf", CHAPTER);
+        resolver.resolve("../images/a.png", CHAPTER);
+        // What `--strict` fails on.
+        let diagnostics = Diagnostics { links: resolver.take_diagnostics() };
+        assert_eq!(diagnostics.links.len(), 2);
+        assert!(!diagnostics.has_broken_links());
+
+        resolver.resolve("missing.xhtml", CHAPTER);
+        let diagnostics = Diagnostics { links: resolver.take_diagnostics() };
+        assert_eq!(diagnostics.broken_links().map(|link| link.key.as_str()).collect::<Vec<_>>(), ["Text/missing.xhtml"]);
+    }
+}
@@ -0,0 +1,102 @@
+// src/epub/progress.rs
+use std::io::Write;
+use std::path::PathBuf;
+
+use serde::Serialize;
+
+/// A stage of `SiteGenerator::run`. Chapter sites go through `TransformChapters`
+/// and `GenerateIndex`; single-page sites through `CombineChapters` instead.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "kebab-case")]
+#[non_exhaustive]
+pub enum Phase {
+    PrepareOutput,
+    PlanPaths,
+    CopyAssets,
+    CopyTemplateAssets,
+    TransformChapters,
+    GenerateIndex,
+    CombineChapters,
+}
+
+impl Phase {
+    /// What the phase does, e.g. "Copying assets".
+    pub fn description(self) -> &'static str {
+        match self {
+            Phase::PrepareOutput => "Preparing the output directory",
+            Phase::PlanPaths => "Planning the site's paths",
+            Phase::CopyAssets => "Copying assets",
+            Phase::CopyTemplateAssets => "Copying template assets",
+            Phase::TransformChapters => "Transforming chapters",
+            Phase::GenerateIndex => "Generating the table of contents",
+            Phase::CombineChapters => "Combining chapters into a single page",
+        }
+    }
+}
+
+/// Something that happened during a build, as told to a `ProgressSink`.
+#[derive(Debug, Clone, Serialize)]
+#[serde(tag = "event", rename_all = "kebab-case")]
+#[non_exhaustive]
+pub enum ProgressEvent {
+    /// `total` is the number of items the phase reports, for phases that report any.
+    PhaseStarted { phase: Phase, total: Option<usize> },
+    PhaseFinished { phase: Phase },
+    /// The `index`-th asset (counting from 1) of `total` was written to the
+    /// site, or embedded in the page of a self-contained build.
+    AssetCopied { path: PathBuf, index: usize, total: usize },
+    /// The `index`-th spine document (counting from 1) of `total` was turned
+    /// into a page, or into a section of the single page.
+    ChapterTransformed { path: PathBuf, title: String, index: usize, total: usize },
+    /// A problem that does not stop the build. It is in the `WebifyReport` too.
+    Warning { message: String },
+}
+
+/// Receives the events of a build as they happen, e.g. to show a progress bar.
+pub trait ProgressSink {
+    fn event(&mut self, event: &ProgressEvent);
+}
+
+impl<S: ProgressSink + ?Sized> ProgressSink for &mut S {
+    fn event(&mut self, event: &ProgressEvent) {
+        (**self).event(event);
+    }
+}
+
+/// Ignores every event; what `SiteGenerator` uses unless told otherwise.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct SilentProgress;
+
+impl ProgressSink for SilentProgress {
+    fn event(&mut self, _event: &ProgressEvent) {}
+}
+
+/// Writes every event to a writer as one line of JSON, for logs and CI:
+///
+/// ```text
+/// {"event":"asset-copied","path":"assets/cover.jpg","index":3,"total":12}
+/// ```
+///
+/// Failing to write is ignored, as progress must never fail a build.
+#[derive(Debug)]
+pub struct JsonProgress<W: Write> {
+    writer: W,
+}
+
+impl<W: Write> JsonProgress<W> {
+    pub fn new(writer: W) -> Self {
+        Self { writer }
+    }
+
+    pub fn into_inner(self) -> W {
+        self.writer
+    }
+}
+
+impl<W: Write> ProgressSink for JsonProgress<W> {
+    fn event(&mut self, event: &ProgressEvent) {
+        if serde_json::to_writer(&mut self.writer, event).is_ok() {
+            let _ = writeln!(self.writer);
+        }
+    }
+}
@@ -1,25 +1,250 @@
-use std::collections::HashMap;
+use std::cell::RefCell;
+use std::collections::{HashMap, HashSet};
 use std::fs;
 use std::io::{copy, Read, Write};
 use std::path::{Path, PathBuf};
+use std::rc::Rc;
 
-use lazy_static::lazy_static;
-use lol_html::{element, rewrite_str, RewriteStrSettings};
-use lol_html::html_content::Element;
+use base64::prelude::*;
+use lol_html::{doc_comments, doctype, element, rewrite_str, text, RewriteStrSettings};
+use lol_html::html_content::{ContentType, Element};
+use minijinja::context;
+use minijinja::value::{Serde, Value};
+use quick_xml::escape::escape;
+use serde::Serialize;
 use pathdiff;
 use path_slash::PathBufExt;
-use regex::{Captures, Regex};
 
-use crate::epub::{model::ManifestItem, Epub};
-use crate::epub::rewriter::{normalize_path_key, rewrite_link};
+use crate::epub::layout::{PageMode, PathPlanner, SiteLayout};
+use crate::epub::{model::{ManifestItem, TocEntry}, Epub};
+use crate::epub::diagnostics::Diagnostics;
+use crate::epub::path_resolver::{LinkSource, PathResolver};
+use crate::epub::progress::{Phase, ProgressEvent, ProgressSink, SilentProgress};
+use crate::epub::{css, toc};
+use crate::epub::rewriter::rewrite_srcset;
+use crate::epub::templates::{BookInfo, PageLink, Templates, TocItem, CHAPTER_TEMPLATE, INDEX_TEMPLATE};
 use crate::error::{AppError, Result};
 
-const TEMPLATE_HTML: &str = include_str!("../../static/template.html");
 const STYLES_CSS: &str = include_str!("../../static/styles.css");
 const STYLES_CSS_FILENAME: &str = "styles.css";
 
-lazy_static! {
-    static ref CSS_URL_RE: Regex = Regex::new(r#"url\((.*?)\)"#).unwrap();
+/// What diagnostics name the place of links found in CSS.
+const CSS_URL: &str = "url()";
+
+/// Elements and the attribute on them that holds a single URL.
+const URL_ATTRIBUTES: &[(&str, &str)] = &[
+    ("a[href], area[href], link[href]", "href"),
+    ("img[src], audio[src], video[src], source[src], track[src], embed[src], iframe[src], input[src], script[src]", "src"),
+    ("video[poster]", "poster"),
+    ("object[data]", "data"),
+    // SVG links; `xlink:href` is the SVG 1.1 spelling of `href`.
+    ("image[href], use[href]", "href"),
+    ("image, use, a", "xlink:href"),
+];
+
+/// Ids used by the page template itself, which chapter content must not take.
+const TEMPLATE_IDS: &[&str] = &["reading-progress", "theme-toggle", "back-to-top"];
+
+/// The element ids of a single-page build. Every chapter becomes a section
+/// of `index.html`, so ids are made unique across the whole book: the first
+/// document to use an id keeps it, later ones get a numbered suffix.
+#[derive(Debug, Default)]
+struct PageAnchors {
+    /// The spine index of each content document, by its site path.
+    documents: HashMap<PathBuf, usize>,
+    /// The id of the section holding each spine item.
+    sections: Vec<String>,
+    /// (spine index, original id) -> id on the page, for the ids that had to change.
+    renamed: HashMap<(usize, String), String>,
+    taken: HashSet<String>,
+}
+
+impl PageAnchors {
+    /// Takes `id`, or the first free `id-2`, `id-3`... if it is already in use.
+    fn claim(&mut self, id: &str) -> String {
+        let mut candidate = id.to_string();
+        let mut counter = 2;
+        while !self.taken.insert(candidate.clone()) {
+            candidate = format!("{}-{}", id, counter);
+            counter += 1;
+        }
+        candidate
+    }
+
+    /// The id on the page of the element that had `id` in the given spine item.
+    fn id(&self, index: usize, id: &str) -> String {
+        self.renamed.get(&(index, id.to_string())).cloned().unwrap_or_else(|| id.to_string())
+    }
+
+    /// An in-page link to a spine item, or to an element in it.
+    fn link(&self, index: usize, fragment: Option<&str>) -> String {
+        match fragment.filter(|fragment| !fragment.is_empty()) {
+            Some(fragment) => format!("#{}", self.id(index, fragment)),
+            None => format!("#{}", self.sections[index]),
+        }
+    }
+}
+
+/// What the rewriter needs to fold one document into a single-page build.
+#[derive(Clone)]
+struct PageContext<'a> {
+    anchors: &'a PageAnchors,
+    /// The spine index of the document being rewritten.
+    index: usize,
+    /// The style sheets taken out of the documents' heads, in order of appearance,
+    /// as the `<link>` or `<style>` element that puts them in the page's head.
+    stylesheets: Rc<RefCell<Vec<String>>>,
+}
+
+/// The assets embedded in a self-contained page, by site path.
+#[derive(Debug, Default)]
+struct InlineAssets {
+    /// `data:` URIs of the embedded files, style sheets included.
+    uris: HashMap<PathBuf, String>,
+    /// The rewritten text of the embedded style sheets, which go into `<style>` blocks.
+    styles: HashMap<PathBuf, String>,
+    /// Files over the limit, which were written to the site instead.
+    too_large: Vec<OversizedAsset>,
+}
+
+impl InlineAssets {
+    /// Like `PathResolver::rewrite_link`, but links to embedded files become their `data:` URI.
+    fn rewrite_link(&self, resolver: &PathResolver, link: &str, from: LinkSource<'_>, source_site_path: &Path) -> Option<String> {
+        if let Some((target_site_path, fragment)) = resolver.resolve(link, from)
+            && let Some(uri) = self.uris.get(target_site_path) {
+            return Some(match fragment {
+                Some(fragment) => format!("{}#{}", uri, fragment),
+                None => uri.clone(),
+            });
+        }
+        resolver.rewrite_link(link, from, source_site_path)
+    }
+}
+
+/// Rewrites links found in one content document.
+struct LinkRewriter<'a> {
+    resolver: &'a PathResolver,
+    /// The document, as a `PathResolver::key`.
+    document: String,
+    source_site_path: PathBuf,
+    /// Set in a single-page build: links to chapters become in-page anchors.
+    page: Option<(&'a PageAnchors, usize)>,
+    /// Set in a self-contained build: links to embedded files become `data:` URIs.
+    inline_assets: Option<&'a InlineAssets>,
+}
+
+impl LinkRewriter<'_> {
+    /// Rewrites `link`, found in `attribute` (or `url()` for CSS).
+    fn rewrite(&self, link: &str, attribute: &str) -> Option<String> {
+        let from = LinkSource { document: &self.document, attribute };
+        if let Some((anchors, index)) = self.page {
+            if let Some(fragment) = link.strip_prefix('#') {
+                return Some(anchors.link(index, Some(fragment)));
+            }
+            if let Some((target_site_path, fragment)) = self.resolver.resolve(link, from)
+                && let Some(&target) = anchors.documents.get(target_site_path) {
+                return Some(anchors.link(target, fragment));
+            }
+        }
+        match self.inline_assets {
+            Some(inline_assets) => inline_assets.rewrite_link(self.resolver, link, from, &self.source_site_path),
+            None => self.resolver.rewrite_link(link, from, &self.source_site_path),
+        }
+    }
+
+    /// The text of the style sheet linked to by `href`, if it is embedded in the page.
+    fn embedded_style(&self, href: &str) -> Option<&str> {
+        let (target_site_path, _) = self.resolver.resolve(href, LinkSource { document: &self.document, attribute: "href" })?;
+        self.inline_assets?.styles.get(target_site_path).map(String::as_str)
+    }
+}
+
+/// How `SiteGenerator` builds a site. Starts from the defaults of the
+/// `webify` command: one page per chapter, flat layout, built-in templates.
+///
+/// ```no_run
+/// use tome::{PageMode, SiteLayout, WebifyOptions};
+///
+/// let options = WebifyOptions::new()
+///     .layout(SiteLayout::Mirror)
+///     .mode(PageMode::Single)
+///     .template_dir("my-theme");
+/// ```
+#[derive(Debug, Clone, Default)]
+pub struct WebifyOptions {
+    no_nav: bool,
+    layout: SiteLayout,
+    mode: PageMode,
+    template_dir: Option<PathBuf>,
+    base_url: Option<String>,
+}
+
+impl WebifyOptions {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Leaves the previous/next links out of chapter pages.
+    pub fn no_nav(mut self, no_nav: bool) -> Self {
+        self.no_nav = no_nav;
+        self
+    }
+
+    /// Where the book's files go in the site.
+    pub fn layout(mut self, layout: SiteLayout) -> Self {
+        self.layout = layout;
+        self
+    }
+
+    /// Whether chapters get their own pages or share `index.html`.
+    pub fn mode(mut self, mode: PageMode) -> Self {
+        self.mode = mode;
+        self
+    }
+
+    /// Renders pages with the templates in `dir` rather than the built-in ones.
+    /// See the README for the variables templates receive.
+    pub fn template_dir(mut self, dir: impl Into<PathBuf>) -> Self {
+        self.template_dir = Some(dir.into());
+        self
+    }
+
+    /// The URL the site will be published at. Social preview tags need an
+    /// absolute link to the cover, so they only name one when this is set.
+    pub fn base_url(mut self, url: impl Into<String>) -> Self {
+        self.base_url = Some(url.into());
+        self
+    }
+
+    /// The template directory set by `template_dir`, if any.
+    pub fn template_dir_path(&self) -> Option<&Path> {
+        self.template_dir.as_deref()
+    }
+}
+
+/// The outcome of a successful `SiteGenerator::run`.
+#[derive(Debug, Default, Serialize)]
+pub struct WebifyReport {
+    /// The number of HTML pages written.
+    pub pages: usize,
+    /// Problems that did not stop the build, such as links that could not be
+    /// resolved or files moved aside because their names clashed.
+    pub warnings: Vec<String>,
+    /// The files a self-contained build could not embed because they are
+    /// over the inline limit. They were written next to `index.html` instead.
+    pub oversized_assets: Vec<OversizedAsset>,
+    /// Every link that could not be followed as written, with where it was
+    /// found. The ones the build warned about are in `warnings` too.
+    pub diagnostics: Diagnostics,
+}
+
+/// A file too large to embed in a self-contained page.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct OversizedAsset {
+    /// Where the file was written, relative to the output directory.
+    pub site_path: PathBuf,
+    /// Its size in bytes.
+    pub size: u64,
 }
 
 /// Orchestrates the conversion of an EPUB to a static website.
@@ -27,83 +252,221 @@ pub struct SiteGenerator<'a> {
     epub: &'a mut Epub,
     output_dir: &'a Path,
     no_nav: bool,
-    path_map: HashMap<String, PathBuf>,
+    layout: SiteLayout,
+    mode: PageMode,
+    /// The URL the site is published at, for links that must be absolute.
+    base_url: Option<String>,
+    resolver: PathResolver,
     spine_items: Vec<ManifestItem>,
+    /// Indices into `spine_items` of the linear items, which form the prev/next chain.
+    reading_order: Vec<usize>,
+    /// Chapter titles taken from the table of contents, keyed by `PathResolver::key`.
+    chapter_titles: HashMap<String, String>,
+    /// The ids of the combined page, in a single-page build.
+    page_anchors: Option<PageAnchors>,
+    /// The embedded assets, in a self-contained build.
+    inline_assets: Option<InlineAssets>,
+    templates: Templates,
+    progress: RefCell<Box<dyn ProgressSink + 'a>>,
+    /// The warnings of the current build, the resolver's included once passed on.
+    warnings: RefCell<Vec<String>>,
 }
 
 impl<'a> SiteGenerator<'a> {
-    pub fn new(epub: &'a mut Epub, output_dir: &'a Path, no_nav: bool) -> Self {
-        Self { epub, output_dir, no_nav, path_map: HashMap::new(), spine_items: Vec::new() }
-    }
-
-    pub fn run(&mut self) -> Result<()> {
-        self.prepare_directory()?;
-        println!("   ├── Prepared output directory.");
-        self.build_path_map_and_spine();
-        println!("   ├── Built path map and identified spine content.");
-        self.process_and_copy_assets()?;
-        println!("   ├── Processed and copied all assets (images, fonts, CSS).");
-        self.copy_static_assets()?;
-        println!("   ├── Injected template assets.");
-        self.transform_content_documents()?;
-        println!("   ├── Transformed HTML content and applied templates.");
-        self.generate_toc()?;
-        println!("   ├── Generated table of contents (index.html).");
+    /// Sets up a build of `epub` into `output_dir`. Fails if the template
+    /// directory of `options` is missing or its templates do not compile.
+    pub fn new(epub: &'a mut Epub, output_dir: &'a Path, options: &WebifyOptions) -> Result<Self> {
+        let templates = match &options.template_dir {
+            Some(dir) => Templates::from_dir(dir)?,
+            None => Templates::builtin(),
+        };
+        Ok(Self {
+            epub,
+            output_dir,
+            no_nav: options.no_nav,
+            layout: options.layout,
+            mode: options.mode,
+            base_url: options.base_url.clone(),
+            resolver: PathResolver::new(),
+            spine_items: Vec::new(),
+            reading_order: Vec::new(),
+            chapter_titles: HashMap::new(),
+            page_anchors: None,
+            inline_assets: None,
+            templates,
+            progress: RefCell::new(Box::new(SilentProgress)),
+            warnings: RefCell::new(Vec::new()),
+        })
+    }
+
+    /// Tells `sink` about the progress of `run` as it goes.
+    pub fn with_progress(self, sink: impl ProgressSink + 'a) -> Self {
+        Self { progress: RefCell::new(Box::new(sink)), ..self }
+    }
+
+    /// Generates the site, replacing whatever `output_dir` held before.
+    pub fn run(&mut self) -> Result<WebifyReport> {
+        self.warnings.borrow_mut().clear();
+        self.phase(Phase::PrepareOutput, None, |generator| generator.prepare_directory())?;
+        self.phase(Phase::PlanPaths, None, |generator| {
+            generator.build_path_map_and_spine();
+            Ok(())
+        })?;
+        let assets = self.asset_count();
+        self.phase(Phase::CopyAssets, Some(assets), |generator| generator.process_and_copy_assets())?;
+        self.phase(Phase::CopyTemplateAssets, None, |generator| generator.copy_static_assets())?;
+        let chapters = self.spine_items.len();
+        let pages = if self.mode != PageMode::Chapters {
+            self.phase(Phase::CombineChapters, Some(chapters), |generator| generator.generate_single_page())?;
+            1
+        } else {
+            self.phase(Phase::TransformChapters, Some(chapters), |generator| generator.transform_content_documents())?;
+            self.phase(Phase::GenerateIndex, None, |generator| generator.generate_toc())?;
+            chapters + 1
+        };
+
+        let warnings = self.warnings.take();
+        let oversized_assets = self.inline_assets.as_mut().map(|inline_assets| std::mem::take(&mut inline_assets.too_large)).unwrap_or_default();
+        let diagnostics = Diagnostics { links: self.resolver.take_diagnostics() };
+        Ok(WebifyReport { pages, warnings, oversized_assets, diagnostics })
+    }
+
+    /// Runs one phase of `run`, telling the progress sink when it starts and ends.
+    fn phase(&mut self, phase: Phase, total: Option<usize>, run: impl FnOnce(&mut Self) -> Result<()>) -> Result<()> {
+        self.emit(ProgressEvent::PhaseStarted { phase, total });
+        run(self)?;
+        self.flush_warnings();
+        self.emit(ProgressEvent::PhaseFinished { phase });
         Ok(())
     }
 
+    fn emit(&self, event: ProgressEvent) {
+        self.progress.borrow_mut().event(&event);
+    }
+
+    /// Records a warning for the report and passes it on to the progress sink.
+    fn warn(&self, message: String) {
+        self.emit(ProgressEvent::Warning { message: message.clone() });
+        self.warnings.borrow_mut().push(message);
+    }
+
+    /// Passes on the warnings the resolver collected since the last call.
+    fn flush_warnings(&self) {
+        for message in self.resolver.take_warnings() {
+            self.warn(message);
+        }
+    }
+
+    /// The number of manifest items `process_and_copy_assets` handles.
+    fn asset_count(&self) -> usize {
+        self.epub.manifest().items.iter()
+            .filter(|item| !matches!(item.media_type.as_str(), "application/xhtml+xml" | "text/html"))
+            .count()
+    }
+
+    /// Reports the `index`-th of `total` assets as done, after any warnings it caused.
+    fn asset_copied(&self, item: &ManifestItem, index: usize, total: usize) {
+        self.flush_warnings();
+        let path = self.resolver.site_path(&item.href).cloned().unwrap_or_else(|| PathBuf::from(&item.href));
+        self.emit(ProgressEvent::AssetCopied { path, index, total });
+    }
+
+    /// Reports the spine item at `spine_index` as done, after any warnings it caused.
+    fn chapter_transformed(&self, spine_index: usize) {
+        self.flush_warnings();
+        let item = &self.spine_items[spine_index];
+        let path = self.resolver.site_path(&item.href).cloned().unwrap_or_else(|| PathBuf::from(&item.href));
+        self.emit(ProgressEvent::ChapterTransformed {
+            path,
+            title: self.chapter_title(item, spine_index),
+            index: spine_index + 1,
+            total: self.spine_items.len(),
+        });
+    }
+
     fn prepare_directory(&self) -> Result<()> {
         if self.output_dir.exists() { fs::remove_dir_all(self.output_dir)?; }
-        fs::create_dir_all(self.output_dir.join("assets"))?;
-        fs::create_dir_all(self.output_dir.join("chapters"))?;
+        match self.mode {
+            PageMode::Chapters => {
+                fs::create_dir_all(self.output_dir.join("assets"))?;
+                fs::create_dir_all(self.output_dir.join("chapters"))?;
+            }
+            PageMode::Single => fs::create_dir_all(self.output_dir.join("assets"))?,
+            // Only the assets too large to embed are written, creating their directory as needed.
+            PageMode::SelfContained { .. } => fs::create_dir_all(self.output_dir)?,
+        }
         Ok(())
     }
 
     fn build_path_map_and_spine(&mut self) {
-        let manifest_map: HashMap<_, _> = self.epub.manifest().items.iter()
-            .map(|item| (normalize_path_key(&item.id), item.clone()))
-            .collect();
-
-        self.spine_items = self.epub.spine().item_refs.iter()
-            .filter_map(|item_ref| manifest_map.get(&normalize_path_key(&item_ref.idref)).cloned())
-            .collect();
+        // Non-linear items are still generated, but kept out of the prev/next chain.
+        self.spine_items.clear();
+        self.reading_order.clear();
+        for item_ref in &self.epub.spine().item_refs {
+            if let Some(item) = self.epub.manifest().spine_item(&item_ref.idref) {
+                if item_ref.is_linear() {
+                    self.reading_order.push(self.spine_items.len());
+                }
+                self.spine_items.push(item.clone());
+            }
+        }
 
+        // Keep the book's files clear of the ones the site gets from its templates.
+        let mut reserved = vec!["index.html".to_string(), format!("assets/{}", STYLES_CSS_FILENAME)];
+        reserved.extend(self.templates.static_files().iter().map(|path| format!("assets/{}", path.to_slash_lossy())));
+        let reserved: Vec<&str> = reserved.iter().map(String::as_str).collect();
+        let mut planner = PathPlanner::new(self.layout, &reserved);
         for item in &self.epub.manifest().items {
-            let key = normalize_path_key(&item.href);
-            let new_path = match item.media_type.as_str() {
-                "application/xhtml+xml" | "text/html" => {
-                    let mut p = PathBuf::from("chapters");
-                    let file_path = PathBuf::from(&key);
-                    p.push(file_path.file_name().unwrap_or_else(|| "unknown.html".as_ref()));
-                    p.set_extension("html");
-                    p
-                }
-                _ => {
-                    let mut p = PathBuf::from("assets");
-                    let file_path = PathBuf::from(&key);
-                    p.push(file_path.file_name().unwrap_or_else(|| "unknown".as_ref()));
-                    p
-                }
-            };
-            self.path_map.insert(key, new_path);
+            let is_document = matches!(item.media_type.as_str(), "application/xhtml+xml" | "text/html");
+            let (new_path, renamed) = planner.assign(&PathResolver::key(&item.href), is_document);
+            // On a single page, documents only lend their names to section ids.
+            if renamed && (!is_document || self.mode == PageMode::Chapters) {
+                self.warn(format!("'{}' clashes with another file, writing it to '{}'", item.href, new_path.to_slash_lossy()));
+            }
+            self.resolver.insert(&item.href, new_path);
+        }
+
+        // The first TOC entry pointing into a document names the whole chapter.
+        self.chapter_titles = toc::document_titles(self.epub.toc());
+    }
+
+    /// Returns the display title for a spine item, preferring its TOC label.
+    fn chapter_title(&self, item: &ManifestItem, spine_index: usize) -> String {
+        if let Some(title) = self.chapter_titles.get(&PathResolver::key(&item.href)) {
+            return title.clone();
+        }
+        let raw_title = self.resolver.site_path(&item.href)
+            .and_then(|site_path| site_path.file_stem())
+            .map(|stem| stem.to_string_lossy().into_owned())
+            .unwrap_or_default();
+        if raw_title.is_empty() || raw_title == "unknown" {
+            format!("第 {} 章", spine_index + 1)
+        } else {
+            raw_title
         }
     }
 
     fn process_and_copy_assets(&mut self) -> Result<()> {
+        if let PageMode::SelfContained { inline_limit } = self.mode {
+            return self.embed_assets(inline_limit);
+        }
         let manifest_items = self.epub.manifest().items.clone();
+        let total = self.asset_count();
+        let mut copied = 0;
         for item in &manifest_items {
             match item.media_type.as_str() {
-                "application/xhtml+xml" | "text/html" => {}
+                "application/xhtml+xml" | "text/html" => continue,
                 "text/css" => self.transform_and_copy_css(item)?,
                 _ => self.copy_single_asset(item)?,
             }
+            copied += 1;
+            self.asset_copied(item, copied, total);
         }
         Ok(())
     }
     
     fn copy_single_asset(&mut self, item: &ManifestItem) -> Result<()> {
-        let key = normalize_path_key(&item.href);
-        if let Some(dest_rel_path) = self.path_map.get(&key) {
+        if let Some(dest_rel_path) = self.resolver.site_path(&item.href) {
             let dest_path = self.output_dir.join(dest_rel_path);
             if let Some(parent) = dest_path.parent() { fs::create_dir_all(parent)?; }
             let mut archive_file = self.epub.read_by_manifest_item(item)?;
@@ -117,182 +480,682 @@ impl<'a> SiteGenerator<'a> {
         let mut original_css = String::new();
         self.epub.read_by_manifest_item(item)?.read_to_string(&mut original_css)?;
 
-        let key = normalize_path_key(&item.href);
-        let source_epub_path = PathBuf::from(&key);
-        let source_epub_dir = source_epub_path.parent().unwrap_or_else(|| Path::new(""));
+        let key = PathResolver::key(&item.href);
+        let from = LinkSource { document: &key, attribute: CSS_URL };
         
-        let source_site_path = self.path_map.get(&key).ok_or_else(|| AppError::InvalidEpubFormat(format!("Path not found in map for CSS href: {}", key)))?;
+        let source_site_path = self.resolver.site_path(&item.href).ok_or_else(|| AppError::InvalidEpubFormat(format!("Path not found in map for CSS href: {}", key)))?;
 
-        let path_map = self.path_map.clone();
-        let source_epub_dir = source_epub_dir.to_path_buf();
-        let source_site_path = source_site_path.to_path_buf();
+        let rewritten_css = css::rewrite_urls(&original_css, |url| self.resolver.rewrite_link(url, from, source_site_path));
 
-        let rewritten_css = CSS_URL_RE.replace_all(&original_css, |caps: &Captures| {
-            let captured_content = &caps[1];
-            let original_url = captured_content.trim_matches(|c| c == '\'' || c == '"');
-            let new_url = rewrite_link(original_url, &source_epub_dir, &source_site_path, &path_map).unwrap_or_else(|| original_url.to_string());
-            format!("url('{}')", new_url)
-        });
+        let dest_path = self.output_dir.join(source_site_path);
+        if let Some(parent) = dest_path.parent() { fs::create_dir_all(parent)?; }
+        let mut dest_file = fs::File::create(&dest_path)?;
+        dest_file.write_all(rewritten_css.as_bytes())?;
+        Ok(())
+    }
 
-        if let Some(dest_rel_path) = self.path_map.get(&key) {
-            let dest_path = self.output_dir.join(dest_rel_path);
-            let mut dest_file = fs::File::create(&dest_path)?;
-            dest_file.write_all(rewritten_css.as_bytes())?;
+    /// Reads the assets of a self-contained build into `data:` URIs. Files
+    /// over `inline_limit` bytes are written to the site as usual, and linked.
+    fn embed_assets(&mut self, inline_limit: u64) -> Result<()> {
+        let mut inline_assets = InlineAssets::default();
+        let mut stylesheets = Vec::new();
+        let total = self.asset_count();
+        let mut copied = 0;
+        for item in self.epub.manifest().items.clone() {
+            let Some(site_path) = self.resolver.site_path(&item.href).cloned() else { continue };
+            match item.media_type.as_str() {
+                "application/xhtml+xml" | "text/html" => {}
+                "text/css" => {
+                    let mut source = String::new();
+                    self.epub.read_by_manifest_item(&item)?.read_to_string(&mut source)?;
+                    stylesheets.push((item, site_path, source));
+                }
+                _ => {
+                    let mut data = Vec::new();
+                    self.epub.read_by_manifest_item(&item)?.read_to_end(&mut data)?;
+                    if data.len() as u64 <= inline_limit {
+                        inline_assets.uris.insert(site_path, data_uri(&item.media_type, &data));
+                    } else {
+                        self.write_site_file(&site_path, &data)?;
+                        inline_assets.too_large.push(OversizedAsset { site_path, size: data.len() as u64 });
+                    }
+                    copied += 1;
+                    self.asset_copied(&item, copied, total);
+                }
+            }
         }
+
+        // Style sheets come last, so the fonts and images they use are already
+        // embedded, and each one waits for the sheets it @imports.
+        let mut linked_as_files = HashSet::new();
+        while !stylesheets.is_empty() {
+            let pending: HashSet<&PathBuf> = stylesheets.iter().map(|(_, site_path, _)| site_path).collect();
+            let references: Vec<Vec<PathBuf>> = stylesheets.iter().map(|(item, _, source)| self.css_references(item, source)).collect();
+            let is_pending = |target: &PathBuf, site_path: &PathBuf| target != site_path && pending.contains(target);
+            let ready = stylesheets.iter().zip(&references)
+                .position(|((_, site_path, _), targets)| !targets.iter().any(|target| is_pending(target, site_path)));
+            let ready = match ready {
+                Some(ready) => ready,
+                None => {
+                    // Sheets that import each other: embed one, and its links to the
+                    // others stay file links, so those are written to the site as well.
+                    let site_path = &stylesheets[0].1;
+                    linked_as_files.extend(references[0].iter().filter(|target| is_pending(target, site_path)).cloned());
+                    0
+                }
+            };
+            let (item, site_path, source) = stylesheets.remove(ready);
+            let key = PathResolver::key(&item.href);
+            let from = LinkSource { document: &key, attribute: CSS_URL };
+            let file_css = || css::rewrite_urls(&source, |url| inline_assets.rewrite_link(&self.resolver, url, from, &site_path));
+
+            // Embedded, the sheet's links are relative to the page rather than to the sheet.
+            let page_css = css::rewrite_urls(&source, |url| inline_assets.rewrite_link(&self.resolver, url, from, Path::new("index.html")));
+            if page_css.len() as u64 <= inline_limit {
+                if linked_as_files.contains(&site_path) {
+                    self.write_site_file(&site_path, file_css().as_bytes())?;
+                }
+                inline_assets.uris.insert(site_path.clone(), data_uri("text/css", page_css.as_bytes()));
+                inline_assets.styles.insert(site_path, page_css);
+            } else {
+                let file_css = file_css();
+                self.write_site_file(&site_path, file_css.as_bytes())?;
+                inline_assets.too_large.push(OversizedAsset { site_path, size: file_css.len() as u64 });
+            }
+            copied += 1;
+            self.asset_copied(&item, copied, total);
+        }
+        self.inline_assets = Some(inline_assets);
+        Ok(())
+    }
+
+    /// The site paths of the files a style sheet links to.
+    fn css_references(&self, item: &ManifestItem, source: &str) -> Vec<PathBuf> {
+        let key = PathResolver::key(&item.href);
+        let references = RefCell::new(Vec::new());
+        css::rewrite_urls(source, |url| {
+            if let Some((target_site_path, _)) = self.resolver.resolve(url, LinkSource { document: &key, attribute: CSS_URL }) {
+                references.borrow_mut().push(target_site_path.clone());
+            }
+            None
+        });
+        references.into_inner()
+    }
+
+    fn write_site_file(&self, site_path: &Path, data: &[u8]) -> Result<()> {
+        let dest_path = self.output_dir.join(site_path);
+        if let Some(parent) = dest_path.parent() { fs::create_dir_all(parent)?; }
+        fs::write(dest_path, data)?;
         Ok(())
     }
 
     fn copy_static_assets(&self) -> Result<()> {
-        let css_path = self.output_dir.join("assets").join(STYLES_CSS_FILENAME);
-        fs::write(css_path, STYLES_CSS)?;
+        let self_contained = matches!(self.mode, PageMode::SelfContained { .. });
+        // A self-contained page has the template's styles in it.
+        if !self_contained {
+            fs::write(self.output_dir.join("assets").join(STYLES_CSS_FILENAME), STYLES_CSS)?;
+        }
+        // The template directory's own files, which may replace the default styles.
+        for relative_path in self.templates.static_files() {
+            if self_contained && relative_path == Path::new(STYLES_CSS_FILENAME) {
+                continue;
+            }
+            self.write_site_file(&Path::new("assets").join(relative_path), &self.templates.read_static_file(relative_path)?)?;
+        }
         Ok(())
     }
 
+    /// The styles of the page template: the template directory's `styles.css`, or the default ones.
+    fn template_styles(&self) -> Result<String> {
+        let path = Path::new(STYLES_CSS_FILENAME);
+        if self.templates.static_files().iter().any(|file| file == path) {
+            return Ok(String::from_utf8_lossy(&self.templates.read_static_file(path)?).into_owned());
+        }
+        Ok(STYLES_CSS.to_string())
+    }
+
     fn transform_content_documents(&mut self) -> Result<()> {
-        for (i, item) in self.spine_items.clone().iter().enumerate() { self.transform_html_file(item, i)?; }
+        for (i, item) in self.spine_items.clone().iter().enumerate() {
+            self.transform_html_file(item, i)?;
+            self.chapter_transformed(i);
+        }
         Ok(())
     }
 
-    fn generate_toc(&mut self) -> Result<()> {
-        let book_title = self.epub.metadata().title.first().cloned().unwrap_or_else(|| "目录".to_string());
-        let mut toc_html = format!("<h1 class=\"toc-title\">{}</h1>\n<ul class=\"toc\">", book_title);
+    fn generate_toc(&self) -> Result<()> {
+        self.write_index("", String::new())
+    }
+
+    /// Renders `index.html`: the cover and table of contents, followed by
+    /// `body`, which holds the chapters in a single-page build.
+    fn write_index(&self, extra_head: &str, body: String) -> Result<()> {
+        let page = Path::new("index.html");
+        let book = self.book_info();
+
+        // Social preview tags, so links to the site show the book's title and cover.
+        let mut head_html = format!("<meta property=\"og:title\" content=\"{}\">", escape(&book.title));
+        // Crawlers do not resolve relative URLs, so without a base URL there is no preview
+        // image, and an embedded cover has no URL for previews to fetch.
+        if let Some(cover_path) = self.cover_site_path()
+            && let Some(base_url) = self.base_url.as_deref()
+            && !self.inline_assets.as_ref().is_some_and(|inline_assets| inline_assets.uris.contains_key(cover_path)) {
+            let cover_path = cover_path.to_slash_lossy();
+            let encoded_path: Vec<_> = cover_path.split('/').map(urlencoding::encode).collect();
+            let image_url = format!("{}/{}", base_url.trim_end_matches('/'), encoded_path.join("/"));
+            head_html.push_str(&format!(
+                "\n    <meta property=\"og:image\" content=\"{}\">\n    <meta name=\"twitter:card\" content=\"summary_large_image\">",
+                escape(&image_url)
+            ));
+        }
+        head_html.push_str(extra_head);
+        let inline_styles = match self.mode {
+            PageMode::SelfContained { .. } => Some(Value::from_safe_string(self.template_styles()?)),
+            _ => None,
+        };
+
+        let html = self.templates.render(INDEX_TEMPLATE, context! {
+            title => &book.title,
+            head => Value::from_safe_string(head_html),
+            body => Value::from_safe_string(body),
+            toc => Serde(self.toc_items(page)),
+            single_page => self.mode != PageMode::Chapters,
+            inline_styles,
+            ..self.common_context(page)
+        })?;
+        fs::write(self.output_dir.join(page), html)?;
+        Ok(())
+    }
+
+    /// The variables every page template gets, with links relative to `page`.
+    fn common_context(&self, page: &Path) -> Value {
+        context! {
+            book => Serde(self.book_info()),
+            dir => self.text_direction(),
+            nav => !self.no_nav,
+            cover => self.cover_link(page),
+            styles_path => self.relative_link(&Path::new("assets").join(STYLES_CSS_FILENAME), page),
+            assets_path => self.relative_link(Path::new("assets"), page),
+            index_path => self.relative_link(Path::new("index.html"), page),
+        }
+    }
+
+    fn book_info(&self) -> BookInfo {
+        let metadata = self.epub.metadata();
+        BookInfo {
+            title: metadata.title().map(str::to_string).unwrap_or_else(|| "目录".to_string()),
+            // Creators without a role are taken to be authors, as they usually are.
+            authors: metadata.creator.iter()
+                .filter(|creator| metadata.role_of(creator).is_none_or(|role| role == "aut"))
+                .map(|creator| creator.name.trim().to_string())
+                .collect(),
+            language: metadata.language.first().map(|language| language.trim().to_string()),
+            publisher: metadata.publisher.first().map(|publisher| publisher.value.trim().to_string()),
+            description: metadata.description.first().map(|description| description.value.trim().to_string()),
+            identifier: metadata.identifier.first().map(|identifier| identifier.value.trim().to_string()),
+        }
+    }
+
+    fn cover_site_path(&self) -> Option<&PathBuf> {
+        self.epub.cover().and_then(|cover| self.resolver.site_path(&cover.href))
+    }
+
+    /// The link from `page` to the cover image; its `data:` URI when it is embedded.
+    fn cover_link(&self, page: &Path) -> Option<String> {
+        let cover_path = self.cover_site_path()?;
+        if let Some(uri) = self.inline_assets.as_ref().and_then(|inline_assets| inline_assets.uris.get(cover_path)) {
+            return Some(uri.clone());
+        }
+        Some(self.relative_link(cover_path, page))
+    }
+
+    /// The table of contents as seen from `page`: the book's own, or else the
+    /// spine in reading order.
+    fn toc_items(&self, page: &Path) -> Vec<TocItem> {
+        if !self.epub.toc().is_empty() {
+            return self.toc_entries(self.epub.toc(), page);
+        }
+        self.reading_order.iter()
+            .filter_map(|&index| {
+                let item = &self.spine_items[index];
+                let site_path = self.resolver.site_path(&item.href)?;
+                Some(TocItem {
+                    label: self.chapter_title(item, index),
+                    href: Some(self.page_link(site_path, None, page)),
+                    current: site_path == page,
+                    children: Vec::new(),
+                })
+            })
+            .collect()
+    }
 
+    /// Converts TOC entries, linking those whose target is part of the site.
+    fn toc_entries(&self, entries: &[TocEntry], page: &Path) -> Vec<TocItem> {
+        entries.iter()
+            .map(|entry| {
+                let site_path = entry.href.as_ref().and_then(|href| self.resolver.site_path(href));
+                TocItem {
+                    label: entry.label.clone(),
+                    href: site_path.map(|site_path| self.page_link(site_path, entry.fragment.as_deref(), page)),
+                    current: site_path.is_some_and(|site_path| site_path == page),
+                    children: self.toc_entries(&entry.children, page),
+                }
+            })
+            .collect()
+    }
+
+    /// Links `page` to a page of the site, or to its section in a single-page build.
+    fn page_link(&self, site_path: &Path, fragment: Option<&str>, page: &Path) -> String {
+        if let Some(anchors) = &self.page_anchors
+            && let Some(&index) = anchors.documents.get(site_path) {
+            return anchors.link(index, fragment);
+        }
+        let mut link = self.relative_link(site_path, page);
+        if let Some(fragment) = fragment {
+            link.push('#');
+            link.push_str(fragment);
+        }
+        link
+    }
+
+    /// The link from `page` to `target`, both site paths.
+    fn relative_link(&self, target: &Path, page: &Path) -> String {
+        let page_dir = page.parent().unwrap_or_else(|| Path::new(""));
+        pathdiff::diff_paths(target, page_dir).unwrap_or_else(|| target.to_path_buf()).to_slash_lossy().into_owned()
+    }
+
+    /// Writes the whole book to `index.html`: the cover and table of contents,
+    /// followed by every spine document in its own `<section class="chapter">`.
+    fn generate_single_page(&mut self) -> Result<()> {
+        let mut documents = Vec::new();
+        for item in &self.spine_items {
+            let mut bytes = Vec::new();
+            self.epub.read_by_manifest_item(item)?.read_to_end(&mut bytes)?;
+            documents.push(String::from_utf8_lossy(&bytes).into_owned());
+        }
+        self.page_anchors = Some(self.plan_page_anchors(&documents)?);
+        let Some(anchors) = &self.page_anchors else { return Ok(()) };
+
+        let stylesheets = Rc::new(RefCell::new(Vec::new()));
+        let mut chapters_html = String::new();
+        for (index, (item, content)) in self.spine_items.iter().zip(&documents).enumerate() {
+            let key = PathResolver::key(&item.href);
+            let page = PageContext { anchors, index, stylesheets: stylesheets.clone() };
+            let settings = self.get_html_rewrite_settings(&key, Path::new("index.html"), Some(page));
+            let rewritten_body_str = rewrite_str(content, settings)?;
+            chapters_html.push_str(&format!(
+                "<section class=\"chapter\" id=\"{}\">\n{}\n</section>\n",
+                escape(&anchors.sections[index]),
+                rewritten_body_str.trim()
+            ));
+            self.chapter_transformed(index);
+        }
+
+        let stylesheets_html: String = stylesheets.borrow().iter().map(|stylesheet| format!("\n    {}", stylesheet)).collect();
+        self.write_index(&stylesheets_html, chapters_html)
+    }
+
+    /// Gives every spine item a section id and makes the element ids of all
+    /// documents unique, in spine order.
+    fn plan_page_anchors(&self, documents: &[String]) -> Result<PageAnchors> {
+        let mut anchors = PageAnchors::default();
+        for id in TEMPLATE_IDS {
+            anchors.claim(id);
+        }
+        // Sections are named first, so an element id can never take a chapter's place.
         for (index, item) in self.spine_items.iter().enumerate() {
-            let key = normalize_path_key(&item.href);
-            if let Some(site_path) = self.path_map.get(&key) {
-                let chapter_title = site_path.file_stem().unwrap_or_default().to_string_lossy();
-                let display_title = if chapter_title.is_empty() || chapter_title == "unknown" {
-                    format!("第 {} 章", index + 1)
-                } else {
-                    chapter_title.to_string()
-                };
-                toc_html.push_str(&format!("<li><a href=\"{}\">{}</a></li>\n", site_path.to_slash_lossy(), display_title));
+            let site_path = self.resolver.site_path(&item.href);
+            let stem = site_path
+                .and_then(|site_path| site_path.file_stem())
+                .map(|stem| stem.to_string_lossy().split_whitespace().collect::<Vec<_>>().join("-"))
+                .unwrap_or_default();
+            let section = anchors.claim(&format!("chapter-{}", stem));
+            anchors.sections.push(section);
+            if let Some(site_path) = site_path {
+                anchors.documents.entry(site_path.clone()).or_insert(index);
             }
         }
-        toc_html.push_str("</ul>");
 
-        let styles_path = format!("assets/{}", STYLES_CSS_FILENAME);
-        
-        // 为TOC页面添加特殊样式处理
-        let final_html = TEMPLATE_HTML
-            .replace("{{ title }}", &book_title)
-            .replace("{{ body }}", &toc_html)
-            .replace("{{ nav }}", "")
-            .replace("{{ styles_path }}", &styles_path)
-            .replace("<main class=\"content\">", "<main class=\"content toc-page\">");
-
-        fs::write(self.output_dir.join("index.html"), final_html)?;
-        Ok(())
+        for (index, content) in documents.iter().enumerate() {
+            let ids = RefCell::new(Vec::new());
+            rewrite_str(content, RewriteStrSettings {
+                element_content_handlers: vec![element!("[id]", |el| {
+                    ids.borrow_mut().extend(el.get_attribute("id"));
+                    Ok(())
+                })],
+                ..RewriteStrSettings::default()
+            })?;
+            for id in ids.into_inner() {
+                let key = (index, id);
+                if anchors.renamed.contains_key(&key) {
+                    continue; // Repeated within the document; all of them keep the first name.
+                }
+                let page_id = anchors.claim(&key.1);
+                anchors.renamed.insert(key, page_id);
+            }
+        }
+        // Only the ids that changed need to be remembered.
+        anchors.renamed.retain(|(_, id), page_id| id != page_id);
+        Ok(anchors)
     }
 
     fn transform_html_file(&mut self, item: &ManifestItem, spine_index: usize) -> Result<()> {
         let mut original_html_bytes = Vec::new();
         self.epub.read_by_manifest_item(item)?.read_to_end(&mut original_html_bytes)?;
         
-        let key = normalize_path_key(&item.href);
-        let source_epub_path = PathBuf::from(&key);
-        let source_site_path = self.path_map.get(&key).ok_or_else(|| AppError::InvalidEpubFormat(format!("Path not found in map for href: {}", key)))?.clone();
-        let settings = self.get_html_rewrite_settings(&source_epub_path, &source_site_path);
+        let key = PathResolver::key(&item.href);
+        let source_site_path = self.resolver.site_path(&item.href).ok_or_else(|| AppError::InvalidEpubFormat(format!("Path not found in map for href: {}", key)))?.clone();
+        let settings = self.get_html_rewrite_settings(&key, &source_site_path, None);
 
         let rewritten_body_str = rewrite_str(&String::from_utf8_lossy(&original_html_bytes), settings)?;
-        
-        let nav_html = self.create_nav_html(spine_index, &source_site_path);
-        let source_site_dir = source_site_path.parent().unwrap_or_else(|| Path::new(""));
-        let styles_rel_path = pathdiff::diff_paths(&self.output_dir.join("assets").join(STYLES_CSS_FILENAME), &self.output_dir.join(source_site_dir)).unwrap_or_else(|| PathBuf::from(STYLES_CSS_FILENAME));
-        
-        // 生成更友好的章节标题
-        let raw_title = source_site_path.file_stem().unwrap_or_default().to_string_lossy();
-        let title = if raw_title.is_empty() || raw_title == "unknown" {
-            format!("第 {} 章", spine_index + 1)
-        } else {
-            raw_title.to_string()
-        };
-        
-        let final_html = TEMPLATE_HTML
-            .replace("{{ title }}", &title)
-            .replace("{{ body }}", &rewritten_body_str)
-            .replace("{{ nav }}", &nav_html)
-            .replace("{{ styles_path }}", &styles_rel_path.to_string_lossy());
 
-        fs::write(self.output_dir.join(&source_site_path), final_html)?;
+        // Non-linear items have no position in the reading order, so they only link back to the TOC.
+        let position = self.reading_order.iter().position(|&index| index == spine_index);
+        let prev = position.and_then(|p| p.checked_sub(1)).map(|p| self.reading_order[p]);
+        let next = position.and_then(|p| self.reading_order.get(p + 1).copied());
+
+        let final_html = self.templates.render(CHAPTER_TEMPLATE, context! {
+            title => self.chapter_title(item, spine_index),
+            body => Value::from_safe_string(rewritten_body_str),
+            toc => Serde(self.toc_items(&source_site_path)),
+            prev => Serde(prev.and_then(|index| self.chapter_link(index, &source_site_path))),
+            next => Serde(next.and_then(|index| self.chapter_link(index, &source_site_path))),
+            spine_index,
+            spine_length => self.spine_items.len(),
+            // How much of the reading order is done at the end of the chapter, in percent.
+            progress => position.map(|p| (p + 1) * 100 / self.reading_order.len()),
+            ..self.common_context(&source_site_path)
+        })?;
+
+        let dest_path = self.output_dir.join(&source_site_path);
+        if let Some(parent) = dest_path.parent() { fs::create_dir_all(parent)?; }
+        fs::write(dest_path, final_html)?;
         Ok(())
     }
 
+    /// A link from `page` to the page of a spine item, titled with its chapter title.
+    fn chapter_link(&self, spine_index: usize, page: &Path) -> Option<PageLink> {
+        let item = &self.spine_items[spine_index];
+        let site_path = self.resolver.site_path(&item.href)?;
+        Some(PageLink { title: self.chapter_title(item, spine_index), href: self.relative_link(site_path, page) })
+    }
+
     /// Creates the settings for lol_html to rewrite links and structure.
-    fn get_html_rewrite_settings(&self, source_epub_path: &Path, source_site_path: &Path) -> RewriteStrSettings {
-        let path_map = self.path_map.clone();
-        let source_epub_dir = source_epub_path.parent().unwrap_or_else(|| Path::new("")).to_path_buf();
-        let source_site_path_clone = source_site_path.to_path_buf();
+    /// `document` is the `PathResolver::key` of the document being rewritten.
+    /// With a `page`, the document is prepared to become a section of the single page.
+    fn get_html_rewrite_settings<'s>(&'s self, document: &str, source_site_path: &Path, page: Option<PageContext<'s>>) -> RewriteStrSettings<'s, 's> {
+        let links = Rc::new(LinkRewriter {
+            resolver: &self.resolver,
+            document: document.to_string(),
+            source_site_path: source_site_path.to_path_buf(),
+            page: page.as_ref().map(|page| (page.anchors, page.index)),
+            inline_assets: self.inline_assets.as_ref(),
+        });
 
-        RewriteStrSettings {
-            element_content_handlers: vec![
-                // Remove the original title, we'll use our template's title.
-                element!("title", |el| { el.remove(); Ok(()) }),
-
-                // Rewrite links in <a> and <link> tags.
-                element!("a[href], link[href]", {
-                    let path_map = path_map.clone();
-                    let source_epub_dir = source_epub_dir.clone();
-                    let source_site_path_clone = source_site_path_clone.clone();
-                    move |el: &mut Element| {
-                        if let Some(href) = el.get_attribute("href") {
-                            if let Some(new_link) = rewrite_link(&href, &source_epub_dir, &source_site_path_clone, &path_map) {
-                                el.set_attribute("href", &new_link)?;
-                            }
-                        }
-                        Ok(())
-                    }
-                }),
-                
-                // Rewrite sources in <img>, <audio>, <video>, etc.
-                element!("img[src], audio[src], video[src], source[src]", {
-                    let path_map = path_map.clone();
-                    let source_epub_dir = source_epub_dir.clone();
-                    let source_site_path_clone = source_site_path_clone.clone();
-                    move |el: &mut Element| {
-                        if let Some(src) = el.get_attribute("src") {
-                            if let Some(new_link) = rewrite_link(&src, &source_epub_dir, &source_site_path_clone, &path_map) {
-                                el.set_attribute("src", &new_link)?;
-                            }
-                        }
-                        Ok(())
-                    }
-                }),
+        let mut document_handlers = Vec::new();
+        let mut handlers = vec![
+            // Remove the original title, we'll use our template's title.
+            element!("title", |el| { el.remove(); Ok(()) }),
+        ];
 
-                // Extract only the content of the <body> tag.
-                element!("body", |el| { el.remove_and_keep_content(); Ok(()) })
-            ], ..RewriteStrSettings::default()
+        if let Some(page) = &page {
+            // The page has a single head: style sheets move there, once each,
+            // and the rest of every document's head goes away (see below).
+            // This runs before the link rewriting, to see the original link.
+            let links = links.clone();
+            let stylesheets = page.stylesheets.clone();
+            handlers.push(element!("head link", move |el| {
+                let is_stylesheet = el.get_attribute("rel").is_some_and(|rel| rel.split_whitespace().any(|r| r.eq_ignore_ascii_case("stylesheet")));
+                if is_stylesheet && let Some(href) = el.get_attribute("href") {
+                    let markup = match links.embedded_style(&href) {
+                        Some(style) => format!("<style>\n{}\n</style>", escape_style_end(style)),
+                        None => format!("<link rel=\"stylesheet\" href=\"{}\">", escape(links.rewrite(&href, "href").unwrap_or(href))),
+                    };
+                    let mut stylesheets = stylesheets.borrow_mut();
+                    if !stylesheets.contains(&markup) {
+                        stylesheets.push(markup);
+                    }
+                }
+                el.remove();
+                Ok(())
+            }));
         }
-    }
 
+        // Attributes holding a single URL.
+        for (selector, attribute) in URL_ATTRIBUTES {
+            let links = links.clone();
+            handlers.push(element!(selector, move |el: &mut Element| {
+                if let Some(url) = el.get_attribute(attribute)
+                    && let Some(new_link) = links.rewrite(&url, attribute) {
+                    el.set_attribute(attribute, &new_link)?;
+                }
+                Ok(())
+            }));
+        }
 
-    fn create_nav_html(&self, spine_index: usize, current_site_path: &Path) -> String {
-        if self.no_nav { return String::new(); }
+        handlers.push(element!("img[srcset], source[srcset]", {
+            let links = links.clone();
+            move |el: &mut Element| {
+                if let Some(srcset) = el.get_attribute("srcset") {
+                    el.set_attribute("srcset", &rewrite_srcset(&srcset, |url| links.rewrite(url, "srcset")))?;
+                }
+                Ok(())
+            }
+        }));
 
-        let current_dir = current_site_path.parent().unwrap_or_else(|| Path::new(""));
-        let mut parts = Vec::new();
-        
-        if spine_index > 0 {
-            if let Some(prev_item) = self.spine_items.get(spine_index - 1) {
-                if let Some(target_path) = self.path_map.get(&normalize_path_key(&prev_item.href)) {
-                    let rel_path = pathdiff::diff_paths(target_path, current_dir).unwrap_or_else(|| target_path.clone());
-                    parts.push(format!("<a href=\"{}\" class=\"nav-prev\">« 上一章</a>", rel_path.to_string_lossy()));
+        // CSS inside the document: style attributes and <style> blocks.
+        handlers.push(element!("[style]", {
+            let links = links.clone();
+            move |el: &mut Element| {
+                if let Some(style) = el.get_attribute("style") {
+                    el.set_attribute("style", &css::rewrite_urls(&style, |url| links.rewrite(url, "style")))?;
+                }
+                Ok(())
+            }
+        }));
+        handlers.push(text!("style", {
+            // A block may arrive in several chunks; rewrite it once it is complete.
+            let mut source = String::new();
+            move |chunk| {
+                source.push_str(chunk.as_str());
+                if chunk.last_in_text_node() {
+                    chunk.replace(&css::rewrite_urls(&source, |url| links.rewrite(url, CSS_URL)), ContentType::Html);
+                    source.clear();
+                } else {
+                    chunk.remove();
                 }
+                Ok(())
             }
-        } else { parts.push("<span></span>".to_string()); }
+        }));
 
-        let toc_path = pathdiff::diff_paths(self.output_dir.join("index.html"), self.output_dir.join(current_dir)).unwrap_or_else(|| PathBuf::from("../index.html"));
-        parts.push(format!("<a href=\"{}\" class=\"nav-toc\">📚 目录</a>", toc_path.to_string_lossy()));
+        // Extract only the content of the <body> tag.
+        handlers.push(element!("body", |el| { el.remove_and_keep_content(); Ok(()) }));
 
-        if spine_index < self.spine_items.len() - 1 {
-            if let Some(next_item) = self.spine_items.get(spine_index + 1) {
-                if let Some(target_path) = self.path_map.get(&normalize_path_key(&next_item.href)) {
-                     let rel_path = pathdiff::diff_paths(target_path, current_dir).unwrap_or_else(|| target_path.clone());
-                    parts.push(format!("<a href=\"{}\" class=\"nav-next\">下一章 »</a>", rel_path.to_string_lossy()));
+        if let Some(PageContext { anchors, index, .. }) = page {
+            // Ids follow the renames that keep them unique across the page.
+            handlers.push(element!("[id]", move |el| {
+                if let Some(id) = el.get_attribute("id") {
+                    el.set_attribute("id", &anchors.id(index, &id))?;
                 }
-            }
-        } else { parts.push("<span></span>".to_string()); }
+                Ok(())
+            }));
+            handlers.push(element!("head meta, head base", |el| { el.remove(); Ok(()) }));
+            handlers.push(element!("html, head", |el| { el.remove_and_keep_content(); Ok(()) }));
+            document_handlers.push(doctype!(|doctype| { doctype.remove(); Ok(()) }));
+            // An XML declaration reads as a comment to an HTML parser.
+            document_handlers.push(doc_comments!(|comment| {
+                if comment.text().starts_with("?xml") {
+                    comment.remove();
+                }
+                Ok(())
+            }));
+        }
+
+        RewriteStrSettings {
+            element_content_handlers: handlers,
+            document_content_handlers: document_handlers,
+            ..RewriteStrSettings::default()
+        }
+    }
+
+    /// The value of the `dir` attribute on generated pages.
+    fn text_direction(&self) -> &'static str {
+        if self.epub.spine().is_rtl() { "rtl" } else { "ltr" }
+    }
+}
+
+/// Encodes `data` as a `data:` URI of the given media type.
+fn data_uri(media_type: &str, data: &[u8]) -> String {
+    format!("data:{};base64,{}", media_type, BASE64_STANDARD.encode(data))
+}
+
+/// Escapes every `</style` (in any case) in a style sheet that goes into a
+/// `<style>` element, since HTML parsers end the element there.
+fn escape_style_end(style: &str) -> String {
+    let lowercase = style.to_ascii_lowercase();
+    let mut escaped = String::with_capacity(style.len());
+    let mut copied = 0;
+    for (start, _) in lowercase.match_indices("</style") {
+        escaped.push_str(&style[copied..=start]);
+        escaped.push('\\');
+        copied = start + 1;
+    }
+    escaped.push_str(&style[copied..]);
+    escaped
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::epub::packer;
+
+    const CONTAINER: &str = r#"<?xml version="1.0"?>
+<container version="1.0" xmlns="urn:oasis:names:tc:opendocument:xmlns:container">
+  <rootfiles><rootfile full-path="OEBPS/content.opf" media-type="application/oebps-package+xml"/></rootfiles>
+</container>
+"#;
+
+    fn package(manifest: &str, spine: &str) -> String {
+        format!(r#"<?xml version="1.0" encoding="UTF-8"?>
+<package xmlns="http://www.idpf.org/2007/opf" unique-identifier="uid" version="3.0">
+  <metadata xmlns:dc="http://purl.org/dc/elements/1.1/">
+    <dc:identifier id="uid">urn:uuid:11111111-2222-3333-4444-555555555555</dc:identifier>
+    <dc:title>Site</dc:title>
+    <dc:language>en</dc:language>
+  </metadata>
+  <manifest>{manifest}</manifest>
+  <spine>{spine}</spine>
+</package>
+"#)
+    }
+
+    fn chapter(head: &str, body: &str) -> String {
+        format!(r#"<?xml version="1.0" encoding="UTF-8"?>
+<html xmlns="http://www.w3.org/1999/xhtml"><head><title>Chapter</title>{head}</head><body>{body}</body></html>
+"#)
+    }
+
+    /// Packs `files` (paths below `OEBPS/`) with the given manifest and spine
+    /// into `dir/book.epub`, and generates a site from it into `dir/site`.
+    fn generate(dir: &Path, manifest: &str, spine: &str, files: &[(&str, &str)], mode: PageMode) -> WebifyReport {
+        let book = dir.join("book");
+        fs::create_dir_all(book.join("META-INF")).unwrap();
+        fs::create_dir_all(book.join("OEBPS")).unwrap();
+        fs::write(book.join("META-INF/container.xml"), CONTAINER).unwrap();
+        fs::write(book.join("OEBPS/content.opf"), package(manifest, spine)).unwrap();
+        for (path, content) in files {
+            fs::write(book.join("OEBPS").join(path), content).unwrap();
+        }
+
+        let packed = dir.join("book.epub");
+        packer::pack_directory(&book, &packed, false).unwrap();
+        let mut epub = Epub::open(&packed).unwrap();
+        let site = dir.join("site");
+        SiteGenerator::new(&mut epub, &site, &WebifyOptions::new().mode(mode)).unwrap().run().unwrap()
+    }
+
+    fn temp_dir(name: &str) -> PathBuf {
+        std::env::temp_dir().join(format!("tome-site-generator-{}-{}", name, std::process::id()))
+    }
+
+    const CHAPTER_ITEM: &str = r#"<item id="ch01" href="ch01.xhtml" media-type="application/xhtml+xml"/>"#;
+    const CHAPTER_SPINE: &str = r#"<itemref idref="ch01"/>"#;
+
+    #[test]
+    fn a_single_page_renames_repeated_ids_and_links_to_them_in_page() {
+        let dir = temp_dir("anchors");
+        let manifest = r#"<item id="ch01" href="ch01.xhtml" media-type="application/xhtml+xml"/>
+<item id="ch02" href="ch02.xhtml" media-type="application/xhtml+xml"/>"#;
+        let one = chapter("", r##"<h1 id="sec3">One</h1><p><a href="ch02.xhtml#sec3">next</a> <a href="#sec3">top</a></p>"##);
+        let two = chapter("", r#"<h1 id="sec3">Two</h1>"#);
+        generate(&dir, manifest, r#"<itemref idref="ch01"/><itemref idref="ch02"/>"#, &[("ch01.xhtml", &one), ("ch02.xhtml", &two)], PageMode::Single);
+        let page = fs::read_to_string(dir.join("site/index.html")).unwrap();
+
+        assert!(page.contains(r#"id="sec3">One"#), "{page}");
+        assert!(page.contains(r#"id="sec3-2">Two"#), "{page}");
+        assert!(page.contains(r##"href="#sec3-2">next"##), "{page}");
+        assert!(page.contains(r##"href="#sec3">top"##), "{page}");
+        assert!(!page.contains("ch02.xhtml"), "{page}");
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn assets_up_to_the_inline_limit_become_data_uris() {
+        let dir = temp_dir("inline-limit");
+        let manifest = format!(r#"{CHAPTER_ITEM}
+<item id="small" href="small.png" media-type="image/png"/>
+<item id="large" href="large.png" media-type="image/png"/>"#);
+        let body = chapter("", r#"<img src="small.png" alt=""/><img src="large.png" alt=""/>"#);
+        let large = "x".repeat(101);
+        let report = generate(
+            &dir, &manifest, CHAPTER_SPINE,
+            &[("ch01.xhtml", &body), ("small.png", "png"), ("large.png", &large)],
+            PageMode::SelfContained { inline_limit: 100 },
+        );
+        let page = fs::read_to_string(dir.join("site/index.html")).unwrap();
+
+        assert!(page.contains(r#"src="data:image/png;base64,cG5n""#), "{page}");
+        assert!(page.contains(r#"src="assets/large.png""#), "{page}");
+        assert!(!dir.join("site/assets/small.png").exists());
+        assert_eq!(fs::read_to_string(dir.join("site/assets/large.png")).unwrap(), large);
+        assert_eq!(report.oversized_assets, [OversizedAsset { site_path: PathBuf::from("assets/large.png"), size: 101 }]);
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn embedded_style_sheets_cannot_close_their_style_element() {
+        let dir = temp_dir("style-end");
+        let manifest = format!(r#"{CHAPTER_ITEM}<item id="css" href="style.css" media-type="text/css"/>"#);
+        let body = chapter(r#"<link rel="stylesheet" href="style.css"/>"#, "<p>Text</p>");
+        let style = "p::after { content: \"</STYLE><script>\"; }";
+        generate(&dir, &manifest, CHAPTER_SPINE, &[("ch01.xhtml", &body), ("style.css", style)], PageMode::SelfContained { inline_limit: 1000 });
+        let page = fs::read_to_string(dir.join("site/index.html")).unwrap();
+
+        assert!(page.contains(r#"content: "<\/STYLE><script>";"#), "{page}");
+        assert!(!page.to_ascii_lowercase().contains("</style><script>"), "{page}");
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn style_sheets_that_import_each_other_link_to_files_that_exist() {
+        let dir = temp_dir("import-cycle");
+        let manifest = format!(r#"{CHAPTER_ITEM}
+<item id="a" href="a.css" media-type="text/css"/>
+<item id="b" href="b.css" media-type="text/css"/>"#);
+        let body = chapter(r#"<link rel="stylesheet" href="a.css"/><link rel="stylesheet" href="b.css"/>"#, "<p>Text</p>");
+        let files = [("ch01.xhtml", body.as_str()), ("a.css", "@import \"b.css\";\np { color: red; }"), ("b.css", "@import \"a.css\";\np { margin: 0; }")];
+        let report = generate(&dir, &manifest, CHAPTER_SPINE, &files, PageMode::SelfContained { inline_limit: 1000 });
+        let page = fs::read_to_string(dir.join("site/index.html")).unwrap();
+
+        assert!(report.oversized_assets.is_empty());
+        let linked: Vec<_> = ["a.css", "b.css"].into_iter().filter(|name| page.contains(&format!("\"assets/{}\"", name))).collect();
+        assert_eq!(linked.len(), 1, "{page}");
+        for name in linked {
+            assert!(dir.join("site/assets").join(name).exists(), "assets/{} is linked but was not written", name);
+        }
 
-        parts.join("\n")
+        fs::remove_dir_all(&dir).unwrap();
     }
 }
@@ -0,0 +1,169 @@
+// src/epub/storage.rs
+use std::fs;
+use std::io::{self, BufReader, Read, Seek};
+use std::path::{Component, Path, PathBuf};
+
+use path_slash::PathBufExt;
+use zip::read::ZipFile;
+use zip::ZipArchive;
+
+use crate::epub::packer;
+use crate::error::{AppError, Result};
+
+/// A source an EPUB can be read from: a file, an in-memory buffer, a blob...
+pub trait ReadSeek: Read + Seek + Send {}
+
+impl<T: Read + Seek + Send> ReadSeek for T {}
+
+/// Where the files of a book live: a ZIP archive, or a directory holding an
+/// unpacked copy such as `tome unpack` writes. Files are named by their path
+/// inside the container, e.g. `OEBPS/content.opf`.
+pub(super) enum Storage {
+    // The reader is boxed so that `Epub` is the same type whatever it was opened from.
+    Zip(ZipArchive<Box<dyn ReadSeek>>),
+    Directory {
+        root: PathBuf,
+        /// The files below `root`, hidden ones excepted, sorted.
+        files: Vec<String>,
+    },
+}
+
+impl Storage {
+    /// Opens `path` as a directory if it is one, and as a ZIP archive otherwise.
+    pub(super) fn open(path: &Path) -> Result<Self> {
+        if path.is_dir() {
+            let mut files = Vec::new();
+            packer::collect_files(path, Path::new(""), &mut files)?;
+            let mut files: Vec<String> = files.iter().map(|file| file.to_slash_lossy().into_owned()).collect();
+            files.sort();
+            return Ok(Storage::Directory { root: path.to_path_buf(), files });
+        }
+        let file = fs::File::open(path)?;
+        Self::from_reader(Box::new(BufReader::new(file)), path)
+    }
+
+    /// Reads a ZIP archive; `path` names it in errors.
+    pub(super) fn from_reader(reader: Box<dyn ReadSeek>, path: &Path) -> Result<Self> {
+        ZipArchive::new(reader)
+            .map(Storage::Zip)
+            .map_err(|source| AppError::ZipExtraction { path: path.to_path_buf(), source })
+    }
+
+    pub(super) fn is_directory(&self) -> bool {
+        matches!(self, Storage::Directory { .. })
+    }
+
+    /// The archive, for checks that only make sense on a ZIP file.
+    pub(super) fn archive(&mut self) -> Option<&mut ZipArchive<Box<dyn ReadSeek>>> {
+        match self {
+            Storage::Zip(archive) => Some(archive),
+            Storage::Directory { .. } => None,
+        }
+    }
+
+    /// The names of every file in the book (and, for archives, directory entries, ending in `/`).
+    pub(super) fn file_names(&self) -> Vec<&str> {
+        match self {
+            Storage::Zip(archive) => archive.file_names().collect(),
+            Storage::Directory { files, .. } => files.iter().map(String::as_str).collect(),
+        }
+    }
+
+    pub(super) fn contains(&self, name: &str) -> bool {
+        match self {
+            Storage::Zip(archive) => archive.index_for_name(name).is_some(),
+            Storage::Directory { files, .. } => files.binary_search_by(|file| file.as_str().cmp(name)).is_ok(),
+        }
+    }
+
+    /// Opens the file called `name`.
+    pub(super) fn entry(&mut self, name: &str) -> Result<EntryReader<'_>> {
+        match self {
+            Storage::Zip(archive) => archive.by_name(name)
+                .map(|file| EntryReader(Entry::Zip(file)))
+                .map_err(|source| AppError::ZipExtraction { path: name.into(), source }),
+            Storage::Directory { root, .. } => {
+                let path = root.join(name);
+                // Names come from the book itself; never let them reach outside of it.
+                let file = if Path::new(name).components().all(|component| matches!(component, Component::Normal(_))) {
+                    fs::File::open(&path)
+                } else {
+                    Err(io::Error::new(io::ErrorKind::InvalidInput, "the path leaves the book's directory"))
+                };
+                file.map(|file| EntryReader(Entry::File(BufReader::new(file))))
+                    .map_err(|source| AppError::FileRead { path, source })
+            }
+        }
+    }
+
+    pub(super) fn read_to_string(&mut self, name: &str) -> Result<String> {
+        let mut content = String::new();
+        self.entry(name)?.read_to_string(&mut content)?;
+        Ok(content)
+    }
+
+    /// Writes every file of the book below `dest_path`; `path` names the book in errors.
+    pub(super) fn extract(&mut self, dest_path: &Path, path: &Path) -> Result<()> {
+        match self {
+            Storage::Zip(archive) => archive.extract(dest_path)
+                .map_err(|source| AppError::ZipExtraction { path: path.to_path_buf(), source }),
+            Storage::Directory { root, files } => {
+                for name in files.iter() {
+                    let target = dest_path.join(name);
+                    if let Some(parent) = target.parent() {
+                        fs::create_dir_all(parent)?;
+                    }
+                    fs::copy(root.join(name), target)?;
+                }
+                Ok(())
+            }
+        }
+    }
+
+    /// Where `extract` writes the file called `name`, if it writes it at all.
+    pub(super) fn extracted_path(&mut self, name: &str) -> Option<PathBuf> {
+        match self {
+            Storage::Zip(archive) => archive.by_name(name).ok().and_then(|file| file.enclosed_name()),
+            Storage::Directory { .. } => self.contains(name).then(|| PathBuf::from(name)),
+        }
+    }
+}
+
+/// A file being read from a book, as returned by `Epub::read_by_manifest_item`.
+pub struct EntryReader<'a>(Entry<'a>);
+
+enum Entry<'a> {
+    Zip(ZipFile<'a, Box<dyn ReadSeek>>),
+    File(BufReader<fs::File>),
+}
+
+impl Read for EntryReader<'_> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        match &mut self.0 {
+            Entry::Zip(file) => file.read(buf),
+            Entry::File(file) => file.read(buf),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn entries_cannot_leave_the_directory() {
+        let dir = std::env::temp_dir().join(format!("tome-storage-test-{}", std::process::id()));
+        let book = dir.join("book");
+        fs::create_dir_all(&book).unwrap();
+        fs::write(book.join("mimetype"), "application/epub+zip").unwrap();
+        fs::write(dir.join("secret.txt"), "secret").unwrap();
+        let mut storage = Storage::open(&book).unwrap();
+
+        assert_eq!(storage.read_to_string("mimetype").unwrap(), "application/epub+zip");
+        for name in ["../secret.txt", "OEBPS/../../secret.txt", &dir.join("secret.txt").to_string_lossy()] {
+            assert!(matches!(storage.entry(name), Err(AppError::FileRead { .. })), "{}", name);
+        }
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
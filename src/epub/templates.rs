@@ -0,0 +1,193 @@
+// src/epub/templates.rs
+use std::fs;
+use std::io::ErrorKind;
+use std::path::{Component, Path, PathBuf};
+
+use minijinja::value::Value;
+use minijinja::{escape_formatter, AutoEscape, Environment};
+use quick_xml::escape::escape;
+use serde::Serialize;
+
+use crate::epub::packer;
+use crate::error::{AppError, Result};
+
+/// The layout shared by the built-in pages, which user templates may extend too.
+const PAGE_HTML: &str = include_str!("../../static/templates/page.html");
+const CHAPTER_HTML: &str = include_str!("../../static/templates/chapter.html");
+const INDEX_HTML: &str = include_str!("../../static/templates/index.html");
+
+/// The template for the page of each spine item.
+pub(super) const CHAPTER_TEMPLATE: &str = "chapter.html";
+/// The template for `index.html`: the table of contents, or the whole book on a single page.
+pub(super) const INDEX_TEMPLATE: &str = "index.html";
+/// The directory of a template directory whose files are copied to the site's `assets/`.
+const STATIC_DIR: &str = "static";
+
+/// The book's metadata, as `book` in templates.
+#[derive(Debug, Serialize)]
+pub(super) struct BookInfo {
+    pub(super) title: String,
+    pub(super) authors: Vec<String>,
+    pub(super) language: Option<String>,
+    pub(super) publisher: Option<String>,
+    pub(super) description: Option<String>,
+    pub(super) identifier: Option<String>,
+}
+
+/// An entry of the table of contents, as an item of `toc` in templates.
+#[derive(Debug, Serialize)]
+pub(super) struct TocItem {
+    pub(super) label: String,
+    /// The link to the entry from the page being rendered; `None` for labels without a target.
+    pub(super) href: Option<String>,
+    /// Whether the entry points into the page being rendered.
+    pub(super) current: bool,
+    pub(super) children: Vec<TocItem>,
+}
+
+/// A link to another page, as `prev` and `next` in templates.
+#[derive(Debug, Serialize)]
+pub(super) struct PageLink {
+    pub(super) title: String,
+    pub(super) href: String,
+}
+
+/// The page templates of a site: the built-in ones, or those of a user's
+/// template directory, which fall back to the built-in ones by name.
+pub(super) struct Templates {
+    env: Environment<'static>,
+    /// The template directory's `static/` directory; empty for the built-in templates.
+    static_dir: PathBuf,
+    /// The files below `static_dir`, relative to it.
+    static_files: Vec<PathBuf>,
+}
+
+impl Templates {
+    pub(super) fn builtin() -> Self {
+        Self { env: environment(None), static_dir: PathBuf::new(), static_files: Vec::new() }
+    }
+
+    /// Loads the templates in `dir`, checking that `chapter.html` and
+    /// `index.html` compile before any page is generated.
+    pub(super) fn from_dir(dir: &Path) -> Result<Self> {
+        if !dir.is_dir() {
+            return Err(AppError::TemplateDirNotFound { path: dir.to_path_buf() });
+        }
+        let env = environment(Some(dir.to_path_buf()));
+        env.get_template(CHAPTER_TEMPLATE)?;
+        env.get_template(INDEX_TEMPLATE)?;
+
+        let static_dir = dir.join(STATIC_DIR);
+        let mut static_files = Vec::new();
+        if static_dir.is_dir() {
+            packer::collect_files(&static_dir, Path::new(""), &mut static_files)?;
+        }
+        static_files.sort();
+        Ok(Self { env, static_dir, static_files })
+    }
+
+    pub(super) fn render(&self, name: &str, context: Value) -> Result<String> {
+        Ok(self.env.get_template(name)?.render(context)?)
+    }
+
+    /// The files of the template directory's `static/` directory, relative to
+    /// it. They are copied to the site's `assets/` directory.
+    pub(super) fn static_files(&self) -> &[PathBuf] {
+        &self.static_files
+    }
+
+    /// Reads a file listed by `static_files`.
+    pub(super) fn read_static_file(&self, relative_path: &Path) -> Result<Vec<u8>> {
+        Ok(fs::read(self.static_dir.join(relative_path))?)
+    }
+}
+
+fn environment(dir: Option<PathBuf>) -> Environment<'static> {
+    let mut env = Environment::new();
+    env.set_loader(move |name| {
+        // Template names are paths inside the directory, never outside it.
+        if let Some(dir) = &dir
+            && Path::new(name).components().all(|component| matches!(component, Component::Normal(_))) {
+            match fs::read_to_string(dir.join(name)) {
+                Ok(source) => return Ok(Some(source)),
+                Err(e) if e.kind() == ErrorKind::NotFound => {}
+                Err(e) => {
+                    return Err(minijinja::Error::new(minijinja::ErrorKind::InvalidOperation, format!("could not read template '{}'", name))
+                        .with_source(e));
+                }
+            }
+        }
+        Ok(match name {
+            "page.html" => Some(PAGE_HTML.to_string()),
+            CHAPTER_TEMPLATE => Some(CHAPTER_HTML.to_string()),
+            INDEX_TEMPLATE => Some(INDEX_HTML.to_string()),
+            _ => None,
+        })
+    });
+    // Escape like the rest of the site does: minijinja's own escaping also
+    // turns every `/` into `&#x2f;`, which makes links hard to read.
+    env.set_formatter(|out, state, value| {
+        if *state.auto_escape() == AutoEscape::Html && !value.is_safe()
+            && let Some(text) = value.as_str() {
+            return out.write_str(&escape(text))
+                .map_err(|e| minijinja::Error::new(minijinja::ErrorKind::WriteFailure, "could not write output").with_source(e));
+        }
+        escape_formatter(out, state, value)
+    });
+    env
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn temp_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("tome-templates-{}-{}", name, std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    #[test]
+    fn missing_templates_fall_back_to_the_builtin_ones() {
+        let dir = temp_dir("fallback");
+        fs::write(dir.join(CHAPTER_TEMPLATE), "custom {{ title }}").unwrap();
+        let templates = Templates::from_dir(&dir).unwrap();
+
+        assert_eq!(templates.render(CHAPTER_TEMPLATE, minijinja::context! { title => "One" }).unwrap(), "custom One");
+        assert_eq!(templates.env.get_template(INDEX_TEMPLATE).unwrap().source(), INDEX_HTML);
+        assert_eq!(templates.env.get_template("page.html").unwrap().source(), PAGE_HTML);
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn template_names_cannot_leave_the_directory() {
+        let parent = temp_dir("outside");
+        let dir = parent.join("theme");
+        fs::create_dir_all(&dir).unwrap();
+        fs::write(parent.join("secret.html"), "secret").unwrap();
+        let templates = Templates::from_dir(&dir).unwrap();
+
+        for name in ["../secret.html", "./../secret.html", &parent.join("secret.html").to_string_lossy()] {
+            let error = templates.env.get_template(name).unwrap_err();
+            assert_eq!(error.kind(), minijinja::ErrorKind::TemplateNotFound, "{}", name);
+        }
+
+        fs::remove_dir_all(&parent).unwrap();
+    }
+
+    #[test]
+    fn hidden_static_files_are_skipped() {
+        let dir = temp_dir("static");
+        fs::create_dir_all(dir.join("static/fonts/.cache")).unwrap();
+        fs::write(dir.join("static/styles.css"), "").unwrap();
+        fs::write(dir.join("static/.DS_Store"), "").unwrap();
+        fs::write(dir.join("static/fonts/body.woff2"), "").unwrap();
+        fs::write(dir.join("static/fonts/.cache/body.woff2"), "").unwrap();
+        let templates = Templates::from_dir(&dir).unwrap();
+
+        assert_eq!(templates.static_files(), [Path::new("fonts/body.woff2"), Path::new("styles.css")]);
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
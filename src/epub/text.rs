@@ -0,0 +1,251 @@
+// src/epub/text.rs
+use crate::epub::xhtml::{collapse_ascii_whitespace, indent, Element, Indent, Node, BLOCK_ELEMENTS, HIDDEN_ELEMENTS};
+
+/// Renders the content of `body` as plain text: blocks are separated by a
+/// blank line, images are replaced by their alt text and, with a `width`,
+/// paragraphs are wrapped at that many columns.
+pub(super) fn render(body: &Element, width: Option<usize>) -> String {
+    blocks(body, width).join("\n\n")
+}
+
+/// Wraps `text` at `width` columns, line by line; see `wrap_line`.
+pub(super) fn wrap(text: &str, width: Option<usize>) -> String {
+    match width {
+        Some(width) => text.split('\n').map(|line| wrap_line(line, width)).collect::<Vec<_>>().join("\n"),
+        None => text.to_string(),
+    }
+}
+
+/// Renders the children of `container` as blocks; runs of inline content
+/// between block elements become paragraphs.
+fn blocks(container: &Element, width: Option<usize>) -> Vec<String> {
+    let mut blocks = Vec::new();
+    let mut paragraph = String::new();
+    for child in &container.children {
+        match child {
+            Node::Text(text) => paragraph.push_str(&spaces(text)),
+            Node::Element(element) if HIDDEN_ELEMENTS.contains(&element.name.as_str()) => {}
+            Node::Element(element) if BLOCK_ELEMENTS.contains(&element.name.as_str()) => {
+                flush_paragraph(&mut paragraph, width, &mut blocks);
+                block(element, width, &mut blocks);
+            }
+            Node::Element(element) => paragraph.push_str(&inline_element(element)),
+        }
+    }
+    flush_paragraph(&mut paragraph, width, &mut blocks);
+    blocks
+}
+
+fn block(element: &Element, width: Option<usize>, blocks: &mut Vec<String>) {
+    match element.name.as_str() {
+        "ul" | "ol" => {
+            let list = list(element, width);
+            if !list.is_empty() {
+                blocks.push(list);
+            }
+        }
+        "blockquote" => {
+            let content = self::blocks(element, width.map(|width| width.saturating_sub(4))).join("\n\n");
+            if !content.is_empty() {
+                blocks.push(indent(&content, "    ", Indent::NonEmpty));
+            }
+        }
+        "pre" => {
+            let code = element.text();
+            let code = code.strip_prefix('\n').unwrap_or(&code).trim_end();
+            if !code.is_empty() {
+                blocks.push(code.to_string());
+            }
+        }
+        "table" => {
+            let lines: Vec<String> = element.rows().iter()
+                .map(|row| {
+                    row.elements()
+                        .filter(|cell| cell.name == "td" || cell.name == "th")
+                        .map(|cell| collapse_ascii_whitespace(&inline(cell)))
+                        .collect::<Vec<_>>()
+                        .join(" | ")
+                })
+                .filter(|line| !line.is_empty())
+                .collect();
+            if !lines.is_empty() {
+                blocks.push(lines.join("\n"));
+            }
+        }
+        "hr" => blocks.push("* * *".to_string()),
+        _ => blocks.extend(self::blocks(element, width)),
+    }
+}
+
+/// Renders a list with `-` or numbered markers, hanging the item text under the marker.
+fn list(list: &Element, width: Option<usize>) -> String {
+    let ordered = list.name == "ol";
+    let start: usize = list.attribute("start").and_then(|start| start.trim().parse().ok()).unwrap_or(1);
+    let mut items = Vec::new();
+    for (number, item) in (start..).zip(list.elements().filter(|element| element.name == "li")) {
+        let marker = if ordered { format!("{}. ", number) } else { "- ".to_string() };
+        let content = blocks(item, width.map(|width| width.saturating_sub(marker.len()))).join("\n");
+        let padding = " ".repeat(marker.len());
+        let mut lines = content.split('\n');
+        let mut text = format!("{}{}", marker, lines.next().unwrap_or(""));
+        for line in lines {
+            text.push('\n');
+            if !line.is_empty() {
+                text.push_str(&padding);
+                text.push_str(line);
+            }
+        }
+        items.push(text.trim_end().to_string());
+    }
+    items.join("\n")
+}
+
+/// Renders the children of `element` as a line of text; `<br>` becomes a newline.
+fn inline(element: &Element) -> String {
+    let mut text = String::new();
+    for child in &element.children {
+        match child {
+            Node::Text(content) => text.push_str(&spaces(content)),
+            Node::Element(child) if HIDDEN_ELEMENTS.contains(&child.name.as_str()) => {}
+            Node::Element(child) => text.push_str(&inline_element(child)),
+        }
+    }
+    text
+}
+
+fn inline_element(element: &Element) -> String {
+    match element.name.as_str() {
+        "br" => "\n".to_string(),
+        // Whitespace keeps the alt text apart from the words around the image.
+        "img" => element.attribute("alt")
+            .map(collapse_ascii_whitespace)
+            .filter(|alt| !alt.is_empty())
+            .map(|alt| format!(" [{}] ", alt))
+            .unwrap_or_default(),
+        // The text of an SVG drawing is graphics, not prose.
+        "svg" => String::new(),
+        _ => inline(element),
+    }
+}
+
+fn flush_paragraph(paragraph: &mut String, width: Option<usize>, blocks: &mut Vec<String>) {
+    let text = paragraph.split('\n')
+        .map(collapse_ascii_whitespace)
+        .filter(|line| !line.is_empty())
+        .collect::<Vec<_>>()
+        .join("\n");
+    paragraph.clear();
+    if !text.is_empty() {
+        blocks.push(wrap(&text, width));
+    }
+}
+
+/// Turns the line breaks of the source into spaces; only `<br>` breaks a line.
+fn spaces(text: &str) -> String {
+    text.replace(|c: char| c.is_ascii_whitespace(), " ")
+}
+
+/// Wraps a line at `width` columns. Lines break at spaces and, since CJK
+/// text has none, between wide characters, which take two columns each.
+/// Words longer than the width are left whole.
+fn wrap_line(line: &str, width: usize) -> String {
+    let mut lines = Vec::new();
+    let mut current = String::new();
+    let mut current_width = 0;
+    for (segment, spaced) in segments(line) {
+        let segment_width: usize = segment.chars().map(char_width).sum();
+        let gap = usize::from(spaced && !current.is_empty());
+        if !current.is_empty() && current_width + gap + segment_width > width {
+            lines.push(std::mem::take(&mut current));
+            current_width = 0;
+        } else if gap == 1 {
+            current.push(' ');
+            current_width += 1;
+        }
+        current.push_str(segment);
+        current_width += segment_width;
+    }
+    lines.push(current);
+    lines.join("\n")
+}
+
+/// Splits a line into the units it may be broken between: words, and
+/// single wide characters. The flag tells whether a space came before.
+fn segments(line: &str) -> Vec<(&str, bool)> {
+    let mut segments = Vec::new();
+    let mut start = None;
+    let mut spaced = false;
+    for (i, c) in line.char_indices() {
+        if c == ' ' || char_width(c) == 2 {
+            if let Some(word_start) = start.take() {
+                segments.push((&line[word_start..i], spaced));
+                spaced = false;
+            }
+            if c == ' ' {
+                spaced = true;
+            } else {
+                segments.push((&line[i..i + c.len_utf8()], spaced));
+                spaced = false;
+            }
+        } else if start.is_none() {
+            start = Some(i);
+        }
+    }
+    if let Some(word_start) = start {
+        segments.push((&line[word_start..], spaced));
+    }
+    segments
+}
+
+/// The number of columns a character takes up in a terminal: two for the
+/// East Asian wide and fullwidth ranges, one otherwise.
+fn char_width(c: char) -> usize {
+    match c as u32 {
+        0x1100..=0x115F
+        | 0x2E80..=0x303E
+        | 0x3041..=0xA4CF
+        | 0xAC00..=0xD7A3
+        | 0xF900..=0xFAFF
+        | 0xFE30..=0xFE4F
+        | 0xFF00..=0xFF60
+        | 0xFFE0..=0xFFE6
+        | 0x1F300..=0x1F64F
+        | 0x1F900..=0x1F9FF
+        | 0x20000..=0x3FFFD => 2,
+        _ => 1,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn ascii_text_wraps_at_spaces() {
+        assert_eq!(wrap_line("the quick brown fox jumps", 10), "the quick\nbrown fox\njumps");
+        assert_eq!(wrap_line("exactly ten", 11), "exactly ten");
+        assert_eq!(wrap("one two\nthree four", Some(7)), "one two\nthree\nfour");
+        assert_eq!(wrap("one two three", None), "one two three");
+    }
+
+    #[test]
+    fn words_longer_than_the_width_are_left_whole() {
+        assert_eq!(wrap_line("a supercalifragilistic word", 10), "a\nsupercalifragilistic\nword");
+    }
+
+    #[test]
+    fn wide_characters_break_anywhere_and_take_two_columns() {
+        assert_eq!(wrap_line("日本語のテキスト", 6), "日本語\nのテキ\nスト");
+        assert_eq!(wrap_line("abc 日本語", 6), "abc 日\n本語");
+        assert_eq!(segments("abc 日本 de"), [("abc", false), ("日", true), ("本", false), ("de", true)]);
+    }
+
+    #[test]
+    fn character_widths() {
+        assert_eq!(char_width('a'), 1);
+        assert_eq!(char_width('é'), 1);
+        assert_eq!(char_width('日'), 2);
+        assert_eq!(char_width('한'), 2);
+        assert_eq!(char_width('Ａ'), 2);
+    }
+}
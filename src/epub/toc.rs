@@ -0,0 +1,316 @@
+// src/epub/toc.rs
+use std::collections::HashMap;
+use std::path::Path;
+
+use path_clean::PathClean;
+use path_slash::PathBufExt;
+use quick_xml::events::{BytesStart, Event};
+use quick_xml::Reader;
+
+use crate::epub::model::TocEntry;
+use crate::epub::path_resolver::PathResolver;
+use crate::epub::xhtml::{attribute, collapse_whitespace, text_content};
+
+/// Parses the `<nav epub:type="toc">` of an EPUB 3 navigation document.
+///
+/// `doc_href` is the manifest href of the navigation document; it is used to
+/// resolve the links so that every entry's `href` is relative to the OPF file.
+pub(super) fn parse_nav(content: &str, doc_href: &str) -> quick_xml::Result<Vec<TocEntry>> {
+    let mut reader = Reader::from_str(content);
+
+    // Each open <ol> collects its entries; each open <li> is an entry being built.
+    let mut lists: Vec<Vec<TocEntry>> = Vec::new();
+    let mut items: Vec<TocEntry> = Vec::new();
+    let mut label_depth = 0usize;
+    let mut nav_depth = 0usize;
+    let mut toc_nav_found = false;
+    let mut result = Vec::new();
+
+    loop {
+        match reader.read_event()? {
+            Event::Start(e) => {
+                let name = e.local_name();
+                match name.as_ref() {
+                    b"nav" if nav_depth > 0 => nav_depth += 1,
+                    b"nav" if !toc_nav_found => {
+                        // Books without an explicit epub:type="toc" get their first <nav>.
+                        let is_toc = attribute(&e, "epub:type")
+                            .is_some_and(|t| t.split_whitespace().any(|t| t == "toc"));
+                        if is_toc || result.is_empty() {
+                            result.clear();
+                            nav_depth = 1;
+                            toc_nav_found = is_toc;
+                        }
+                    }
+                    _ if nav_depth == 0 => {}
+                    b"ol" | b"ul" => lists.push(Vec::new()),
+                    b"li" => items.push(TocEntry::default()),
+                    b"a" | b"span" if label_depth == 0 => {
+                        if let Some(item) = items.last_mut().filter(|item| item.label.is_empty()) {
+                            if let Some(href) = attribute(&e, "href") {
+                                (item.href, item.fragment) = resolve_href(doc_href, &href);
+                            }
+                            label_depth = 1;
+                        }
+                    }
+                    _ if label_depth > 0 => label_depth += 1,
+                    _ => {}
+                }
+            }
+            Event::End(e) => {
+                let name = e.local_name();
+                match name.as_ref() {
+                    b"nav" if nav_depth > 0 => {
+                        nav_depth -= 1;
+                        if nav_depth == 0 && toc_nav_found {
+                            break;
+                        }
+                    }
+                    _ if nav_depth == 0 => {}
+                    b"ol" | b"ul" => {
+                        let list = lists.pop().unwrap_or_default();
+                        match items.last_mut() {
+                            Some(parent) => parent.children.extend(list),
+                            None => result.extend(list),
+                        }
+                    }
+                    b"li" => {
+                        if let Some(mut item) = items.pop() {
+                            item.label = collapse_whitespace(&item.label);
+                            if let Some(list) = lists.last_mut() {
+                                list.push(item);
+                            }
+                        }
+                    }
+                    _ if label_depth > 0 => label_depth -= 1,
+                    _ => {}
+                }
+            }
+            Event::Text(e) if label_depth > 0 => {
+                if let Some(item) = items.last_mut() {
+                    item.label.push_str(&text_content(&e));
+                }
+            }
+            Event::CData(e) if label_depth > 0 => {
+                if let Some(item) = items.last_mut() {
+                    item.label.push_str(&String::from_utf8_lossy(&e));
+                }
+            }
+            Event::Eof => break,
+            _ => {}
+        }
+    }
+
+    Ok(result)
+}
+
+/// Parses the `<navMap>` of an EPUB 2 NCX document.
+///
+/// `doc_href` is the manifest href of the NCX file, used to resolve `<content src>`.
+pub(super) fn parse_ncx(content: &str, doc_href: &str) -> quick_xml::Result<Vec<TocEntry>> {
+    let mut reader = Reader::from_str(content);
+
+    let mut points: Vec<TocEntry> = Vec::new();
+    let mut in_nav_map = false;
+    let mut in_label_text = false;
+    let mut result = Vec::new();
+
+    loop {
+        match reader.read_event()? {
+            Event::Start(e) => match e.local_name().as_ref() {
+                b"navMap" => in_nav_map = true,
+                b"navPoint" if in_nav_map => points.push(TocEntry::default()),
+                b"text" if in_nav_map => in_label_text = true,
+                b"content" if in_nav_map => set_ncx_target(&mut points, doc_href, &e),
+                _ => {}
+            },
+            Event::Empty(e) if in_nav_map && e.local_name().as_ref() == b"content" => {
+                set_ncx_target(&mut points, doc_href, &e);
+            }
+            Event::End(e) => match e.local_name().as_ref() {
+                b"navMap" => break,
+                b"text" => in_label_text = false,
+                b"navPoint" => {
+                    if let Some(mut point) = points.pop() {
+                        point.label = collapse_whitespace(&point.label);
+                        match points.last_mut() {
+                            Some(parent) => parent.children.push(point),
+                            None => result.push(point),
+                        }
+                    }
+                }
+                _ => {}
+            },
+            Event::Text(e) if in_label_text => {
+                // Nested points are pushed before their label, so this is always the right owner.
+                if let Some(point) = points.last_mut() {
+                    point.label.push_str(&text_content(&e));
+                }
+            }
+            Event::Eof => break,
+            _ => {}
+        }
+    }
+
+    Ok(result)
+}
+
+fn set_ncx_target(points: &mut [TocEntry], doc_href: &str, element: &BytesStart) {
+    if let (Some(point), Some(src)) = (points.last_mut(), attribute(element, "src"))
+        && point.href.is_none() {
+        (point.href, point.fragment) = resolve_href(doc_href, &src);
+    }
+}
+
+/// Resolves a link found in the document at `doc_href` into an OPF-relative
+/// path and an optional fragment. External links resolve to `(None, None)`.
+pub(super) fn resolve_href(doc_href: &str, link: &str) -> (Option<String>, Option<String>) {
+    if link.contains(':') {
+        return (None, None);
+    }
+
+    let (file_part, fragment) = match link.split_once('#') {
+        Some((file, fragment)) => (file, Some(fragment.to_string()).filter(|f| !f.is_empty())),
+        None => (link, None),
+    };
+
+    let href = if file_part.is_empty() {
+        doc_href.to_string()
+    } else {
+        let doc_dir = Path::new(doc_href).parent().unwrap_or_else(|| Path::new(""));
+        doc_dir.join(file_part).clean().to_slash_lossy().into_owned()
+    };
+    (Some(href), fragment)
+}
+
+/// Names each document after the first entry pointing into it, keyed by
+/// `PathResolver::key` of the document's href.
+pub(super) fn document_titles(entries: &[TocEntry]) -> HashMap<String, String> {
+    fn collect(entries: &[TocEntry], titles: &mut HashMap<String, String>) {
+        for entry in entries {
+            if let Some(href) = &entry.href
+                && !entry.label.is_empty() {
+                titles.entry(PathResolver::key(href)).or_insert_with(|| entry.label.clone());
+            }
+            collect(&entry.children, titles);
+        }
+    }
+
+    let mut titles = HashMap::new();
+    collect(entries, &mut titles);
+    titles
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn labels(entries: &[TocEntry]) -> Vec<&str> {
+        entries.iter().map(|entry| entry.label.as_str()).collect()
+    }
+
+    #[test]
+    fn nested_lists_become_children() {
+        let nav = r#"<html><body><nav epub:type="toc"><ol>
+            <li><a href="ch1.xhtml">One</a>
+                <ol>
+                    <li><a href="ch1.xhtml#a">One A</a></li>
+                    <li><a href="ch1.xhtml#b">One B</a>
+                        <ol><li><a href="ch1.xhtml#b1">One B 1</a></li></ol>
+                    </li>
+                </ol>
+            </li>
+            <li><a href="ch2.xhtml">Two</a></li>
+        </ol></nav></body></html>"#;
+
+        let toc = parse_nav(nav, "Text/nav.xhtml").unwrap();
+        assert_eq!(labels(&toc), ["One", "Two"]);
+        assert_eq!(labels(&toc[0].children), ["One A", "One B"]);
+        assert_eq!(labels(&toc[0].children[1].children), ["One B 1"]);
+        assert!(toc[1].children.is_empty());
+
+        let nested = &toc[0].children[1].children[0];
+        assert_eq!(nested.href.as_deref(), Some("Text/ch1.xhtml"));
+        assert_eq!(nested.fragment.as_deref(), Some("b1"));
+    }
+
+    #[test]
+    fn hidden_entries_stay_in_the_toc_and_other_navs_are_ignored() {
+        let nav = r#"<html><body>
+            <nav epub:type="landmarks" hidden=""><ol><li><a href="cover.xhtml">Cover</a></li></ol></nav>
+            <nav epub:type="toc"><ol>
+                <li hidden=""><a href="cover.xhtml">Cover</a></li>
+                <li><a href="ch1.xhtml">One</a></li>
+            </ol></nav>
+            <nav epub:type="page-list" hidden=""><ol><li><a href="ch1.xhtml#p1">1</a></li></ol></nav>
+        </body></html>"#;
+
+        let toc = parse_nav(nav, "nav.xhtml").unwrap();
+        assert_eq!(labels(&toc), ["Cover", "One"]);
+    }
+
+    #[test]
+    fn a_nav_without_epub_type_is_used_as_the_toc() {
+        let nav = r#"<html><body><nav><ol><li><a href="ch1.xhtml">One</a></li></ol></nav></body></html>"#;
+
+        let toc = parse_nav(nav, "nav.xhtml").unwrap();
+        assert_eq!(labels(&toc), ["One"]);
+    }
+
+    #[test]
+    fn span_labels_group_their_children() {
+        let nav = r#"<html><body><nav epub:type="toc"><ol>
+            <li><span>Part <em>I</em></span>
+                <ol><li><a href="ch1.xhtml"><span>Chapter</span>
+                    <b>One</b></a></li></ol>
+            </li>
+        </ol></nav></body></html>"#;
+
+        let toc = parse_nav(nav, "nav.xhtml").unwrap();
+        assert_eq!(labels(&toc), ["Part I"]);
+        assert_eq!(toc[0].href, None);
+        assert_eq!(labels(&toc[0].children), ["Chapter One"]);
+        assert_eq!(toc[0].children[0].href.as_deref(), Some("ch1.xhtml"));
+    }
+
+    #[test]
+    fn ncx_nav_points_nest() {
+        let ncx = r#"<ncx><navMap>
+            <navPoint id="p1" playOrder="1">
+                <navLabel><text>One</text></navLabel>
+                <content src="Text/ch1.xhtml"/>
+                <navPoint id="p1a" playOrder="2">
+                    <navLabel><text>One A</text></navLabel>
+                    <content src="Text/ch1.xhtml#a"/>
+                </navPoint>
+            </navPoint>
+            <navPoint id="p2" playOrder="3">
+                <navLabel><text>  Two
+                    again </text></navLabel>
+                <content src="Text/ch2.xhtml"></content>
+            </navPoint>
+        </navMap></ncx>"#;
+
+        let toc = parse_ncx(ncx, "toc.ncx").unwrap();
+        assert_eq!(labels(&toc), ["One", "Two again"]);
+        assert_eq!(labels(&toc[0].children), ["One A"]);
+        assert_eq!(toc[0].href.as_deref(), Some("Text/ch1.xhtml"));
+        assert_eq!(toc[0].fragment, None);
+        assert_eq!(toc[0].children[0].fragment.as_deref(), Some("a"));
+        assert_eq!(toc[1].href.as_deref(), Some("Text/ch2.xhtml"));
+    }
+
+    #[test]
+    fn hrefs_resolve_against_the_navigation_document() {
+        assert_eq!(
+            resolve_href("nav/toc.xhtml", "../Text/ch1.xhtml#s1"),
+            (Some("Text/ch1.xhtml".to_string()), Some("s1".to_string()))
+        );
+        assert_eq!(
+            resolve_href("Text/nav.xhtml", "#intro"),
+            (Some("Text/nav.xhtml".to_string()), Some("intro".to_string()))
+        );
+        assert_eq!(resolve_href("nav.xhtml", "ch1.xhtml#"), (Some("ch1.xhtml".to_string()), None));
+        assert_eq!(resolve_href("nav.xhtml", "https://example.com/"), (None, None));
+    }
+}
@@ -0,0 +1,527 @@
+// src/epub/validator.rs
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+use std::io::{Read, Seek};
+use std::path::Path;
+
+use path_clean::PathClean;
+use path_slash::PathBufExt;
+use quick_xml::events::{BytesStart, Event};
+use quick_xml::Reader;
+use zip::CompressionMethod;
+
+use crate::epub::model::Container;
+use crate::epub::storage::Storage;
+use crate::epub::xhtml::attribute;
+use crate::error::Result;
+
+const EPUB_MIMETYPE: &str = "application/epub+zip";
+const OPF_MEDIA_TYPE: &str = "application/oebps-package+xml";
+const CONTAINER_PATH: &str = "META-INF/container.xml";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Severity {
+    Warning,
+    Error,
+}
+
+impl fmt::Display for Severity {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Severity::Warning => write!(f, "warning"),
+            Severity::Error => write!(f, "error"),
+        }
+    }
+}
+
+/// A single problem found while checking an EPUB.
+#[derive(Debug, Clone)]
+pub struct Diagnostic {
+    pub severity: Severity,
+    /// A stable identifier such as `OPF-003`, suitable for filtering.
+    pub code: &'static str,
+    /// The archive entry the problem was found in, with a line number when known.
+    pub location: String,
+    pub message: String,
+}
+
+impl fmt::Display for Diagnostic {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}[{}] {}: {}", self.severity, self.code, self.location, self.message)
+    }
+}
+
+/// Checks the EPUB at `path` against the structural rules of the OCF and
+/// package specifications. Problems with the book are reported as
+/// diagnostics; only failing to read the file at all is an `Err`.
+///
+/// `path` may also be an unpacked directory. The checks on how `mimetype`
+/// is stored in the archive are skipped for it.
+pub fn validate_file(path: &Path) -> Result<Vec<Diagnostic>> {
+    Ok(validate(&mut Storage::open(path)?))
+}
+
+/// Like `validate_file`, for an EPUB held by any seekable reader.
+pub fn validate_reader<R: Read + Seek + Send + 'static>(reader: R) -> Result<Vec<Diagnostic>> {
+    Ok(validate(&mut Storage::from_reader(Box::new(reader), Path::new("<reader>"))?))
+}
+
+/// Runs every check against an opened book.
+fn validate(storage: &mut Storage) -> Vec<Diagnostic> {
+    let mut validator = Validator { storage, diagnostics: Vec::new() };
+    validator.check_mimetype();
+    if let Some(opf_path) = validator.check_container() {
+        validator.check_package(&opf_path);
+    }
+    validator.diagnostics.sort_by_key(|d| std::cmp::Reverse(d.severity));
+    validator.diagnostics
+}
+
+/// An element of an XML document, as seen by the event-level scan.
+struct ScannedElement {
+    name: String,
+    attributes: HashMap<String, String>,
+    line: usize,
+}
+
+struct Validator<'a> {
+    storage: &'a mut Storage,
+    diagnostics: Vec<Diagnostic>,
+}
+
+impl Validator<'_> {
+    fn report(&mut self, severity: Severity, code: &'static str, location: impl Into<String>, message: impl Into<String>) {
+        self.diagnostics.push(Diagnostic { severity, code, location: location.into(), message: message.into() });
+    }
+
+    fn read_entry(&mut self, name: &str) -> Option<Vec<u8>> {
+        let mut file = self.storage.entry(name).ok()?;
+        let mut content = Vec::new();
+        file.read_to_end(&mut content).ok()?;
+        Some(content)
+    }
+
+    fn check_mimetype(&mut self) {
+        if let Some(archive) = self.storage.archive() {
+            let entry = archive.by_name("mimetype").ok().map(|file| (file.header_start(), file.compression()));
+            let Some((header_start, compression)) = entry else {
+                self.report(Severity::Error, "PKG-001", "mimetype", "the archive has no 'mimetype' entry");
+                return;
+            };
+
+            if header_start != 0 {
+                self.report(Severity::Error, "PKG-002", "mimetype", "'mimetype' must be the first entry in the archive");
+            }
+            if compression != CompressionMethod::Stored {
+                self.report(Severity::Error, "PKG-003", "mimetype", "'mimetype' must be stored without compression");
+            }
+        } else if !self.storage.contains("mimetype") {
+            self.report(Severity::Error, "PKG-001", "mimetype", "the directory has no 'mimetype' file");
+            return;
+        }
+        let content = self.read_entry("mimetype").unwrap_or_default();
+        if content != EPUB_MIMETYPE.as_bytes() {
+            self.report(
+                Severity::Error,
+                "PKG-004",
+                "mimetype",
+                format!("'mimetype' must contain exactly '{}', found {:?}", EPUB_MIMETYPE, String::from_utf8_lossy(&content)),
+            );
+        }
+    }
+
+    /// Checks `container.xml` and returns the path of the package document if it can be found.
+    fn check_container(&mut self) -> Option<String> {
+        let Some(content) = self.read_entry(CONTAINER_PATH) else {
+            self.report(Severity::Error, "OCF-001", CONTAINER_PATH, "the archive has no container file");
+            return None;
+        };
+
+        let container: Container = match quick_xml::de::from_str(&String::from_utf8_lossy(&content)) {
+            Ok(container) => container,
+            Err(e) => {
+                self.report(Severity::Error, "OCF-002", CONTAINER_PATH, format!("could not parse the container file: {}", e));
+                return None;
+            }
+        };
+
+        let Some(rootfile) = container.rootfiles.rootfile.first() else {
+            self.report(Severity::Error, "OCF-003", CONTAINER_PATH, "no <rootfile> is declared");
+            return None;
+        };
+        if rootfile.media_type != OPF_MEDIA_TYPE {
+            self.report(
+                Severity::Error,
+                "OCF-004",
+                CONTAINER_PATH,
+                format!("rootfile media-type must be '{}', found '{}'", OPF_MEDIA_TYPE, rootfile.media_type),
+            );
+        }
+        if !self.storage.contains(&rootfile.full_path) {
+            self.report(
+                Severity::Error,
+                "OCF-005",
+                CONTAINER_PATH,
+                format!("rootfile '{}' does not exist in the archive", rootfile.full_path),
+            );
+            return None;
+        }
+        Some(rootfile.full_path.clone())
+    }
+
+    fn check_package(&mut self, opf_path: &str) {
+        let content = self.read_entry(opf_path).unwrap_or_default();
+        let elements = match scan_elements(&String::from_utf8_lossy(&content)) {
+            Ok(elements) => elements,
+            Err((line, e)) => {
+                self.report(Severity::Error, "OPF-001", format!("{}:{}", opf_path, line), format!("package document is not well-formed: {}", e));
+                return;
+            }
+        };
+        let at = |line: usize| format!("{}:{}", opf_path, line);
+
+        // Duplicate ids anywhere in the package document.
+        let mut seen_ids = HashSet::new();
+        for element in &elements {
+            if let Some(id) = element.attributes.get("id")
+                && !seen_ids.insert(id.as_str()) {
+                self.report(Severity::Error, "OPF-004", at(element.line), format!("duplicate id '{}'", id));
+            }
+        }
+
+        // unique-identifier must point at a dc:identifier.
+        let package = elements.iter().find(|e| e.name == "package");
+        match package.and_then(|p| p.attributes.get("unique-identifier")) {
+            Some(uid) => {
+                let found = elements.iter().any(|e| e.name == "identifier" && e.attributes.get("id") == Some(uid));
+                if !found {
+                    self.report(Severity::Error, "OPF-006", at(package.map_or(1, |p| p.line)), format!("unique-identifier '{}' does not match any dc:identifier", uid));
+                }
+            }
+            None => self.report(Severity::Error, "OPF-006", at(package.map_or(1, |p| p.line)), "<package> has no unique-identifier attribute"),
+        }
+
+        // Every manifest item must exist; remember the archive names they cover.
+        let opf_dir = Path::new(opf_path).parent().unwrap_or_else(|| Path::new(""));
+        let mut manifest_ids = HashSet::new();
+        let mut covered_entries = HashSet::new();
+        let mut xhtml_entries = Vec::new();
+        for element in elements.iter().filter(|e| e.name == "item") {
+            if let Some(id) = element.attributes.get("id") {
+                manifest_ids.insert(id.clone());
+            }
+            let Some(href) = element.attributes.get("href") else {
+                self.report(Severity::Error, "OPF-002", at(element.line), "manifest item has no href");
+                continue;
+            };
+            if href.contains(':') {
+                continue; // Remote resources are allowed for some media types.
+            }
+            let decoded = urlencoding::decode(href).map(|s| s.into_owned()).unwrap_or_else(|_| href.clone());
+            let entry_name = opf_dir.join(decoded).clean().to_slash_lossy().into_owned();
+            if !self.storage.contains(&entry_name) {
+                self.report(Severity::Error, "OPF-002", at(element.line), format!("manifest item '{}' does not exist in the archive", href));
+                continue;
+            }
+            if element.attributes.get("media-type").map(String::as_str) == Some("application/xhtml+xml") {
+                xhtml_entries.push(entry_name.clone());
+            }
+            covered_entries.insert(entry_name);
+        }
+
+        // Every spine itemref must resolve to a manifest item.
+        for element in elements.iter().filter(|e| e.name == "itemref") {
+            match element.attributes.get("idref") {
+                Some(idref) if manifest_ids.contains(idref) => {}
+                Some(idref) => self.report(Severity::Error, "OPF-003", at(element.line), format!("spine itemref '{}' does not match any manifest item", idref)),
+                None => self.report(Severity::Error, "OPF-003", at(element.line), "spine itemref has no idref"),
+            }
+        }
+
+        // Files that are in the archive but not declared anywhere.
+        let undeclared: Vec<String> = self.storage.file_names().into_iter()
+            .filter(|name| !name.ends_with('/') && *name != "mimetype" && *name != opf_path && !name.starts_with("META-INF/"))
+            .filter(|name| !covered_entries.contains(*name))
+            .map(str::to_string)
+            .collect();
+        for name in undeclared {
+            self.report(Severity::Warning, "OPF-005", name, "file is present in the archive but not declared in the manifest");
+        }
+
+        for entry_name in xhtml_entries {
+            self.check_xhtml(&entry_name);
+        }
+    }
+
+    fn check_xhtml(&mut self, entry_name: &str) {
+        let content = self.read_entry(entry_name).unwrap_or_default();
+        let content = match String::from_utf8(content) {
+            Ok(content) => content,
+            Err(_) => {
+                self.report(Severity::Error, "HTM-001", entry_name, "document is not valid UTF-8");
+                return;
+            }
+        };
+        if let Err((line, e)) = scan_elements(&content) {
+            self.report(Severity::Error, "HTM-001", format!("{}:{}", entry_name, line), format!("document is not well-formed XML: {}", e));
+        }
+    }
+}
+
+/// Scans an XML document, returning every element with its attributes and
+/// line number, or the line and description of the first well-formedness error.
+fn scan_elements(content: &str) -> std::result::Result<Vec<ScannedElement>, (usize, String)> {
+    let mut reader = Reader::from_str(content);
+    let mut elements = Vec::new();
+    let mut open: Vec<String> = Vec::new();
+    // Line numbers are computed incrementally; positions only ever move forward.
+    let (mut counted_to, mut line) = (0usize, 1usize);
+    let mut line_at = |pos: u64| {
+        let pos = (pos as usize).min(content.len());
+        if pos > counted_to {
+            line += content.as_bytes()[counted_to..pos].iter().filter(|&&b| b == b'\n').count();
+            counted_to = pos;
+        }
+        line
+    };
+
+    let record = |e: &BytesStart, line: usize| {
+        let name = String::from_utf8_lossy(e.name().as_ref()).into_owned();
+        let attributes = e.attributes()
+            .flatten()
+            .map(|a| String::from_utf8_lossy(a.key.as_ref()).into_owned())
+            .filter_map(|key| attribute(e, &key).map(|value| (key, value)))
+            .collect();
+        ScannedElement { name: local_name(&name).to_string(), attributes, line }
+    };
+
+    loop {
+        let position = reader.buffer_position();
+        match reader.read_event() {
+            Ok(Event::Start(e)) => {
+                elements.push(record(&e, line_at(position)));
+                open.push(String::from_utf8_lossy(e.name().as_ref()).into_owned());
+            }
+            Ok(Event::Empty(e)) => elements.push(record(&e, line_at(position))),
+            Ok(Event::End(_)) => {
+                open.pop();
+            }
+            Ok(Event::Eof) => break,
+            Ok(_) => {}
+            Err(e) => return Err((line_at(reader.error_position()), e.to_string())),
+        }
+    }
+
+    match open.last() {
+        Some(unclosed) => Err((line_at(reader.buffer_position()), format!("element <{}> is never closed", unclosed))),
+        None => Ok(elements),
+    }
+}
+
+fn local_name(name: &str) -> &str {
+    name.rsplit(':').next().unwrap_or(name)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+    use std::io::{Cursor, Write};
+
+    use zip::write::SimpleFileOptions;
+    use zip::ZipWriter;
+
+    use super::*;
+
+    const CONTAINER: &str = r#"<?xml version="1.0"?>
+<container version="1.0" xmlns="urn:oasis:names:tc:opendocument:xmlns:container">
+  <rootfiles><rootfile full-path="OEBPS/content.opf" media-type="application/oebps-package+xml"/></rootfiles>
+</container>"#;
+
+    const PACKAGE: &str = r#"<?xml version="1.0"?>
+<package xmlns="http://www.idpf.org/2007/opf" version="3.0" unique-identifier="uid">
+  <metadata xmlns:dc="http://purl.org/dc/elements/1.1/"><dc:identifier id="uid">urn:uuid:1</dc:identifier></metadata>
+  <manifest><item id="ch1" href="ch1.xhtml" media-type="application/xhtml+xml"/></manifest>
+  <spine><itemref idref="ch1"/></spine>
+</package>"#;
+
+    const CHAPTER: &str = r#"<html xmlns="http://www.w3.org/1999/xhtml"><body><p>One</p></body></html>"#;
+
+    /// The entries of a valid book, with `changes` replacing (or, with `None`, removing) entries.
+    fn book<'a>(changes: &[(&'a str, Option<&'a str>)]) -> Vec<(&'a str, &'a str)> {
+        let mut entries = vec![
+            ("mimetype", EPUB_MIMETYPE),
+            (CONTAINER_PATH, CONTAINER),
+            ("OEBPS/content.opf", PACKAGE),
+            ("OEBPS/ch1.xhtml", CHAPTER),
+        ];
+        for &(name, content) in changes {
+            let position = entries.iter().position(|(n, _)| *n == name);
+            match (position, content) {
+                (Some(i), Some(content)) => entries[i].1 = content,
+                (Some(i), None) => _ = entries.remove(i),
+                (None, Some(content)) => entries.push((name, content)),
+                (None, None) => {}
+            }
+        }
+        entries
+    }
+
+    fn zip(entries: &[(&str, &str)], mimetype_compression: CompressionMethod) -> Storage {
+        let mut writer = ZipWriter::new(Cursor::new(Vec::new()));
+        for (name, content) in entries {
+            let compression = if *name == "mimetype" { mimetype_compression } else { CompressionMethod::Deflated };
+            writer.start_file(*name, SimpleFileOptions::default().compression_method(compression)).unwrap();
+            writer.write_all(content.as_bytes()).unwrap();
+        }
+        Storage::from_reader(Box::new(writer.finish().unwrap()), Path::new("book.epub")).unwrap()
+    }
+
+    fn codes(entries: &[(&str, &str)]) -> Vec<&'static str> {
+        validate(&mut zip(entries, CompressionMethod::Stored)).iter().map(|d| d.code).collect()
+    }
+
+    fn package_with(manifest: &str, spine: &str) -> String {
+        PACKAGE
+            .replace(r#"<item id="ch1" href="ch1.xhtml" media-type="application/xhtml+xml"/>"#, manifest)
+            .replace(r#"<itemref idref="ch1"/>"#, spine)
+    }
+
+    #[test]
+    fn a_valid_book_has_no_diagnostics() {
+        assert_eq!(codes(&book(&[])), Vec::<&str>::new());
+    }
+
+    #[test]
+    fn missing_mimetype() {
+        assert_eq!(codes(&book(&[("mimetype", None)])), ["PKG-001"]);
+    }
+
+    #[test]
+    fn missing_mimetype_in_an_unpacked_directory() {
+        let dir = std::env::temp_dir().join(format!("tome-validator-test-{}", std::process::id()));
+        for (name, content) in book(&[("mimetype", None)]) {
+            let path = dir.join(name);
+            fs::create_dir_all(path.parent().unwrap()).unwrap();
+            fs::write(path, content).unwrap();
+        }
+        let codes = |dir: &Path| -> Vec<&str> { validate_file(dir).unwrap().iter().map(|d| d.code).collect() };
+        assert_eq!(codes(&dir), ["PKG-001"]);
+        fs::write(dir.join("mimetype"), EPUB_MIMETYPE).unwrap();
+        assert_eq!(codes(&dir), Vec::<&str>::new());
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn mimetype_not_first() {
+        let mut entries = book(&[]);
+        entries.swap(0, 1);
+        assert_eq!(codes(&entries), ["PKG-002"]);
+    }
+
+    #[test]
+    fn compressed_mimetype() {
+        let diagnostics = validate(&mut zip(&book(&[]), CompressionMethod::Deflated));
+        assert_eq!(diagnostics.iter().map(|d| d.code).collect::<Vec<_>>(), ["PKG-003"]);
+    }
+
+    #[test]
+    fn wrong_mimetype_content() {
+        assert_eq!(codes(&book(&[("mimetype", Some("application/zip"))])), ["PKG-004"]);
+    }
+
+    #[test]
+    fn missing_container() {
+        assert_eq!(codes(&book(&[(CONTAINER_PATH, None)])), ["OCF-001"]);
+    }
+
+    #[test]
+    fn unparsable_container() {
+        assert_eq!(codes(&book(&[(CONTAINER_PATH, Some("<container><rootfiles>"))])), ["OCF-002"]);
+    }
+
+    #[test]
+    fn container_without_rootfile() {
+        let container = "<container><rootfiles></rootfiles></container>";
+        assert_eq!(codes(&book(&[(CONTAINER_PATH, Some(container))])), ["OCF-003"]);
+    }
+
+    #[test]
+    fn rootfile_with_the_wrong_media_type() {
+        let container = CONTAINER.replace(OPF_MEDIA_TYPE, "text/xml");
+        assert_eq!(codes(&book(&[(CONTAINER_PATH, Some(&container))])), ["OCF-004"]);
+    }
+
+    #[test]
+    fn rootfile_that_does_not_exist() {
+        let container = CONTAINER.replace("content.opf", "package.opf");
+        assert_eq!(codes(&book(&[(CONTAINER_PATH, Some(&container))])), ["OCF-005"]);
+    }
+
+    #[test]
+    fn malformed_package_document() {
+        let diagnostics = validate(&mut zip(&book(&[("OEBPS/content.opf", Some("<package>\n<manifest>\n</package>"))]), CompressionMethod::Stored));
+        assert_eq!(diagnostics.len(), 1);
+        assert_eq!(diagnostics[0].code, "OPF-001");
+        assert_eq!(diagnostics[0].location, "OEBPS/content.opf:3");
+    }
+
+    #[test]
+    fn manifest_item_that_does_not_exist() {
+        let package = package_with(
+            r#"<item id="ch1" href="ch1.xhtml" media-type="application/xhtml+xml"/><item id="ch2" href="ch2.xhtml" media-type="application/xhtml+xml"/><item id="x" media-type="text/css"/>"#,
+            r#"<itemref idref="ch1"/>"#,
+        );
+        assert_eq!(codes(&book(&[("OEBPS/content.opf", Some(&package))])), ["OPF-002", "OPF-002"]);
+    }
+
+    #[test]
+    fn spine_itemref_without_a_manifest_item() {
+        let package = package_with(
+            r#"<item id="ch1" href="ch1.xhtml" media-type="application/xhtml+xml"/>"#,
+            r#"<itemref idref="ch1"/><itemref idref="ch2"/><itemref/>"#,
+        );
+        assert_eq!(codes(&book(&[("OEBPS/content.opf", Some(&package))])), ["OPF-003", "OPF-003"]);
+    }
+
+    #[test]
+    fn duplicate_ids() {
+        let package = package_with(
+            r#"<item id="ch1" href="ch1.xhtml" media-type="application/xhtml+xml"/><item id="ch1" href="ch1.xhtml" media-type="application/xhtml+xml"/>"#,
+            r#"<itemref idref="ch1"/>"#,
+        );
+        assert_eq!(codes(&book(&[("OEBPS/content.opf", Some(&package))])), ["OPF-004"]);
+    }
+
+    #[test]
+    fn undeclared_files_are_warnings() {
+        let diagnostics = validate(&mut zip(&book(&[("OEBPS/notes.txt", Some("notes"))]), CompressionMethod::Stored));
+        assert_eq!(diagnostics.len(), 1);
+        assert_eq!((diagnostics[0].code, diagnostics[0].severity), ("OPF-005", Severity::Warning));
+        assert_eq!(diagnostics[0].location, "OEBPS/notes.txt");
+    }
+
+    #[test]
+    fn unique_identifier_without_a_matching_identifier() {
+        let package = PACKAGE.replace(r#"unique-identifier="uid""#, r#"unique-identifier="isbn""#);
+        assert_eq!(codes(&book(&[("OEBPS/content.opf", Some(&package))])), ["OPF-006"]);
+        let package = PACKAGE.replace(r#" unique-identifier="uid""#, "");
+        assert_eq!(codes(&book(&[("OEBPS/content.opf", Some(&package))])), ["OPF-006"]);
+    }
+
+    #[test]
+    fn malformed_xhtml_document() {
+        let chapter = "<html>\n<body><p>One</body></html>";
+        let diagnostics = validate(&mut zip(&book(&[("OEBPS/ch1.xhtml", Some(chapter))]), CompressionMethod::Stored));
+        assert_eq!(diagnostics.len(), 1);
+        assert_eq!(diagnostics[0].code, "HTM-001");
+        assert_eq!(diagnostics[0].location, "OEBPS/ch1.xhtml:2");
+    }
+
+    #[test]
+    fn errors_are_listed_before_warnings() {
+        let diagnostics = validate(&mut zip(&book(&[("extra.txt", Some("x")), ("mimetype", None)]), CompressionMethod::Stored));
+        let severities: Vec<Severity> = diagnostics.iter().map(|d| d.severity).collect();
+        assert_eq!(severities, [Severity::Error, Severity::Warning]);
+    }
+}
@@ -0,0 +1,262 @@
+// src/epub/xhtml.rs
+use quick_xml::escape::resolve_predefined_entity;
+use quick_xml::events::{BytesStart, BytesText, Event};
+use quick_xml::Reader;
+
+/// Resolves the XML predefined entities plus the named HTML entities that
+/// commonly show up in EPUB content documents. Without this `&nbsp;` and
+/// friends would fail to unescape.
+pub(super) fn resolve_html_entity(name: &str) -> Option<&'static str> {
+    if let Some(predefined) = resolve_predefined_entity(name) {
+        return Some(predefined);
+    }
+    Some(match name {
+        "nbsp" => "\u{a0}",
+        "ensp" => "\u{2002}",
+        "emsp" => "\u{2003}",
+        "thinsp" => "\u{2009}",
+        "zwnj" => "\u{200c}",
+        "zwj" => "\u{200d}",
+        "shy" => "\u{ad}",
+        "ndash" => "–",
+        "mdash" => "—",
+        "hellip" => "…",
+        "lsquo" => "‘",
+        "rsquo" => "’",
+        "sbquo" => "‚",
+        "ldquo" => "“",
+        "rdquo" => "”",
+        "bdquo" => "„",
+        "laquo" => "«",
+        "raquo" => "»",
+        "middot" => "·",
+        "bull" => "•",
+        "copy" => "©",
+        "reg" => "®",
+        "trade" => "™",
+        "deg" => "°",
+        "times" => "×",
+        "divide" => "÷",
+        "para" => "¶",
+        "sect" => "§",
+        "dagger" => "†",
+        "Dagger" => "‡",
+        _ => return None,
+    })
+}
+
+/// Unescapes a text node, falling back to the raw text if it contains an
+/// entity we don't know about.
+pub(super) fn text_content(text: &BytesText) -> String {
+    text.unescape_with(resolve_html_entity)
+        .map(|s| s.into_owned())
+        .unwrap_or_else(|_| String::from_utf8_lossy(text.as_ref()).into_owned())
+}
+
+/// Reads an attribute by its qualified name (e.g. `href` or `epub:type`).
+pub(super) fn attribute(element: &BytesStart, name: &str) -> Option<String> {
+    element
+        .attributes()
+        .flatten()
+        .find(|attr| attr.key.as_ref() == name.as_bytes())
+        .map(|attr| {
+            attr.unescape_value_with(resolve_html_entity)
+                .map(|v| v.into_owned())
+                .unwrap_or_else(|_| String::from_utf8_lossy(&attr.value).into_owned())
+        })
+}
+
+/// Collapses runs of whitespace into single spaces and trims the ends.
+pub(super) fn collapse_whitespace(text: &str) -> String {
+    text.split_whitespace().collect::<Vec<_>>().join(" ")
+}
+
+/// Like `collapse_whitespace`, but only for ASCII whitespace: non-breaking
+/// spaces in a document's text are left alone.
+pub(super) fn collapse_ascii_whitespace(text: &str) -> String {
+    text.split(|c: char| c.is_ascii_whitespace()).filter(|word| !word.is_empty()).collect::<Vec<_>>().join(" ")
+}
+
+/// Which lines `indent` prefixes.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(super) enum Indent {
+    /// Every line, empty ones included.
+    All,
+    /// Every non-empty line.
+    NonEmpty,
+    /// Every non-empty line after the first, which follows a marker such as `- `.
+    Continuation,
+}
+
+/// Prefixes the lines of `text` chosen by `lines` with `prefix`.
+pub(super) fn indent(text: &str, prefix: &str, lines: Indent) -> String {
+    text.split('\n')
+        .enumerate()
+        .map(|(i, line)| {
+            let prefixed = match lines {
+                Indent::All => true,
+                Indent::NonEmpty => !line.is_empty(),
+                Indent::Continuation => i > 0 && !line.is_empty(),
+            };
+            if prefixed { format!("{}{}", prefix, line) } else { line.to_string() }
+        })
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+/// Returns the source of the first image in a document: an `<img src>` or an
+/// SVG `<image>`, as used by most EPUB cover pages.
+pub(super) fn first_image_src(content: &str) -> Option<String> {
+    let mut reader = Reader::from_str(content);
+    loop {
+        match reader.read_event().ok()? {
+            Event::Start(e) | Event::Empty(e) => match e.local_name().as_ref() {
+                b"img" => return attribute(&e, "src"),
+                b"image" => return attribute(&e, "xlink:href").or_else(|| attribute(&e, "href")),
+                _ => {}
+            },
+            Event::Eof => return None,
+            _ => {}
+        }
+    }
+}
+
+/// Elements whose content is never part of a document's text.
+pub(super) const HIDDEN_ELEMENTS: &[&str] = &["head", "script", "style", "template", "rp", "rt"];
+
+/// Elements that start a new block rather than continuing the current line.
+pub(super) const BLOCK_ELEMENTS: &[&str] = &[
+    "address", "article", "aside", "blockquote", "body", "center", "dd", "details", "div", "dl", "dt",
+    "figcaption", "figure", "footer", "h1", "h2", "h3", "h4", "h5", "h6", "header", "hgroup", "hr",
+    "html", "li", "main", "nav", "ol", "p", "pre", "section", "summary", "table", "ul",
+];
+
+/// A node of a parsed content document.
+#[derive(Debug)]
+pub(super) enum Node {
+    Element(Element),
+    Text(String),
+}
+
+/// An element of a parsed content document, for converters that need to
+/// look at the whole tree rather than stream through it.
+#[derive(Debug, Default)]
+pub(super) struct Element {
+    /// The local name, lowercased (`epub:switch` is `switch`).
+    pub(super) name: String,
+    /// Attributes by qualified name, e.g. `epub:type`.
+    pub(super) attributes: Vec<(String, String)>,
+    pub(super) children: Vec<Node>,
+}
+
+impl Element {
+    pub(super) fn attribute(&self, name: &str) -> Option<&str> {
+        self.attributes.iter().find(|(key, _)| key == name).map(|(_, value)| value.as_str())
+    }
+
+    /// Checks the structural semantics of the element: `epub:type` or its ARIA `role` counterpart.
+    pub(super) fn has_semantic(&self, semantic: &str) -> bool {
+        let epub_type = self.attribute("epub:type").is_some_and(|types| types.split_whitespace().any(|t| t == semantic));
+        let role = self.attribute("role").is_some_and(|roles| roles.split_whitespace().any(|r| r == format!("doc-{}", semantic)));
+        epub_type || role
+    }
+
+    /// The rows of a table. Nested tables are left out, to be flattened into their cell's text.
+    pub(super) fn rows(&self) -> Vec<&Element> {
+        let mut rows = Vec::new();
+        for child in self.elements() {
+            match child.name.as_str() {
+                "tr" => rows.push(child),
+                "table" => {}
+                _ => rows.extend(child.rows()),
+            }
+        }
+        rows
+    }
+
+    pub(super) fn elements(&self) -> impl Iterator<Item = &Element> {
+        self.children.iter().filter_map(|child| match child {
+            Node::Element(element) => Some(element),
+            Node::Text(_) => None,
+        })
+    }
+
+    /// Finds the first descendant with the given name, depth first.
+    pub(super) fn find(&self, name: &str) -> Option<&Element> {
+        self.elements().find_map(|child| if child.name == name { Some(child) } else { child.find(name) })
+    }
+
+    /// Calls `visit` on the element and all its descendants, depth first.
+    pub(super) fn walk<'a>(&'a self, visit: &mut impl FnMut(&'a Element)) {
+        visit(self);
+        for child in self.elements() {
+            child.walk(visit);
+        }
+    }
+
+    /// The concatenated text of all descendants, without any whitespace handling.
+    pub(super) fn text(&self) -> String {
+        let mut text = String::new();
+        for child in &self.children {
+            match child {
+                Node::Element(element) => text.push_str(&element.text()),
+                Node::Text(content) => text.push_str(content),
+            }
+        }
+        text
+    }
+}
+
+/// Parses a content document into a tree, returning a nameless element that
+/// holds the document's root element. Mismatched end tags are tolerated, as
+/// they are in many real-world books.
+pub(super) fn parse_tree(content: &str) -> Result<Element, quick_xml::Error> {
+    let mut reader = Reader::from_str(content);
+    reader.config_mut().check_end_names = false;
+
+    // The open elements, innermost last, below the nameless root.
+    let mut root = Element::default();
+    let mut open: Vec<Element> = Vec::new();
+    let append = |root: &mut Element, open: &mut Vec<Element>, node: Node| {
+        open.last_mut().unwrap_or(root).children.push(node);
+    };
+
+    loop {
+        match reader.read_event()? {
+            Event::Start(e) => open.push(element_from(&e)),
+            Event::Empty(e) => append(&mut root, &mut open, Node::Element(element_from(&e))),
+            Event::End(e) => {
+                let name = String::from_utf8_lossy(e.local_name().as_ref()).to_lowercase();
+                // Close everything up to the matching element; ignore stray end tags.
+                if let Some(position) = open.iter().rposition(|element| element.name == name) {
+                    while open.len() > position {
+                        let Some(element) = open.pop() else { break };
+                        append(&mut root, &mut open, Node::Element(element));
+                    }
+                }
+            }
+            Event::Text(e) => append(&mut root, &mut open, Node::Text(text_content(&e))),
+            Event::CData(e) => append(&mut root, &mut open, Node::Text(String::from_utf8_lossy(&e).into_owned())),
+            Event::Eof => break,
+            _ => {}
+        }
+    }
+    while let Some(element) = open.pop() {
+        append(&mut root, &mut open, Node::Element(element));
+    }
+    Ok(root)
+}
+
+fn element_from(start: &BytesStart) -> Element {
+    let name = String::from_utf8_lossy(start.local_name().as_ref()).to_lowercase();
+    let attributes = start.attributes().flatten()
+        .map(|attr| {
+            let key = String::from_utf8_lossy(attr.key.as_ref()).into_owned();
+            let value = attr.unescape_value_with(resolve_html_entity)
+                .map(|v| v.into_owned())
+                .unwrap_or_else(|_| String::from_utf8_lossy(&attr.value).into_owned());
+            (key, value)
+        })
+        .collect();
+    Element { name, attributes, children: Vec::new() }
+}
@@ -21,6 +21,20 @@ pub enum AppError {
         source: zip::result::ZipError,
     },
 
+    #[error("Failed to write ZIP archive '{path}': {source}")]
+    ZipCreation {
+        path: PathBuf,
+        #[source]
+        source: zip::result::ZipError,
+    },
+
+    #[error("Failed to read '{path}': {source}")]
+    FileRead {
+        path: PathBuf,
+        #[source]
+        source: std::io::Error,
+    },
+
     #[error("Failed to parse XML file '{path}': {source}")]
     XmlParsing {
         path: PathBuf,
@@ -31,6 +45,18 @@ pub enum AppError {
     #[error("Invalid EPUB format: {0}")]
     InvalidEpubFormat(String),
 
+    #[error("No cover image found in '{path}'")]
+    CoverNotFound { path: PathBuf },
+
+    #[error("Cannot edit metadata: {0}")]
+    InvalidMetadataEdit(String),
+
+    #[error("Validation failed with {errors} error(s)")]
+    ValidationFailed { errors: usize },
+
+    #[error("The site has {count} broken link(s)")]
+    BrokenLinks { count: usize },
+
     #[error("I/O error: {source}")]
     Io {
         #[from] // Automatically convert from std::io::Error into AppError::Io
@@ -43,6 +69,18 @@ pub enum AppError {
         source: lol_html::errors::RewritingError,
     },
 
+    #[error("Failed to serialize output as {format}: {message}")]
+    Serialization { format: &'static str, message: String },
+
     #[error("Source file '{path}' has no valid file name")]
     InvalidSourcePath { path: PathBuf },
+
+    #[error("Template directory '{path}' does not exist")]
+    TemplateDirNotFound { path: PathBuf },
+
+    #[error("Template error: {source:#}")]
+    Template {
+        #[from]
+        source: minijinja::Error,
+    },
 }
\ No newline at end of file
@@ -0,0 +1,35 @@
+//! Reading, editing and converting EPUB books.
+//!
+//! This is the library behind the `tome` command line tool. It opens EPUB
+//! files and exposes their package (metadata, manifest, spine, table of
+//! contents), and turns them into static websites, Markdown or plain text.
+//! Nothing here prints: progress and problems are returned to the caller.
+//!
+//! ```no_run
+//! use std::path::Path;
+//!
+//! use tome::{Epub, PageMode, SiteGenerator, WebifyOptions};
+//!
+//! # fn main() -> tome::Result<()> {
+//! let mut epub = Epub::open(Path::new("book.epub"))?;
+//! println!("{}", epub.metadata().title().unwrap_or("Untitled"));
+//!
+//! let options = WebifyOptions::new().mode(PageMode::Single);
+//! let report = SiteGenerator::new(&mut epub, Path::new("book.site"), &options)?.run()?;
+//! for warning in &report.warnings {
+//!     eprintln!("{}", warning);
+//! }
+//! # Ok(())
+//! # }
+//! ```
+
+pub mod epub;
+pub mod error;
+
+pub use epub::diagnostics::{Diagnostics, LinkDiagnostic, LinkProblem};
+pub use epub::layout::{PageMode, SiteLayout};
+pub use epub::model;
+pub use epub::progress::{JsonProgress, Phase, ProgressEvent, ProgressSink, SilentProgress};
+pub use epub::site_generator::{OversizedAsset, SiteGenerator, WebifyOptions, WebifyReport};
+pub use epub::Epub;
+pub use error::{AppError, Result};
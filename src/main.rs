@@ -1,26 +1,41 @@
 mod cli;
 mod command;
-mod epub;
-mod error;
+mod output;
+mod server;
 
 use clap::Parser;
 
 use cli::{Cli, Commands};
+use output::Verbosity;
 use anyhow::Result;
 
 fn main() -> Result<()> {
     let cli = Cli::parse();
+    output::set_verbosity(if cli.quiet {
+        Verbosity::Quiet
+    } else if cli.verbose {
+        Verbosity::Verbose
+    } else {
+        Verbosity::Normal
+    });
 
-    let result = match cli.command {
-        Commands::Unpack(args) => command::unpack::run(args),
-        Commands::Meta(args) => command::meta::run(args),
-        Commands::Webify(args) => command::webify::run(args),
-    };
-
-    if let Err(e) = result {
+    if let Err(e) = run(cli) {
         eprintln!("❌ Error: {}", e);
         std::process::exit(1);
     }
 
+    Ok(())
+}
+
+fn run(cli: Cli) -> Result<()> {
+    match cli.command {
+        Commands::Unpack(args) => command::unpack::run(args)?,
+        Commands::Pack(args) => command::pack::run(args)?,
+        Commands::Meta(args) => command::meta::run(args)?,
+        Commands::Webify(args) => command::webify::run(args)?,
+        Commands::Check(args) => command::check::run(args)?,
+        Commands::Cover(args) => command::cover::run(args)?,
+        Commands::Convert(args) => command::convert::run(args)?,
+    }
     Ok(())
 }
\ No newline at end of file
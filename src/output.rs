@@ -0,0 +1,116 @@
+use std::sync::atomic::{AtomicU8, Ordering};
+use std::time::Duration;
+
+use indicatif::{ProgressBar, ProgressStyle};
+use path_slash::PathBufExt;
+use tome::{Phase, ProgressEvent, ProgressSink};
+
+/// How much the tool prints, set once from `--quiet` and `--verbose`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Verbosity {
+    Quiet,
+    Normal,
+    Verbose,
+}
+
+static VERBOSITY: AtomicU8 = AtomicU8::new(Verbosity::Normal as u8);
+
+pub fn set_verbosity(verbosity: Verbosity) {
+    VERBOSITY.store(verbosity as u8, Ordering::Relaxed);
+}
+
+pub fn verbosity() -> Verbosity {
+    match VERBOSITY.load(Ordering::Relaxed) {
+        0 => Verbosity::Quiet,
+        1 => Verbosity::Normal,
+        _ => Verbosity::Verbose,
+    }
+}
+
+/// Prints a status line like `println!`, unless `--quiet` was given.
+macro_rules! status {
+    ($($arg:tt)*) => {
+        if $crate::output::verbosity() != $crate::output::Verbosity::Quiet {
+            println!($($arg)*);
+        }
+    };
+}
+pub(crate) use status;
+
+/// Shows the progress of a `webify` build: a line per finished phase, with a
+/// progress bar while assets and chapters are written. `--verbose` prints a
+/// line per file instead of the bar; `--quiet` prints only the warnings.
+#[derive(Default)]
+pub struct TerminalProgress {
+    bar: Option<ProgressBar>,
+    /// The number of items of the current phase.
+    total: Option<usize>,
+}
+
+impl TerminalProgress {
+    /// Prints a line without tearing the progress bar.
+    fn print(&self, line: impl FnOnce()) {
+        match &self.bar {
+            Some(bar) => bar.suspend(line),
+            None => line(),
+        }
+    }
+
+    fn advance(&self, index: usize, total: usize, label: String) {
+        if let Some(bar) = &self.bar {
+            bar.set_position(index as u64);
+            bar.set_message(label);
+        } else if verbosity() == Verbosity::Verbose {
+            println!("   │   [{}/{}] {}", index, total, label);
+        }
+    }
+}
+
+impl ProgressSink for TerminalProgress {
+    fn event(&mut self, event: &ProgressEvent) {
+        match event {
+            ProgressEvent::PhaseStarted { total, .. } => {
+                self.total = *total;
+                if let Some(total) = total
+                    && verbosity() == Verbosity::Normal {
+                    let bar = ProgressBar::new(*total as u64).with_style(
+                        ProgressStyle::with_template("   │   [{bar:30}] {pos}/{len} {wide_msg}")
+                            .expect("the progress bar template is valid")
+                            .progress_chars("=> "),
+                    );
+                    bar.enable_steady_tick(Duration::from_millis(100));
+                    self.bar = Some(bar);
+                }
+            }
+            ProgressEvent::PhaseFinished { phase } => {
+                if let Some(bar) = self.bar.take() {
+                    bar.finish_and_clear();
+                }
+                status!("   ├── {}", finished_message(*phase, self.total));
+            }
+            ProgressEvent::AssetCopied { path, index, total } => {
+                self.advance(*index, *total, path.to_slash_lossy().into_owned());
+            }
+            ProgressEvent::ChapterTransformed { path, title, index, total } => {
+                self.advance(*index, *total, format!("{} ({})", title, path.to_slash_lossy()));
+            }
+            ProgressEvent::Warning { message } => self.print(|| eprintln!("⚠️  {}", message)),
+            _ => {}
+        }
+    }
+}
+
+/// The line printed once a phase is done.
+fn finished_message(phase: Phase, total: Option<usize>) -> String {
+    let total = total.unwrap_or_default();
+    match phase {
+        Phase::PrepareOutput => "Prepared output directory.".to_string(),
+        Phase::PlanPaths => "Built path map and identified spine content.".to_string(),
+        Phase::CopyAssets => format!("Processed and copied {} asset(s) (images, fonts, CSS).", total),
+        Phase::CopyTemplateAssets => "Injected template assets.".to_string(),
+        Phase::TransformChapters => format!("Transformed {} chapter(s) and applied templates.", total),
+        Phase::GenerateIndex => "Generated table of contents (index.html).".to_string(),
+        Phase::CombineChapters => format!("Combined {} chapter(s) into a single page (index.html).", total),
+        _ => format!("{}: done.", phase.description()),
+    }
+}
@@ -0,0 +1,326 @@
+// src/server.rs
+use std::fs;
+use std::io::{BufRead, BufReader, Write};
+use std::net::{IpAddr, SocketAddr, TcpListener, TcpStream};
+use std::path::{Component, Path, PathBuf};
+use std::sync::mpsc::{self, RecvTimeoutError, Sender};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::Duration;
+
+use anyhow::{anyhow, Result};
+
+use crate::output::status;
+
+/// The endpoint browsers subscribe to for live-reload events.
+const LIVE_RELOAD_PATH: &str = "/__tome/livereload";
+
+/// Injected before `</body>` of every HTML page when live reload is enabled.
+const LIVE_RELOAD_SCRIPT: &str = r#"<script>
+(function() {
+    const source = new EventSource("/__tome/livereload");
+    source.addEventListener("reload", function() { window.location.reload(); });
+})();
+</script>
+"#;
+
+/// How often an idle event stream receives a keep-alive comment.
+const KEEP_ALIVE_INTERVAL: Duration = Duration::from_secs(15);
+
+/// Broadcasts reload events to every browser tab connected to the server.
+#[derive(Clone, Default)]
+pub struct LiveReload {
+    clients: Arc<Mutex<Vec<Sender<()>>>>,
+}
+
+impl LiveReload {
+    /// Tells every connected page to reload itself.
+    pub fn notify_reload(&self) {
+        let mut clients = self.clients.lock().unwrap_or_else(|e| e.into_inner());
+        // Senders whose receiving connection has gone away are dropped here.
+        clients.retain(|client| client.send(()).is_ok());
+    }
+
+    fn subscribe(&self) -> mpsc::Receiver<()> {
+        let (sender, receiver) = mpsc::channel();
+        self.clients.lock().unwrap_or_else(|e| e.into_inner()).push(sender);
+        receiver
+    }
+}
+
+/// A small static file server used to preview a generated site.
+///
+/// It only understands `GET` and `HEAD`, answers every request with
+/// `Connection: close`, and handles each connection on its own thread.
+pub struct StaticServer {
+    root: PathBuf,
+    listener: TcpListener,
+    live_reload: Option<LiveReload>,
+}
+
+impl StaticServer {
+    /// Binds a listener on `bind:port` that will serve files from `root`.
+    pub fn bind(root: &Path, bind: IpAddr, port: u16) -> Result<Self> {
+        let address = SocketAddr::new(bind, port);
+        let listener = TcpListener::bind(address)
+            .map_err(|source| anyhow!("Failed to start web server on {}: {}", address, source))?;
+        Ok(Self { root: root.to_path_buf(), listener, live_reload: None })
+    }
+
+    /// Enables live reload: HTML pages get a small script that listens for
+    /// events pushed through the returned handle.
+    pub fn enable_live_reload(&mut self) -> LiveReload {
+        self.live_reload.get_or_insert_with(LiveReload::default).clone()
+    }
+
+    /// The address the server is actually listening on.
+    pub fn local_addr(&self) -> Result<SocketAddr> {
+        Ok(self.listener.local_addr()?)
+    }
+
+    /// Accepts connections until the process is terminated.
+    pub fn run(&self) -> Result<()> {
+        for stream in self.listener.incoming() {
+            let stream = match stream {
+                Ok(stream) => stream,
+                Err(e) => {
+                    eprintln!("⚠️  Failed to accept connection: {}", e);
+                    continue;
+                }
+            };
+            let root = self.root.clone();
+            let live_reload = self.live_reload.clone();
+            thread::spawn(move || {
+                if let Err(e) = handle_connection(stream, &root, live_reload.as_ref()) {
+                    eprintln!("⚠️  Error while serving request: {}", e);
+                }
+            });
+        }
+        Ok(())
+    }
+}
+
+fn handle_connection(stream: TcpStream, root: &Path, live_reload: Option<&LiveReload>) -> std::io::Result<()> {
+    let mut reader = BufReader::new(stream.try_clone()?);
+    let mut request_line = String::new();
+    reader.read_line(&mut request_line)?;
+
+    // Drain the headers; we don't need any of them.
+    let mut header = String::new();
+    while reader.read_line(&mut header)? > 2 {
+        header.clear();
+    }
+
+    let mut parts = request_line.split_whitespace();
+    let (method, target) = match (parts.next(), parts.next()) {
+        (Some(method), Some(target)) => (method, target),
+        _ => return write_status(stream, 400, "Bad Request", false),
+    };
+    let head_only = method == "HEAD";
+    if method != "GET" && !head_only {
+        return write_status(stream, 405, "Method Not Allowed", false);
+    }
+
+    if let Some(live_reload) = live_reload
+        && target == LIVE_RELOAD_PATH {
+        return stream_reload_events(stream, live_reload);
+    }
+
+    let status = respond(&stream, root, target, head_only, live_reload.is_some())?;
+    status!("   {} {} -> {}", method, target, status);
+    Ok(())
+}
+
+/// Keeps the connection open as a Server-Sent Events stream until the client disconnects.
+fn stream_reload_events(mut stream: TcpStream, live_reload: &LiveReload) -> std::io::Result<()> {
+    let events = live_reload.subscribe();
+    write!(stream, "HTTP/1.1 200 OK\r\nContent-Type: text/event-stream\r\nCache-Control: no-cache\r\nConnection: keep-alive\r\n\r\n")?;
+    stream.flush()?;
+
+    loop {
+        let message = match events.recv_timeout(KEEP_ALIVE_INTERVAL) {
+            Ok(()) => "event: reload\ndata: reload\n\n",
+            Err(RecvTimeoutError::Timeout) => ": keep-alive\n\n",
+            Err(RecvTimeoutError::Disconnected) => return Ok(()),
+        };
+        // A failed write means the tab was closed or navigated away.
+        if stream.write_all(message.as_bytes()).and_then(|_| stream.flush()).is_err() {
+            return Ok(());
+        }
+    }
+}
+
+/// Resolves `target` below `root` and writes the response, returning the status code.
+fn respond(stream: &TcpStream, root: &Path, target: &str, head_only: bool, inject_reload: bool) -> std::io::Result<u16> {
+    let url_path = request_path(target);
+    let Some(relative) = sanitize_request_path(url_path) else {
+        write_status(stream, 400, "Bad Request", head_only)?;
+        return Ok(400);
+    };
+
+    let mut file_path = root.join(&relative);
+    if file_path.is_dir() {
+        // Relative links only resolve correctly when directories end with a slash.
+        if !url_path.ends_with('/') {
+            let location = format!("{}/", url_path);
+            write!(&*stream, "HTTP/1.1 301 Moved Permanently\r\nLocation: {}\r\nContent-Length: 0\r\nConnection: close\r\n\r\n", location)?;
+            return Ok(301);
+        }
+        file_path.push("index.html");
+    }
+
+    match fs::read(&file_path) {
+        Ok(mut body) => {
+            let content_type = mime_type_for(&file_path);
+            if inject_reload && content_type.starts_with("text/html") {
+                body = inject_live_reload_script(body);
+            }
+            write_response(stream, 200, "OK", content_type, &body, head_only)?;
+            Ok(200)
+        }
+        Err(_) => {
+            write_status(stream, 404, "Not Found", head_only)?;
+            Ok(404)
+        }
+    }
+}
+
+/// The path of a request target, without its query string or fragment.
+fn request_path(target: &str) -> &str {
+    target.split(['?', '#']).next().unwrap_or("/")
+}
+
+/// Inserts the live-reload client right before `</body>`, or appends it if there is none.
+fn inject_live_reload_script(html: Vec<u8>) -> Vec<u8> {
+    let needle = b"</body>";
+    let position = html.windows(needle.len()).rposition(|window| window.eq_ignore_ascii_case(needle));
+    let mut result = Vec::with_capacity(html.len() + LIVE_RELOAD_SCRIPT.len());
+    match position {
+        Some(pos) => {
+            result.extend_from_slice(&html[..pos]);
+            result.extend_from_slice(LIVE_RELOAD_SCRIPT.as_bytes());
+            result.extend_from_slice(&html[pos..]);
+        }
+        None => {
+            result.extend_from_slice(&html);
+            result.extend_from_slice(LIVE_RELOAD_SCRIPT.as_bytes());
+        }
+    }
+    result
+}
+
+/// Decodes a request path and turns it into a relative filesystem path.
+/// Returns `None` if the path tries to escape the served directory.
+fn sanitize_request_path(url_path: &str) -> Option<PathBuf> {
+    let decoded = urlencoding::decode(url_path).ok()?;
+    let mut relative = PathBuf::new();
+    for component in Path::new(decoded.trim_start_matches('/')).components() {
+        match component {
+            Component::Normal(part) => relative.push(part),
+            Component::CurDir => {}
+            _ => return None,
+        }
+    }
+    Some(relative)
+}
+
+fn write_status(stream: impl Write, code: u16, reason: &str, head_only: bool) -> std::io::Result<()> {
+    let body = format!("{} {}\n", code, reason);
+    write_response(stream, code, reason, "text/plain; charset=utf-8", body.as_bytes(), head_only)
+}
+
+fn write_response(mut stream: impl Write, code: u16, reason: &str, content_type: &str, body: &[u8], head_only: bool) -> std::io::Result<()> {
+    write!(
+        stream,
+        "HTTP/1.1 {} {}\r\nContent-Type: {}\r\nContent-Length: {}\r\nCache-Control: no-cache\r\nConnection: close\r\n\r\n",
+        code, reason, content_type, body.len()
+    )?;
+    if !head_only {
+        stream.write_all(body)?;
+    }
+    stream.flush()
+}
+
+/// Maps a file extension to the MIME type browsers expect for it.
+/// Covers everything the site generator writes into `assets/` and `chapters/`.
+pub fn mime_type_for(path: &Path) -> &'static str {
+    let extension = path.extension()
+        .and_then(|ext| ext.to_str())
+        .map(|ext| ext.to_ascii_lowercase())
+        .unwrap_or_default();
+
+    match extension.as_str() {
+        "html" | "htm" => "text/html; charset=utf-8",
+        "xhtml" => "application/xhtml+xml; charset=utf-8",
+        "css" => "text/css; charset=utf-8",
+        "js" | "mjs" => "text/javascript; charset=utf-8",
+        "json" => "application/json",
+        "xml" | "opf" => "application/xml",
+        "ncx" => "application/x-dtbncx+xml",
+        "smil" => "application/smil+xml",
+        "txt" => "text/plain; charset=utf-8",
+        "vtt" => "text/vtt; charset=utf-8",
+        "svg" => "image/svg+xml",
+        "png" => "image/png",
+        "jpg" | "jpeg" => "image/jpeg",
+        "gif" => "image/gif",
+        "webp" => "image/webp",
+        "avif" => "image/avif",
+        "bmp" => "image/bmp",
+        "ico" => "image/x-icon",
+        "ttf" => "font/ttf",
+        "otf" => "font/otf",
+        "woff" => "font/woff",
+        "woff2" => "font/woff2",
+        "mp3" => "audio/mpeg",
+        "m4a" => "audio/mp4",
+        "aac" => "audio/aac",
+        "oga" | "ogg" => "audio/ogg",
+        "opus" => "audio/opus",
+        "wav" => "audio/wav",
+        "mp4" | "m4v" => "video/mp4",
+        "ogv" => "video/ogg",
+        "webm" => "video/webm",
+        "pdf" => "application/pdf",
+        _ => "application/octet-stream",
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn request_paths_stay_below_the_served_directory() {
+        assert_eq!(sanitize_request_path("/chapters/ch01.html"), Some(PathBuf::from("chapters/ch01.html")));
+        assert_eq!(sanitize_request_path("/./assets/./a.css"), Some(PathBuf::from("assets/a.css")));
+        assert_eq!(sanitize_request_path("/"), Some(PathBuf::new()));
+    }
+
+    #[test]
+    fn parent_directories_are_rejected() {
+        assert_eq!(sanitize_request_path("/../etc/passwd"), None);
+        assert_eq!(sanitize_request_path("/chapters/../../etc/passwd"), None);
+    }
+
+    #[test]
+    fn percent_encoded_parent_directories_are_rejected() {
+        assert_eq!(sanitize_request_path("/%2e%2e/etc/passwd"), None);
+        assert_eq!(sanitize_request_path("/%2E%2E%2Fetc%2Fpasswd"), None);
+        assert_eq!(sanitize_request_path("/assets/..%2f..%2fsecret"), None);
+    }
+
+    #[test]
+    fn absolute_paths_are_served_relative_to_the_directory() {
+        assert_eq!(sanitize_request_path("//etc/passwd"), Some(PathBuf::from("etc/passwd")));
+        assert_eq!(sanitize_request_path("/%2Fetc%2Fpasswd"), Some(PathBuf::from("etc/passwd")));
+    }
+
+    #[test]
+    fn query_strings_and_fragments_are_not_part_of_the_path() {
+        assert_eq!(request_path("/index.html?v=2"), "/index.html");
+        assert_eq!(request_path("/ch01.html#sec3"), "/ch01.html");
+        assert_eq!(request_path("/a.css?x=../../etc/passwd"), "/a.css");
+        assert_eq!(sanitize_request_path(request_path("/%2e%2e/x?y")), None);
+    }
+}